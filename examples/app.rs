@@ -0,0 +1,92 @@
+//! The README example. Builds unchanged for `wasm32-wasip1`, which CI checks with
+//! `cargo build --target wasm32-wasip1 --example app`.
+
+#![allow(unused_variables)]
+
+#[macro_use(cmd_try, cmd_expect)]
+extern crate command_cli;
+extern crate io_providers;
+
+use std::env;
+use std::process;
+use command_cli::{Application, Arguments, Command, CommandResult, Context, Parameter, StaticApplication};
+use io_providers::stream;
+
+const APP: StaticApplication = Application {
+    name: "app",
+    commands: &[
+        Command {
+            name: "cmd1",
+            short_desc: "foos the bars via extensible frameworks",
+            params: &[
+                Parameter {
+                    name: "FOO",
+                    required: true,
+                    repeating: false,
+                    ..Parameter::DEFAULT
+                },
+                Parameter {
+                    name: "BAR",
+                    required: true,
+                    repeating: true,
+                    ..Parameter::DEFAULT
+                },
+            ],
+            handler: cmd1_handler,
+            ..Command::DEFAULT
+        },
+        Command {
+            name: "cmd2",
+            short_desc: "executes command #2 on the thing",
+            params: &[
+                Parameter {
+                    name: "THING",
+                    required: false,
+                    repeating: false,
+                    ..Parameter::DEFAULT
+                },
+            ],
+            handler: cmd2_handler,
+            ..Command::DEFAULT
+        },
+        Command {
+            name: "cmd3",
+            short_desc: "runs command #3 on the files",
+            params: &[
+                Parameter {
+                    name: "FILE",
+                    required: false,
+                    repeating: true,
+                    ..Parameter::DEFAULT
+                },
+            ],
+            handler: cmd3_handler,
+            ..Command::DEFAULT
+        },
+    ],
+    ..Application::DEFAULT
+};
+
+fn cmd1_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+    let first_foo: &String = &args["FOO"][0];
+    let bars: &Vec<String> = &args["BAR"];
+    let home_dir = cmd_expect!(ctx, env::home_dir(), "Error: Unable to get home directory");
+    CommandResult::Success
+}
+
+fn cmd2_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+    let thing: Option<&String> = args["THING"].first();
+    let var = cmd_try!(ctx, env::var("ENV_VAR"), "Error: Unable to get 'ENV_VAR' environment variable");
+    CommandResult::ArgumentError
+}
+
+fn cmd3_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+    CommandResult::ExecutionError(None)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut sp = stream::Std::new();
+    let (exit_code, _) = APP.run(&mut sp, args);
+    process::exit(exit_code);
+}
@@ -0,0 +1,263 @@
+//! Bookkeeping for commands which operate on several items (typically the
+//! arguments to a repeating `FILE` parameter), reporting progress as each
+//! item is done and a summary at the end. Counts are formatted for the
+//! user's locale when the batch is made with `with_locale`.
+//!
+//! ```no_run
+//! # extern crate command_cli;
+//! use std::fs;
+//! use command_cli::{Arguments, CommandResult, Context};
+//! use command_cli::batch::Batch;
+//!
+//! fn remove_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+//!     let mut batch = Batch::with_locale(args["FILE"].len(), ctx.locale());
+//!     for file in &args["FILE"] {
+//!         let result = fs::remove_file(file).map_err(|e| e.to_string());
+//!         batch.record(ctx, file, result);
+//!     }
+//!     batch.finish(ctx)
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! For a long batch, a `Checkpoint` saves how far it got, so that when it's
+//! interrupted, running it again with `--resume` can skip the items done. The
+//! application should set `Application::resumable`, so that `--resume` is listed
+//! in its usage:
+//!
+//! ```no_run
+//! # extern crate command_cli;
+//! # use std::fs;
+//! # use command_cli::{Arguments, CommandResult, Context};
+//! use command_cli::batch::{Batch, Checkpoint};
+//! use command_cli::idempotency;
+//!
+//! fn remove_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+//!     let files = &args["FILE"];
+//!     let mut checkpoint = Checkpoint::load(ctx, &idempotency::key("remove", args)).unwrap();
+//!     let mut batch = Batch::with_locale(files.len(), ctx.locale());
+//!     batch.skip(ctx, checkpoint.done());
+//!     for file in &files[checkpoint.done()..] {
+//!         let result = fs::remove_file(file).map_err(|e| e.to_string());
+//!         batch.record(ctx, file, result);
+//!         checkpoint.advance(ctx).unwrap();
+//!     }
+//!     checkpoint.clear(ctx).unwrap();
+//!     batch.finish(ctx)
+//! }
+//! # fn main() {}
+//! ```
+
+use std::io;
+use io_providers::stream;
+use locale::Locale;
+use {CommandResult, Context};
+
+/// Tracks the outcome of each item in a batch.
+pub struct Batch {
+    total: usize,
+    done: usize,
+    /// The number of items skipped because they were done by an earlier run.
+    skipped: usize,
+    /// Failed items, along with the reason each failed.
+    failures: Vec<(String, String)>,
+    locale: Locale,
+}
+
+impl Batch {
+    /// Constructs a `Batch` of `total` items, whose counts are formatted as in
+    /// the `C` locale.
+    pub fn new(total: usize) -> Batch {
+        Batch::with_locale(total, Locale::C)
+    }
+
+    /// Constructs a `Batch` of `total` items, whose counts are formatted for
+    /// `locale`, typically `Context::locale`.
+    pub fn with_locale(total: usize, locale: Locale) -> Batch {
+        Batch { total, done: 0, skipped: 0, failures: Vec::new(), locale }
+    }
+
+    /// Formats a count for the batch's locale.
+    fn count(&self, n: usize) -> String {
+        self.locale.format_int(n as i64)
+    }
+
+    /// Records the outcome of an item, writing a progress line to stderr.
+    pub fn record(&mut self, sp: &mut stream::Provider, item: &str, result: Result<(), String>) {
+        self.done += 1;
+        match result {
+            Ok(()) => writeln!(sp.error(), "[{}/{}] {}: ok", self.count(self.done), self.count(self.total), item).unwrap(),
+            Err(reason) => {
+                writeln!(sp.error(), "[{}/{}] {}: failed: {}", self.count(self.done), self.count(self.total), item, reason).unwrap();
+                self.failures.push((item.to_string(), reason));
+            },
+        }
+    }
+
+    /// Skips the first `count` items, which an earlier run did (see
+    /// `Checkpoint`), writing a line saying so to stderr if there are any.
+    pub fn skip(&mut self, sp: &mut stream::Provider, count: usize) {
+        if count > 0 {
+            self.done += count;
+            self.skipped += count;
+            writeln!(sp.error(), "[{}/{}] skipped, as done by an earlier run", self.count(self.done), self.count(self.total)).unwrap();
+        }
+    }
+
+    /// Writes a summary of the batch to stderr, listing the failed items.
+    ///
+    /// Yields `Success` if no item failed, or else a `PartialFailure`.
+    pub fn finish(&self, sp: &mut stream::Provider) -> CommandResult {
+        let succeeded = self.done - self.skipped - self.failures.len();
+        let failed = self.count(self.failures.len());
+        if self.skipped > 0 {
+            writeln!(sp.error(), "\n{} succeeded, {} failed, {} skipped", self.count(succeeded), failed, self.count(self.skipped)).unwrap();
+        } else {
+            writeln!(sp.error(), "\n{} succeeded, {} failed", self.count(succeeded), failed).unwrap();
+        }
+
+        let width = self.failures.iter().map(|(item, _)| item.chars().count()).max().unwrap_or(0);
+        for (item, reason) in &self.failures {
+            writeln!(sp.error(), "  {: <2$}  {}", item, reason, width).unwrap();
+        }
+
+        if self.failures.is_empty() {
+            CommandResult::Success
+        } else {
+            CommandResult::PartialFailure { failed: self.failures.len(), total: self.total }
+        }
+    }
+}
+
+/// How many items of a batch are done, saved in the application's state (see
+/// `Context::state`) as each is.
+pub struct Checkpoint {
+    key: String,
+    done: usize,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint for the batch identified by `key` (see
+    /// `idempotency::key`). Unless the user passed `--resume`, any progress
+    /// saved before is discarded, so the batch starts over.
+    pub fn load(ctx: &mut Context, key: &str) -> io::Result<Checkpoint> {
+        let key = format!("checkpoint.{}", key);
+        let resume = ctx.resume();
+        let state = try!(ctx.state());
+        let done = match state.get(&key) {
+            Some(done) if resume => done.parse().unwrap_or(0),
+            _ => 0,
+        };
+        Ok(Checkpoint { key, done })
+    }
+
+    /// Returns the number of items done, which are to be skipped.
+    pub fn done(&self) -> usize {
+        self.done
+    }
+
+    /// Records that one more item is done.
+    pub fn advance(&mut self, ctx: &mut Context) -> io::Result<()> {
+        self.done += 1;
+        ctx.set_state(&self.key, &self.done.to_string())
+    }
+
+    /// Forgets the progress, once the whole batch is done.
+    pub fn clear(self, ctx: &mut Context) -> io::Result<()> {
+        ctx.remove_state(&self.key)
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use io_providers::stream;
+    use {env, CommandResult, Context};
+
+    #[test]
+    fn batch__finish_with_failures__prints_summary() {
+        let mut sp = stream::Virtual::new();
+        let mut batch = Batch::new(3);
+
+        batch.record(&mut sp, "a", Ok(()));
+        batch.record(&mut sp, "bb", Err("not found".to_string()));
+        batch.record(&mut sp, "c", Err("denied".to_string()));
+        let result = batch.finish(&mut sp);
+
+        assert!(matches!(result, CommandResult::PartialFailure { failed: 2, total: 3 }));
+        assert_eq!("\
+            [1/3] a: ok\n\
+            [2/3] bb: failed: not found\n\
+            [3/3] c: failed: denied\n\
+            \n\
+            1 succeeded, 2 failed\n  \
+            bb  not found\n  \
+            c   denied\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn batch__finish_without_failures__success() {
+        let mut sp = stream::Virtual::new();
+        let mut batch = Batch::new(1);
+
+        batch.record(&mut sp, "a", Ok(()));
+        let result = batch.finish(&mut sp);
+
+        assert!(matches!(result, CommandResult::Success));
+    }
+
+    #[test]
+    fn batch__skip__counted_separately() {
+        let mut sp = stream::Virtual::new();
+        let mut batch = Batch::new(3);
+
+        batch.skip(&mut sp, 0);
+        batch.skip(&mut sp, 2);
+        batch.record(&mut sp, "c", Ok(()));
+        let result = batch.finish(&mut sp);
+
+        assert!(matches!(result, CommandResult::Success));
+        assert_eq!("\
+            [2/3] skipped, as done by an earlier run\n\
+            [3/3] c: ok\n\
+            \n\
+            1 succeeded, 0 failed, 2 skipped\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn batch__with_locale__counts_localized() {
+        let mut sp = stream::Virtual::new();
+        let mut batch = Batch::with_locale(1500, Locale::from_name("de_DE"));
+
+        batch.skip(&mut sp, 1499);
+        batch.record(&mut sp, "last", Ok(()));
+        batch.finish(&mut sp);
+
+        assert_eq!("\
+            [1.499/1.500] skipped, as done by an earlier run\n\
+            [1.500/1.500] last: ok\n\
+            \n\
+            1 succeeded, 0 failed, 1.499 skipped\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn checkpoint__load__resumes_only_with_resume() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        let mut ctx = Context::new(&mut sp, &mut ep);
+
+        let mut checkpoint = Checkpoint::load(&mut ctx, "k").unwrap();
+        checkpoint.advance(&mut ctx).unwrap();
+        checkpoint.advance(&mut ctx).unwrap();
+        assert_eq!(0, Checkpoint::load(&mut ctx, "k").unwrap().done());
+        ctx.set_resume(true);
+        assert_eq!(2, Checkpoint::load(&mut ctx, "k").unwrap().done());
+
+        Checkpoint::load(&mut ctx, "k").unwrap().clear(&mut ctx).unwrap();
+        assert_eq!(0, Checkpoint::load(&mut ctx, "k").unwrap().done());
+    }
+}
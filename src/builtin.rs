@@ -0,0 +1,368 @@
+//! Commands provided by the framework itself. These are hidden, i.e. not listed
+//! in the application's usage, since they're meant for tooling rather than users.
+
+use std::path::Path;
+use io_providers::stream;
+use env;
+use fingerprint;
+use markup;
+use output;
+use quote;
+use {Application, Command, HelpTopic, ParamKind, SECRET_MASK};
+use {ARGUMENT_ERROR_EXIT_CODE, EXECUTION_ERROR_EXIT_CODE, SUCCESS_EXIT_CODE};
+
+/// The name of the command which lists the application's commands.
+pub const COMMANDS: &str = "commands";
+
+/// The name of the command which prints help about a command or topic.
+pub const HELP: &str = "help";
+
+/// The name of the command which shows how arguments would be dispatched.
+pub const ROUTE: &str = "__route";
+
+/// Names which application commands shouldn't use, since they belong to
+/// built-in commands; see `ReservedNamePolicy`.
+pub const RESERVED_NAMES: &[&str] = &[COMMANDS, HELP, ROUTE];
+
+/// Prefix under which a built-in remains available when an application command
+/// takes its name, with `ReservedNamePolicy::Rename`.
+pub const RENAME_PREFIX: &str = "__";
+
+/// Runs `app commands [--params] [--json]`, which prints the name of each
+/// command one per line, or (with `--params`) its usage line. With `--json`,
+/// the commands are printed as a JSON array instead.
+pub fn commands(app: &Application, sp: &mut stream::Provider, args: &[String]) -> i32 {
+    let (mut with_params, mut json) = (false, false);
+    for arg in args {
+        match &arg[..] {
+            "--params" => with_params = true,
+            "--json" => json = true,
+            _ => {
+                writeln!(sp.error(), "Usage: {} {} [--params] [--json]", app.name, COMMANDS).unwrap();
+                return ARGUMENT_ERROR_EXIT_CODE;
+            },
+        }
+    }
+
+    if json {
+        let commands: Vec<String> = app.commands.iter()
+            .map(|cmd| {
+                let mut json = format!("{{\"name\":{}", output::json_string(cmd.name));
+                if with_params {
+                    let params: Vec<String> = cmd.all_params().iter()
+                        .map(|p| format!(
+                            "{{\"name\":{},\"required\":{},\"repeating\":{}}}",
+                            output::json_string(p.name), p.required, p.repeating))
+                        .collect();
+                    json.push_str(&format!(",\"params\":[{}]", params.join(",")));
+                }
+                json.push('}');
+                json
+            })
+            .collect();
+        writeln!(sp.output(), "[{}]", commands.join(",")).unwrap();
+    } else {
+        for cmd in app.commands {
+            if with_params {
+                writeln!(sp.output(), "{}", cmd).unwrap();
+            } else {
+                writeln!(sp.output(), "{}", cmd.name).unwrap();
+            }
+        }
+    }
+
+    SUCCESS_EXIT_CODE
+}
+
+/// Runs `app help [--json | --bundle | --offline-bundle PATH | --matrix] [NAME]`,
+/// which prints the application's usage, or else the usage and description of
+/// the command or the text of the help topic called `NAME`.
+///
+/// Descriptions are fit to the terminal's width (from `$COLUMNS`), and styled if
+/// stdout is a terminal and `fancy` output is on. With `--json`, the same content
+/// is printed as a JSON object for editors and other tools, with descriptions
+/// left as written. `--bundle` prints all of the help as a single file (see
+/// `Application::help_bundle`), and `--offline-bundle` reads the help from one.
+/// `--matrix` prints a table of the commands with a `verb` and `noun`, with a
+/// row per noun and a column per verb.
+pub fn help(app: &Application, sp: &mut stream::Provider, ep: &env::Provider, args: &[String], fancy: bool) -> i32 {
+    let mut json = false;
+    let mut bundle = false;
+    let mut matrix = false;
+    let mut offline_bundle = None;
+    let mut names = Vec::new();
+    let mut args_iter = args.iter();
+    while let Some(arg) = args_iter.next() {
+        match &arg[..] {
+            "--json" => json = true,
+            "--bundle" => bundle = true,
+            "--matrix" => matrix = true,
+            "--offline-bundle" => match args_iter.next() {
+                Some(path) => offline_bundle = Some(path),
+                None => return print_help_usage(app, sp),
+            },
+            _ => names.push(arg),
+        }
+    }
+
+    if matrix {
+        if json || bundle || offline_bundle.is_some() || !names.is_empty() {
+            return print_help_usage(app, sp);
+        }
+        write!(sp.output(), "{}", verb_noun_matrix(app)).unwrap();
+        return SUCCESS_EXIT_CODE;
+    } else if let Some(path) = offline_bundle {
+        return match names[..] {
+            [] => help_from_bundle(app, sp, ep, path, None),
+            [name] if !json && !bundle => help_from_bundle(app, sp, ep, path, Some(name)),
+            _ => print_help_usage(app, sp),
+        };
+    } else if bundle {
+        if json || !names.is_empty() {
+            return print_help_usage(app, sp);
+        }
+        write!(sp.output(), "{}", app.help_bundle()).unwrap();
+        return SUCCESS_EXIT_CODE;
+    }
+
+    let name = match names[..] {
+        [] if json => {
+            writeln!(sp.output(), "{}", app_json(app)).unwrap();
+            return SUCCESS_EXIT_CODE;
+        },
+        [] => {
+            writeln!(sp.output(), "{}", app.usage_string()).unwrap();
+            return SUCCESS_EXIT_CODE;
+        },
+        [name] => name,
+        _ => return print_help_usage(app, sp),
+    };
+
+    if json {
+        let found = app.commands.iter().find(|cmd| cmd.name == *name).map(|cmd| command_json(app, cmd))
+            .or_else(|| app.topics.iter().find(|topic| topic.name == *name).map(topic_json));
+        match found {
+            Some(found) => writeln!(sp.output(), "{}", found).unwrap(),
+            None => {
+                writeln!(sp.error(), "Error: No command or help topic named '{}'", name).unwrap();
+                return ARGUMENT_ERROR_EXIT_CODE;
+            },
+        }
+        return SUCCESS_EXIT_CODE;
+    }
+
+    if let Some(cmd) = app.commands.iter().find(|cmd| cmd.name == name) {
+        writeln!(sp.output(), "{}\n\n{}", cmd.usage_string(app.name), cmd.short_desc).unwrap();
+        if cmd.mutates {
+            writeln!(sp.output(), "\nThis command modifies state.").unwrap();
+        }
+        if cmd.requires_network {
+            writeln!(sp.output(), "\nThis command needs network access.").unwrap();
+        }
+        if !cmd.long_desc.is_empty() {
+            let width = ep.var("COLUMNS").and_then(|c| c.parse().ok()).unwrap_or(80);
+            let styled = fancy && ep.is_terminal(env::Stream::Output);
+            writeln!(sp.output(), "\n{}", markup::render(cmd.long_desc, width, styled)).unwrap();
+        }
+    } else if let Some(topic) = app.topics.iter().find(|topic| topic.name == name) {
+        writeln!(sp.output(), "{}\n\n{}", topic.title, topic.text.trim_end()).unwrap();
+    } else {
+        writeln!(sp.error(), "Error: No command or help topic named '{}'", name).unwrap();
+        return ARGUMENT_ERROR_EXIT_CODE;
+    }
+
+    SUCCESS_EXIT_CODE
+}
+
+fn print_help_usage(app: &Application, sp: &mut stream::Provider) -> i32 {
+    writeln!(sp.error(), "Usage: {} {} [--json | --bundle | --offline-bundle PATH | --matrix] [NAME]", app.name, HELP)
+        .unwrap();
+    ARGUMENT_ERROR_EXIT_CODE
+}
+
+/// Lays out the commands with a verb and noun as a table, with verbs and nouns
+/// in order of first use. Combinations without a command are shown as `-`.
+fn verb_noun_matrix(app: &Application) -> String {
+    let mut verbs: Vec<&str> = Vec::new();
+    let mut nouns: Vec<&str> = Vec::new();
+    for cmd in app.commands {
+        if let (Some(verb), Some(noun)) = (cmd.verb, cmd.noun) {
+            if !verbs.contains(&verb) {
+                verbs.push(verb);
+            }
+            if !nouns.contains(&noun) {
+                nouns.push(noun);
+            }
+        }
+    }
+
+    let cell = |verb: &str, noun: &str| app.commands.iter()
+        .find(|cmd| cmd.verb == Some(verb) && cmd.noun == Some(noun))
+        .map_or("-", |cmd| cmd.name);
+    let rows: Vec<Vec<&str>> = Some(Some("").into_iter().chain(verbs.iter().cloned()).collect())
+        .into_iter()
+        .chain(nouns.iter().map(|&noun| Some(noun).into_iter().chain(verbs.iter().map(|verb| cell(verb, noun))).collect()))
+        .collect();
+
+    let widths: Vec<usize> = (0..verbs.len() + 1)
+        .map(|i| rows.iter().map(|row| row[i].chars().count()).max().unwrap_or(0))
+        .collect();
+    let mut table = String::new();
+    for row in rows {
+        let cells: Vec<String> = row.iter().zip(&widths).map(|(cell, &width)| format!("{: <1$}", cell, width)).collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// The first word of a help bundle's first line.
+const BUNDLE_MAGIC: &str = "command-cli-help-bundle";
+
+impl<'c, 'p> Application<'c, 'p> {
+    /// Returns all of the application's help in a single file, which `app help
+    /// --offline-bundle PATH [NAME]` can print from. This is for environments
+    /// where documentation is stripped from binaries (e.g. by blanking
+    /// `long_desc`): the bundle is exported from a full build and shipped alongside.
+    ///
+    /// Each command's and topic's help is included as `app help NAME` prints it
+    /// without styling, at 80 columns. The first line holds the application's
+    /// name and a checksum of the rest, so that damaged bundles are caught.
+    pub fn help_bundle(&self) -> String {
+        let mut body = String::new();
+        let names = Some("").into_iter()
+            .chain(self.commands.iter().map(|cmd| cmd.name))
+            .chain(self.topics.iter().map(|topic| topic.name));
+        for name in names {
+            let mut sp = stream::Virtual::new();
+            let args: Vec<String> = Some(name.to_string()).into_iter().filter(|n| !n.is_empty()).collect();
+            help(self, &mut sp, &env::Virtual::new(), &args, false);
+
+            // Content lines are marked so that none can be taken for a section header
+            body.push_str(&format!("@{}\n", name));
+            for line in String::from_utf8_lossy(sp.read_output()).lines() {
+                body.push_str(&format!("|{}\n", line));
+            }
+        }
+
+        format!("{} {} {:016x}\n{}", BUNDLE_MAGIC, self.name, fingerprint::fnv1a(body.as_bytes()), body)
+    }
+}
+
+/// Prints the help for `name` (or the application's usage) from the bundle at
+/// `path`, after checking that it's intact and for this application.
+fn help_from_bundle(app: &Application, sp: &mut stream::Provider, ep: &env::Provider, path: &str, name: Option<&str>)
+    -> i32
+{
+    let contents = match ep.read_file(Path::new(path)) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => {
+            writeln!(sp.error(), "Error: Unable to read help bundle '{}': {}", path, e).unwrap();
+            return EXECUTION_ERROR_EXIT_CODE;
+        },
+    };
+
+    let (header, body) = match contents.find('\n') {
+        Some(i) => (&contents[..i], &contents[i + 1..]),
+        None => (&contents[..], ""),
+    };
+    let fields: Vec<&str> = header.split(' ').collect();
+    let intact = match fields[..] {
+        [magic, _, checksum] => magic == BUNDLE_MAGIC && checksum == format!("{:016x}", fingerprint::fnv1a(body.as_bytes())),
+        _ => false,
+    };
+    if !intact {
+        writeln!(sp.error(), "Error: '{}' isn't a help bundle, or is damaged", path).unwrap();
+        return EXECUTION_ERROR_EXIT_CODE;
+    } else if fields[1] != app.name {
+        writeln!(sp.error(), "Error: '{}' is a help bundle for '{}', not '{}'", path, fields[1], app.name).unwrap();
+        return EXECUTION_ERROR_EXIT_CODE;
+    }
+
+    let header = format!("@{}", name.unwrap_or(""));
+    let mut lines = body.lines().skip_while(|line| *line != header);
+    if lines.next().is_none() {
+        writeln!(sp.error(), "Error: No command or help topic named '{}'", name.unwrap_or("")).unwrap();
+        return ARGUMENT_ERROR_EXIT_CODE;
+    }
+    for line in lines.take_while(|line| line.starts_with('|')) {
+        writeln!(sp.output(), "{}", &line[1..]).unwrap();
+    }
+    SUCCESS_EXIT_CODE
+}
+
+/// The whole of the application's help, for `app help --json`.
+fn app_json(app: &Application) -> String {
+    let commands: Vec<String> = app.commands.iter().map(|cmd| command_json(app, cmd)).collect();
+    let topics: Vec<String> = app.topics.iter().map(topic_json).collect();
+    format!(
+        "{{\"name\":{},\"usage\":{},\"commands\":[{}],\"topics\":[{}]}}",
+        output::json_string(app.name), output::json_string(&app.usage_string()),
+        commands.join(","), topics.join(","))
+}
+
+fn command_json(app: &Application, cmd: &Command) -> String {
+    format!(
+        "{{\"name\":{},\"usage\":{},\"short_desc\":{},\"long_desc\":{},\"mutates\":{},\"requires_network\":{}}}",
+        output::json_string(cmd.name), output::json_string(&cmd.usage_string(app.name)),
+        output::json_string(cmd.short_desc), output::json_string(cmd.long_desc), cmd.mutates, cmd.requires_network)
+}
+
+fn topic_json(topic: &HelpTopic) -> String {
+    format!(
+        "{{\"name\":{},\"title\":{},\"text\":{}}}",
+        output::json_string(topic.name), output::json_string(topic.title), output::json_string(topic.text))
+}
+
+/// Runs `app __route COMMAND [ARGS]`, which prints the command that would run and
+/// the arguments bound to each of its parameters (quoted for POSIX shells), one
+/// per line, without running it:
+///
+/// ```text
+/// command: cp
+/// SOURCE: a.txt 'b c.txt'
+/// DEST: out/
+/// ```
+pub fn route(app: &Application, sp: &mut stream::Provider, args: &[String]) -> i32 {
+    let cmd_name = match args.first() {
+        Some(name) => name,
+        None => {
+            writeln!(sp.error(), "Usage: {} {} COMMAND [ARGS]", app.name, ROUTE).unwrap();
+            return ARGUMENT_ERROR_EXIT_CODE;
+        },
+    };
+
+    // Resolved as dispatch does, but without the deprecation notice
+    let cmd_name = app.renamed_commands.iter().find(|&&(old, _)| old == cmd_name).map_or(&cmd_name[..], |&(_, new)| new);
+    let cmd = match app.commands.iter().find(|cmd| cmd.name == cmd_name) {
+        Some(cmd) => cmd,
+        None => {
+            writeln!(sp.error(), "Error: Unrecognized command '{}'", cmd_name).unwrap();
+            return ARGUMENT_ERROR_EXIT_CODE;
+        },
+    };
+
+    let mut cmd_args = vec![app.name.to_string()];
+    cmd_args.extend(args.iter().cloned());
+    let (params, arguments) = match cmd.parse_arguments(cmd_args) {
+        Some(parsed) => parsed,
+        None => {
+            writeln!(sp.error(), "Error: Arguments don't match any form of '{}'", cmd.name).unwrap();
+            cmd.print_usage(sp, app.name);
+            return ARGUMENT_ERROR_EXIT_CODE;
+        },
+    };
+
+    writeln!(sp.output(), "command: {}", cmd.name).unwrap();
+    if !cmd.forms.is_empty() {
+        writeln!(sp.output(), "form: {}", arguments.form()).unwrap();
+    }
+    for param in params {
+        let values: Vec<String> = arguments[param.name].iter()
+            .map(|v| if param.kind == ParamKind::Secret { SECRET_MASK.to_string() } else { quote::posix(v) })
+            .collect();
+        writeln!(sp.output(), "{}: {}", param.name, values.join(" ")).unwrap();
+    }
+
+    SUCCESS_EXIT_CODE
+}
@@ -0,0 +1,154 @@
+//! Output from tasks running concurrently within a handler, e.g. on worker
+//! threads.
+//!
+//! Each task writes to its own `TaskWriter`, which passes on whole lines only,
+//! and the handler copies them to its output with a `Collector`, so that lines
+//! from different tasks never get mixed up:
+//!
+//! ```
+//! # extern crate command_cli;
+//! use std::io::Write;
+//! use std::thread;
+//! use command_cli::{Arguments, CommandResult, Context};
+//! use command_cli::concurrent::Collector;
+//!
+//! fn handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+//!     let collector = Collector::new();
+//!     for file in &args["FILE"] {
+//!         let mut out = collector.writer();
+//!         let file = file.clone();
+//!         thread::spawn(move || writeln!(out, "{}: done", file).unwrap());
+//!     }
+//!
+//!     collector.write_to(ctx.output()).unwrap();
+//!     CommandResult::Success
+//! }
+//! # fn main() {}
+//! ```
+
+use std::io::{self, Write};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// Gathers the lines written by `TaskWriter`s, in the order they're completed.
+pub struct Collector {
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl Default for Collector {
+    fn default() -> Collector {
+        Collector::new()
+    }
+}
+
+impl Collector {
+    /// Constructs a `Collector` with no writers.
+    pub fn new() -> Collector {
+        let (sender, receiver) = mpsc::channel();
+        Collector { sender, receiver }
+    }
+
+    /// Returns a new writer for a task, which can be sent to another thread.
+    pub fn writer(&self) -> TaskWriter {
+        TaskWriter { sender: self.sender.clone(), buf: Vec::new() }
+    }
+
+    /// Writes the lines completed so far to `out`, without waiting for more.
+    pub fn write_pending(&self, out: &mut Write) -> io::Result<()> {
+        while let Ok(lines) = self.receiver.try_recv() {
+            try!(out.write_all(&lines));
+        }
+        Ok(())
+    }
+
+    /// Writes lines to `out` as they're completed, until every writer has been dropped.
+    pub fn write_to(self, out: &mut Write) -> io::Result<()> {
+        let Collector { sender, receiver } = self;
+        drop(sender);
+        for lines in receiver {
+            try!(out.write_all(&lines));
+        }
+        Ok(())
+    }
+}
+
+/// A writer for one task, which buffers output until it has a whole line.
+///
+/// Any unfinished last line is passed on when the writer is dropped.
+pub struct TaskWriter {
+    sender: Sender<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+impl Write for TaskWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        if let Some(i) = self.buf.iter().rposition(|&b| b == b'\n') {
+            let rest = self.buf.split_off(i + 1);
+            let lines = mem::replace(&mut self.buf, rest);
+            try!(self.sender.send(lines).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Collector dropped")));
+        }
+        Ok(buf.len())
+    }
+
+    /// Does nothing, since only whole lines are passed on.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TaskWriter {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            let _ = self.sender.send(mem::take(&mut self.buf));
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::thread;
+
+    #[test]
+    fn collector__concurrent_writers__lines_not_interleaved() {
+        let collector = Collector::new();
+        let threads: Vec<_> = (0..4).map(|i| {
+            let mut out = collector.writer();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    write!(out, "task {} ", i).unwrap();
+                    writeln!(out, "line").unwrap();
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let mut out = Vec::new();
+
+        collector.write_to(&mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(400, out.lines().count());
+        assert!(out.lines().all(|line| line.starts_with("task ") && line.ends_with(" line") && line.len() == 11));
+    }
+
+    #[test]
+    fn task_writer__partial_line__held_until_drop() {
+        let collector = Collector::new();
+        let mut writer = collector.writer();
+        let mut out = Vec::new();
+
+        write!(writer, "a\nb").unwrap();
+        collector.write_pending(&mut out).unwrap();
+        assert_eq!(b"a\n", &out[..]);
+
+        drop(writer);
+        collector.write_to(&mut out).unwrap();
+        assert_eq!(b"a\nb", &out[..]);
+    }
+}
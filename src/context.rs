@@ -0,0 +1,795 @@
+//! The context in which a command's handler runs.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
+use io_providers::stream;
+use env;
+use filter::Filtered;
+use locale::Locale;
+use state::State;
+
+/// Gives a handler access to the standard streams and the process environment.
+///
+/// `Context` implements `stream::Provider`, and also has `input`, `output` and
+/// `error` as inherent methods so that `writeln!(ctx.output(), ...)` works
+/// without importing the trait.
+pub struct Context<'a> {
+    sp: Streams<'a>,
+    env: Env<'a>,
+    warnings: usize,
+    strict: bool,
+    raw_args: Vec<String>,
+    injected_args: Vec<String>,
+    /// The working directory before `-C` changed it, if it did.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    original_dir: Option<PathBuf>,
+    state_path: Option<PathBuf>,
+    state: Option<State>,
+    temp_dir: Option<PathBuf>,
+    locale: Option<&'static str>,
+    fancy: bool,
+    deterministic: bool,
+    offline: bool,
+    resume: bool,
+}
+
+impl<'a> Context<'a> {
+    /// Constructs a `Context` over the given streams and environment, e.g. to unit test a handler.
+    pub fn new(sp: &'a mut stream::Provider, ep: &'a mut env::Provider) -> Context<'a> {
+        Context::with_streams(Streams::Plain(sp), ep)
+    }
+
+    /// Constructs a `Context` whose streams pass through the application's filters.
+    pub(crate) fn filtered(sp: Filtered<'a>, ep: &'a mut env::Provider) -> Context<'a> {
+        Context::with_streams(Streams::Filtered(sp), ep)
+    }
+
+    fn with_streams(sp: Streams<'a>, ep: &'a mut env::Provider) -> Context<'a> {
+        Context {
+            sp,
+            env: Env { ep, overrides: BTreeMap::new(), sandbox: None },
+            warnings: 0,
+            strict: false,
+            raw_args: Vec::new(),
+            injected_args: Vec::new(),
+            original_dir: None,
+            state_path: None,
+            state: None,
+            temp_dir: None,
+            locale: None,
+            fancy: true,
+            deterministic: false,
+            offline: false,
+            resume: false,
+        }
+    }
+
+    /// Sets whether warnings are treated as errors.
+    pub(crate) fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Sets the command-line arguments returned by `raw_args`.
+    pub(crate) fn set_raw_args(&mut self, args: Vec<String>) {
+        self.raw_args = args;
+    }
+
+    /// Sets the working directory the invocation started in, before `-C DIR`
+    /// changed it, for `reexec_with_sudo`.
+    pub(crate) fn set_original_dir(&mut self, dir: Option<PathBuf>) {
+        self.original_dir = dir;
+    }
+
+    /// Sets the arguments returned by `injected_args`.
+    pub(crate) fn set_injected_args(&mut self, args: Vec<String>) {
+        self.injected_args = args;
+    }
+
+    /// Sets where the state returned by `state` is saved.
+    pub(crate) fn set_state_path(&mut self, path: Option<PathBuf>) {
+        self.state_path = path;
+    }
+
+    /// Sets the name of the locale returned by `locale`, instead of the
+    /// environment's.
+    pub(crate) fn set_locale(&mut self, name: Option<&'static str>) {
+        self.locale = name;
+    }
+
+    /// Sets whether `fancy` output is allowed.
+    pub(crate) fn set_fancy(&mut self, fancy: bool) {
+        self.fancy = fancy;
+    }
+
+    /// Sets the directory outside of which `write_file` refuses to write.
+    pub(crate) fn set_sandbox(&mut self, root: Option<PathBuf>) {
+        self.env.sandbox = root;
+    }
+
+    /// Sets the value returned by `offline`.
+    pub(crate) fn set_offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    /// Sets the value returned by `resume`.
+    pub(crate) fn set_resume(&mut self, resume: bool) {
+        self.resume = resume;
+    }
+
+    /// Sets the value returned by `deterministic`.
+    pub(crate) fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    /// Gets the input stream.
+    pub fn input(&mut self) -> &mut Read {
+        self.sp.get().input()
+    }
+
+    /// Gets the output stream.
+    pub fn output(&mut self) -> &mut Write {
+        self.sp.get().output()
+    }
+
+    /// Gets the error stream.
+    pub fn error(&mut self) -> &mut Write {
+        self.sp.get().error()
+    }
+
+    /// Gets the output stream without any of the application's filters (such as
+    /// escape sequence stripping, CRLF line endings or `--encoding`), for binary
+    /// output such as archives or images. Output written through `output`
+    /// beforehand is written out first.
+    pub fn stdout_raw(&mut self) -> &mut Write {
+        self.sp.unfiltered().output()
+    }
+
+    /// Writes out any output held back by `Application::output_buffering`, e.g.
+    /// before a long-running step, so that what's been printed so far is seen.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.sp.get().output().flush()
+    }
+
+    /// Writes a warning to stderr, prefixed with `Warning: `.
+    ///
+    /// When warnings are treated as errors (see `Application::strict`), the message
+    /// is prefixed with `Error: ` instead, and the command fails even if the
+    /// handler succeeds.
+    pub fn warn(&mut self, message: &str) {
+        self.warnings += 1;
+        let prefix = if self.strict { "Error" } else { "Warning" };
+        writeln!(self.sp.get().error(), "{}: {}", prefix, message).unwrap();
+    }
+
+    /// Writes `contents` to a file, as `Env::write_file` does. A write refused
+    /// because it's outside the sandbox (see `--sandbox DIR`) is also reported
+    /// with `warn`.
+    pub fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let result = self.env.write_file(path, contents);
+        if let Err(ref e) = result {
+            if self.env.sandbox.is_some() && e.kind() == io::ErrorKind::PermissionDenied {
+                self.warn(&e.to_string());
+            }
+        }
+        result
+    }
+
+    /// Returns the number of warnings written with `warn`.
+    pub fn warnings(&self) -> usize {
+        self.warnings
+    }
+
+    /// Returns the command-line arguments exactly as the application received
+    /// them, including its name and any global options, e.g. to re-run itself.
+    ///
+    /// This is empty for a `Context` constructed with `new`.
+    pub fn raw_args(&self) -> &[String] {
+        &self.raw_args
+    }
+
+    /// Returns the arguments inserted from the variable named by
+    /// `Application::opts_var`, which aren't part of `raw_args`.
+    pub fn injected_args(&self) -> &[String] {
+        &self.injected_args
+    }
+
+    /// Asks on stderr whether to re-run the current invocation (see `raw_args`)
+    /// under `sudo`, and if the answer read from stdin is yes, does so and waits
+    /// for it to finish. Returns `None` if the user declined. Variables set through
+    /// `env` are passed on to the new invocation.
+    ///
+    /// It's an error if stdin isn't a terminal, since the answer would be taken
+    /// from piped input. Only the answer's line is read from stdin.
+    ///
+    /// This is meant for commands which find they lack the rights they need; the
+    /// handler would normally exit with the returned status.
+    #[cfg(unix)]
+    pub fn reexec_with_sudo(&mut self) -> io::Result<Option<process::ExitStatus>> {
+        if self.raw_args.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "No command line to re-run"));
+        }
+        if !self.env.is_terminal(env::Stream::Input) {
+            return Err(io::Error::other("Can't ask whether to use sudo, since stdin isn't a terminal"));
+        }
+
+        write!(self.sp.get().error(), "This command needs elevated privileges. Re-run it with sudo? [y/N] ").unwrap();
+        let mut answer = Vec::new();
+        let mut byte = [0];
+        while try!(self.sp.get().input().read(&mut byte)) == 1 && byte[0] != b'\n' {
+            answer.push(byte[0]);
+        }
+        match String::from_utf8_lossy(&answer).trim() {
+            "y" | "Y" | "yes" | "Yes" => (),
+            _ => return Ok(None),
+        }
+
+        // sudo resets the environment, so variables are passed on through env(1),
+        // and injected arguments are given explicitly. It runs from where the
+        // invocation started, so that a relative `-C DIR` isn't applied twice.
+        let exe = try!(::std::env::current_exe());
+        let mut sudo = process::Command::new("sudo");
+        sudo.arg("--").arg("env").args(self.env.env_args()).arg(exe)
+            .args(&self.injected_args).args(&self.raw_args[1..]);
+        if let Some(ref dir) = self.original_dir {
+            sudo.current_dir(dir);
+        }
+        sudo.status().map(Some)
+    }
+
+    /// Gets the application's persisted state, loading it on first use.
+    ///
+    /// For a `Context` constructed with `new`, the state is kept in memory only.
+    pub fn state(&mut self) -> io::Result<&State> {
+        try!(self.load_state());
+        Ok(self.state.as_ref().unwrap())
+    }
+
+    /// Stores `value` under `key` in the application's state, and saves it.
+    /// Like any other file, it's written through `env`, so that e.g. a write
+    /// outside of the sandbox (see `--sandbox DIR`) is refused.
+    pub fn set_state(&mut self, key: &str, value: &str) -> io::Result<()> {
+        try!(self.load_state());
+        self.state.as_mut().unwrap().set(key, value);
+        self.save_state()
+    }
+
+    /// Removes the value stored under `key` from the application's state, and
+    /// saves it as `set_state` does.
+    pub fn remove_state(&mut self, key: &str) -> io::Result<()> {
+        try!(self.load_state());
+        self.state.as_mut().unwrap().remove(key);
+        self.save_state()
+    }
+
+    fn load_state(&mut self) -> io::Result<()> {
+        if self.state.is_none() {
+            self.state = Some(match self.state_path {
+                Some(ref path) => try!(State::load(&self.env, path)),
+                None => State::new(),
+            });
+        }
+        Ok(())
+    }
+
+    fn save_state(&mut self) -> io::Result<()> {
+        match (self.state.as_ref(), self.state_path.as_ref()) {
+            (Some(state), Some(path)) => state.save(&mut self.env, path),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gets a directory for temporary files, creating it on first use. It's
+    /// removed along with its contents when the `Context` is dropped, i.e. once
+    /// the handler returns or panics; a process which is killed can't clean up.
+    pub fn temp_dir(&mut self) -> io::Result<&Path> {
+        if self.temp_dir.is_none() {
+            self.temp_dir = Some(try!(self.env.ep.create_temp_dir()));
+        }
+        Ok(self.temp_dir.as_ref().unwrap())
+    }
+
+    /// Returns whether output may be decorated with colors, spinners, progress
+    /// bars, box drawing and the like. This is false in accessibility mode
+    /// (`--no-fancy`, or see `FrameworkFeatures::FANCY_OUTPUT`), in which
+    /// handlers should write linear plain text that reads well aloud; escape
+    /// sequences are removed from their output regardless.
+    pub fn fancy(&self) -> bool {
+        self.fancy
+    }
+
+    /// Returns whether the user has ruled out network access with `--offline`.
+    /// Commands which can do without it, e.g. by skipping an update check,
+    /// should do so; see also `Command::requires_network`.
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Returns whether the user asked with `--resume` to carry on with a batch
+    /// which was interrupted; see `batch::Checkpoint`.
+    pub fn resume(&self) -> bool {
+        self.resume
+    }
+
+    /// Returns whether output should be the same from run to run, e.g. without
+    /// timestamps or durations, for golden tests; see `Application::deterministic`.
+    pub fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Returns the locale to format numbers and dates for people with; see
+    /// `Application::locale` and `Locale::from_env`.
+    pub fn locale(&self) -> Locale {
+        match self.locale {
+            Some(name) => Locale::from_name(name),
+            None => Locale::from_env(&self.env),
+        }
+    }
+
+    /// Gets the environment the command is running in.
+    pub fn env(&mut self) -> &mut Env<'a> {
+        &mut self.env
+    }
+
+    /// Gets the streams and the environment at once.
+    pub fn parts(&mut self) -> (&mut stream::Provider, &mut Env<'a>) {
+        (self.sp.get(), &mut self.env)
+    }
+
+    /// Like `parts`, but the streams bypass the filters the application applies to
+    /// handlers' output (see e.g. `Application::sanitize`), for output which the
+    /// framework has already filtered, such as that of `Application::watch`.
+    pub fn unfiltered_parts(&mut self) -> (&mut stream::Provider, &mut Env<'a>) {
+        (self.sp.unfiltered(), &mut self.env)
+    }
+}
+
+impl<'a> Drop for Context<'a> {
+    fn drop(&mut self) {
+        if let Some(dir) = self.temp_dir.take() {
+            if let Err(e) = self.env.ep.remove_dir_all(&dir) {
+                let _ = writeln!(self.sp.get().error(), "Warning: Unable to remove temporary directory {}: {}",
+                    dir.display(), e);
+            }
+        }
+    }
+}
+
+impl<'a> stream::Provider for Context<'a> {
+    fn input(&mut self) -> &mut Read {
+        Context::input(self)
+    }
+
+    fn output(&mut self) -> &mut Write {
+        Context::output(self)
+    }
+
+    fn error(&mut self) -> &mut Write {
+        Context::error(self)
+    }
+}
+
+/// The streams a `Context` gives access to.
+enum Streams<'a> {
+    Plain(&'a mut stream::Provider),
+    Filtered(Filtered<'a>),
+}
+
+impl<'a> Streams<'a> {
+    fn get(&mut self) -> &mut stream::Provider {
+        match *self {
+            Streams::Plain(ref mut sp) => &mut **sp,
+            Streams::Filtered(ref mut sp) => sp,
+        }
+    }
+
+    fn unfiltered(&mut self) -> &mut stream::Provider {
+        match *self {
+            Streams::Plain(ref mut sp) => &mut **sp,
+            Streams::Filtered(ref mut sp) => {
+                let _ = sp.finish();
+                sp.inner()
+            },
+        }
+    }
+}
+
+/// The environment a command runs in.
+///
+/// Variables set through `Env` (or with the global `--env KEY=VALUE` option) are
+/// layered over the process environment for the duration of the handler; the
+/// process environment itself is never modified. Use `apply_to` to pass them on
+/// to child processes.
+///
+/// `Env` is also an `env::Provider`, which sees the layered variables.
+pub struct Env<'a> {
+    ep: &'a mut env::Provider,
+    /// Values of `None` mark variables removed from the overlay.
+    overrides: BTreeMap<String, Option<String>>,
+    /// The canonical directory outside of which files may not be written.
+    sandbox: Option<PathBuf>,
+}
+
+impl<'a> Env<'a> {
+    /// Returns the value of an environment variable.
+    pub fn var(&self, key: &str) -> Option<String> {
+        match self.overrides.get(key) {
+            Some(value) => value.clone(),
+            None => self.ep.var(key),
+        }
+    }
+
+    /// Sets an environment variable for the rest of the command.
+    pub fn set_var(&mut self, key: &str, value: &str) {
+        self.overrides.insert(key.to_string(), Some(value.to_string()));
+    }
+
+    /// Unsets an environment variable for the rest of the command.
+    pub fn remove_var(&mut self, key: &str) {
+        self.overrides.insert(key.to_string(), None);
+    }
+
+    /// Returns the current working directory.
+    pub fn current_dir(&self) -> io::Result<PathBuf> {
+        self.ep.current_dir()
+    }
+
+    /// Returns whether a standard stream is connected to a terminal.
+    pub fn is_terminal(&self, stream: env::Stream) -> bool {
+        self.ep.is_terminal(stream)
+    }
+
+    /// Turns echoing of what's typed at the terminal on or off, e.g. while a
+    /// password is entered.
+    pub fn set_echo(&mut self, echo: bool) -> io::Result<()> {
+        self.ep.set_echo(echo)
+    }
+
+    /// Reads the whole of a file; relative paths are resolved against the
+    /// working directory.
+    pub fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.ep.read_file(path)
+    }
+
+    /// Writes `contents` to a file, replacing it if it exists; relative paths are
+    /// resolved against the working directory. When running with `--sandbox DIR`,
+    /// writing outside of `DIR` is refused with a `PermissionDenied` error.
+    pub fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        try!(self.check_sandbox(path));
+        self.ep.write_file(path, contents)
+    }
+
+    /// Creates a directory along with any missing directories containing it,
+    /// within the sandbox as for `write_file`.
+    pub fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        try!(self.check_sandbox(path));
+        self.ep.create_dir_all(path)
+    }
+
+    /// Renames a file, replacing `to` if it exists; both paths have to be within
+    /// the sandbox as for `write_file`.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        try!(self.check_sandbox(from));
+        try!(self.check_sandbox(to));
+        self.ep.rename(from, to)
+    }
+
+    /// Refuses `path` if there's a sandbox and `path` isn't inside it. As much of
+    /// `path` as exists is resolved, so that symbolic links out of the sandbox
+    /// (including the file itself being one) are caught, and the rest has to be
+    /// plain names.
+    fn check_sandbox(&self, path: &Path) -> io::Result<()> {
+        let root = match self.sandbox {
+            Some(ref root) => root,
+            None => return Ok(()),
+        };
+
+        let path = try!(self.ep.current_dir()).join(path);
+        let mut existing = path.as_path();
+        let resolved = loop {
+            match self.ep.canonicalize(existing) {
+                Ok(resolved) => break Some(resolved),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => match existing.parent() {
+                    Some(parent) => existing = parent,
+                    None => break None,
+                },
+                Err(e) => return Err(e),
+            }
+        };
+        let rest = path.strip_prefix(existing).unwrap_or(&path);
+        let inside = resolved.is_some_and(|resolved| resolved.starts_with(root))
+            && rest.components().all(|c| matches!(c, Component::Normal(_)));
+
+        if inside {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                format!("Refusing to write '{}', which is outside the sandbox '{}'", path.display(), root.display())))
+        }
+    }
+
+    /// Returns the current time. Time-dependent handlers should use this rather
+    /// than the system clock, so that tests can control it.
+    pub fn now(&self) -> SystemTime {
+        self.ep.now()
+    }
+
+    /// Waits for `duration` to pass; like `now`, this can be simulated in tests.
+    pub fn sleep(&mut self, duration: Duration) {
+        self.ep.sleep(duration)
+    }
+
+    /// Returns a random number, e.g. to pick a hint or to add jitter to a retry.
+    /// Like `now`, this can be controlled in tests; see `env::Virtual::set_random_seed`.
+    pub fn random(&mut self) -> u64 {
+        self.ep.random()
+    }
+
+    /// Returns arguments for `env(1)` which apply the variables set or removed
+    /// through this `Env`.
+    #[cfg(unix)]
+    fn env_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (key, value) in &self.overrides {
+            if value.is_none() {
+                args.push("-u".to_string());
+                args.push(key.clone());
+            }
+        }
+        for (key, value) in &self.overrides {
+            if let Some(ref value) = *value {
+                args.push(format!("{}={}", key, value));
+            }
+        }
+        args
+    }
+
+    /// Applies the variables set or removed through this `Env` to a child process.
+    pub fn apply_to(&self, cmd: &mut process::Command) {
+        for (key, value) in &self.overrides {
+            match *value {
+                Some(ref value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+    }
+}
+
+impl<'a> env::Provider for Env<'a> {
+    fn current_dir(&self) -> io::Result<PathBuf> {
+        self.ep.current_dir()
+    }
+
+    fn set_current_dir(&mut self, path: &Path) -> io::Result<()> {
+        self.ep.set_current_dir(path)
+    }
+
+    fn var(&self, key: &str) -> Option<String> {
+        Env::var(self, key)
+    }
+
+    fn is_terminal(&self, stream: env::Stream) -> bool {
+        Env::is_terminal(self, stream)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.ep.canonicalize(path)
+    }
+
+    fn set_echo(&mut self, echo: bool) -> io::Result<()> {
+        Env::set_echo(self, echo)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        Env::read_file(self, path)
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        Env::write_file(self, path, contents)
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        Env::create_dir_all(self, path)
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        Env::rename(self, from, to)
+    }
+
+    fn now(&self) -> SystemTime {
+        Env::now(self)
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        Env::sleep(self, duration)
+    }
+
+    fn random(&mut self) -> u64 {
+        Env::random(self)
+    }
+
+    fn create_temp_dir(&mut self) -> io::Result<PathBuf> {
+        self.ep.create_temp_dir()
+    }
+
+    fn remove_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        self.ep.remove_dir_all(path)
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use io_providers::stream;
+    use env;
+    use env::Provider;
+
+    #[test]
+    #[cfg(unix)]
+    fn env__write_file_symlink_out_of_sandbox__refused() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Std::new();
+        let (root, outside) = (ep.create_temp_dir().unwrap(), ep.create_temp_dir().unwrap());
+        let (root, outside) = (ep.canonicalize(&root).unwrap(), ep.canonicalize(&outside).unwrap());
+        fs::write(outside.join("existing.txt"), b"before").unwrap();
+        ::std::os::unix::fs::symlink(outside.join("existing.txt"), root.join("link.txt")).unwrap();
+        let results = {
+            let mut ctx = Context::new(&mut sp, &mut ep);
+            ctx.set_sandbox(Some(root.clone()));
+            vec![
+                ctx.env().write_file(&root.join("link.txt"), b"after").map_err(|e| e.kind()),
+                ctx.env().write_file(&root.join("plain.txt"), b"after").map_err(|e| e.kind()),
+            ]
+        };
+        let contents = fs::read(outside.join("existing.txt")).unwrap();
+        ep.remove_dir_all(&root).unwrap();
+        ep.remove_dir_all(&outside).unwrap();
+
+        assert_eq!(vec![Err(io::ErrorKind::PermissionDenied), Ok(())], results);
+        assert_eq!(b"before".to_vec(), contents);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn context__reexec_with_sudo_declined__not_run() {
+        let mut sp = stream::Virtual::new();
+        sp.write_input(b"n\nrest");
+        let mut ep = env::Virtual::new();
+        ep.set_terminal(env::Stream::Input, true);
+        let mut ctx = Context::new(&mut sp, &mut ep);
+        ctx.set_raw_args(vec!["app".to_string(), "cmd".to_string()]);
+
+        let result = ctx.reexec_with_sudo();
+        let mut rest = String::new();
+        ctx.input().read_to_string(&mut rest).unwrap();
+
+        assert!(result.unwrap().is_none());
+        assert_eq!("rest", rest);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn context__reexec_with_sudo_piped_stdin__error() {
+        let mut sp = stream::Virtual::new();
+        sp.write_input(b"y\n");
+        let mut ep = env::Virtual::new();
+        let result = {
+            let mut ctx = Context::new(&mut sp, &mut ep);
+            ctx.set_raw_args(vec!["app".to_string(), "cmd".to_string()]);
+            ctx.reexec_with_sudo().map(|_| ())
+        };
+
+        assert!(result.is_err());
+        assert_eq!(0, sp.read_error().len());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn env__env_args__unsets_then_sets() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        let mut ctx = Context::new(&mut sp, &mut ep);
+
+        ctx.env().set_var("B", "two words");
+        ctx.env().remove_var("A");
+
+        assert_eq!(vec!["-u", "A", "B=two words"], ctx.env().env_args());
+    }
+
+    #[test]
+    fn env__set_var__overlays_without_changing_provider() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("A", "real");
+        {
+            let mut ctx = Context::new(&mut sp, &mut ep);
+
+            ctx.env().set_var("A", "override");
+            ctx.env().set_var("B", "new");
+
+            assert_eq!(Some("override".to_string()), ctx.env().var("A"));
+            assert_eq!(Some("new".to_string()), ctx.env().var("B"));
+        }
+
+        assert_eq!(Some("real".to_string()), ep.var("A"));
+        assert_eq!(None, ep.var("B"));
+    }
+
+    #[test]
+    fn env__remove_var__hides_provider_value() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("A", "real");
+        let mut ctx = Context::new(&mut sp, &mut ep);
+
+        ctx.env().remove_var("A");
+
+        assert_eq!(None, ctx.env().var("A"));
+    }
+
+    #[test]
+    fn context__temp_dir__created_once_and_removed_on_drop() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        {
+            let mut ctx = Context::new(&mut sp, &mut ep);
+            let dir = ctx.temp_dir().unwrap().to_path_buf();
+
+            assert_eq!(dir, ctx.temp_dir().unwrap());
+            assert!(ctx.env().canonicalize(&dir).is_ok());
+        }
+
+        assert!(ep.canonicalize(Path::new("/tmp/1")).is_err());
+        assert_eq!(PathBuf::from("/tmp/2"), ep.create_temp_dir().unwrap());
+    }
+
+    #[test]
+    fn context__warn__prefixes_and_counts() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        {
+            let mut ctx = Context::new(&mut sp, &mut ep);
+
+            ctx.warn("first");
+            ctx.set_strict(true);
+            ctx.warn("second");
+
+            assert_eq!(2, ctx.warnings());
+        }
+
+        assert_eq!("Warning: first\nError: second\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn context__locale__env_overrides_then_setting() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("LANG", "en_US.UTF-8");
+        let mut ctx = Context::new(&mut sp, &mut ep);
+
+        assert_eq!("1,000", ctx.locale().format_int(1000));
+        ctx.env().set_var("LC_ALL", "de_DE.UTF-8");
+        assert_eq!("1.000", ctx.locale().format_int(1000));
+        ctx.set_locale(Some("C"));
+        assert_eq!("1000", ctx.locale().format_int(1000));
+    }
+
+    #[test]
+    fn context__streams__delegate() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        {
+            let mut ctx = Context::new(&mut sp, &mut ep);
+
+            write!(ctx.output(), "out").unwrap();
+            write!(ctx.error(), "err").unwrap();
+        }
+
+        assert_eq!("out", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!("err", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+}
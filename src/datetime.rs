@@ -0,0 +1,240 @@
+//! Parsing of the dates and times given to `ParamKind::DateTime` parameters.
+//!
+//! These may be given as RFC 3339 timestamps (`2024-03-01T12:30:00+01:00`),
+//! dates (`2024-03-01`, meaning midnight UTC), `now`, `today`, `yesterday`,
+//! `tomorrow`, or relative to now (`2 hours ago`). Before the handler runs,
+//! they're converted to RFC 3339 timestamps in UTC, which handlers can get as
+//! `SystemTime`s with `Arguments::times`.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Like `try!`, for `Option`s.
+macro_rules! try_opt {
+    ($e:expr) => (match $e { Some(v) => v, None => return None })
+}
+
+/// Parses a date or time in any of the accepted forms, with relative ones
+/// resolved against `now`.
+pub fn parse(value: &str, now: SystemTime) -> Option<SystemTime> {
+    let now_secs = to_unix(now).0;
+    let today = now_secs - now_secs.rem_euclid(SECS_PER_DAY);
+
+    let lower = value.trim().to_lowercase();
+    match &lower[..] {
+        "now" => return Some(now),
+        "today" => return Some(from_unix(today, 0)),
+        "yesterday" => return Some(from_unix(today - SECS_PER_DAY, 0)),
+        "tomorrow" => return Some(from_unix(today + SECS_PER_DAY, 0)),
+        _ => (),
+    }
+
+    parse_relative(&lower, now_secs).map(|secs| from_unix(secs, to_unix(now).1))
+        .or_else(|| parse_rfc3339(&lower))
+        .or_else(|| parse_date(&lower).map(|days| from_unix(days * SECS_PER_DAY, 0)))
+}
+
+/// Formats `time` as an RFC 3339 timestamp in UTC, e.g. `2024-03-01T11:30:00Z`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let (secs, nanos) = to_unix(time);
+    let days = secs.div_euclid(SECS_PER_DAY);
+    let secs_of_day = secs.rem_euclid(SECS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+
+    let mut s = format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year, month, day, secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60);
+    if nanos > 0 {
+        s.push_str(format!(".{:09}", nanos).trim_end_matches('0'));
+    }
+    s.push('Z');
+    s
+}
+
+/// Returns the year, month and day of `time` in UTC.
+pub(crate) fn date_of(time: SystemTime) -> (i64, i64, i64) {
+    civil_from_days(to_unix(time).0.div_euclid(SECS_PER_DAY))
+}
+
+/// Parses e.g. `2 hours ago` into seconds since the epoch.
+fn parse_relative(value: &str, now_secs: i64) -> Option<i64> {
+    let words: Vec<&str> = value.split_whitespace().collect();
+    if words.len() != 3 || words[2] != "ago" {
+        return None;
+    }
+
+    let count: i64 = match words[0].parse() {
+        Ok(count) if count >= 0 => count,
+        _ => return None,
+    };
+    let unit = match words[1].trim_end_matches('s') {
+        "second" | "sec" => 1,
+        "minute" | "min" => 60,
+        "hour" => 60 * 60,
+        "day" => SECS_PER_DAY,
+        "week" => 7 * SECS_PER_DAY,
+        _ => return None,
+    };
+    count.checked_mul(unit).and_then(|offset| now_secs.checked_sub(offset))
+}
+
+/// Parses an RFC 3339 timestamp, also accepting a space in place of the `T`.
+fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    if value.len() < 20 || !value.is_ascii() {
+        return None;
+    }
+    let days = try_opt!(parse_date(&value[..10]));
+    match &value[10..11] {
+        "t" | " " => (),
+        _ => return None,
+    }
+
+    let rest = &value[11..];
+    if rest.len() < 9 || &rest[2..3] != ":" || &rest[5..6] != ":" {
+        return None;
+    }
+    let hours = try_opt!(parse_digits(&rest[..2]));
+    let minutes = try_opt!(parse_digits(&rest[3..5]));
+    let seconds = try_opt!(parse_digits(&rest[6..8]));
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    let mut rest = &rest[8..];
+    let mut nanos = 0;
+    if rest.starts_with('.') {
+        let digits = rest[1..].find(|c: char| !c.is_ascii_digit()).map_or(rest.len() - 1, |i| i);
+        if digits == 0 {
+            return None;
+        }
+        let fraction = &rest[1..1 + digits.min(9)];
+        nanos = try_opt!(parse_digits(fraction)) as u32 * 10u32.pow(9 - fraction.len() as u32);
+        rest = &rest[1 + digits..];
+    }
+
+    let offset = match rest {
+        "z" => 0,
+        _ if rest.len() == 6 && &rest[3..4] == ":" => {
+            let sign = match &rest[..1] {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            sign * (try_opt!(parse_digits(&rest[1..3])) * 3600 + try_opt!(parse_digits(&rest[4..6])) * 60)
+        },
+        _ => return None,
+    };
+
+    Some(from_unix(days * SECS_PER_DAY + hours * 3600 + minutes * 60 + seconds - offset, nanos))
+}
+
+/// Parses a `YYYY-MM-DD` date into days since the epoch.
+fn parse_date(value: &str) -> Option<i64> {
+    if value.len() != 10 || !value.is_ascii() || &value[4..5] != "-" || &value[7..8] != "-" {
+        return None;
+    }
+    let year = try_opt!(parse_digits(&value[..4]));
+    let month = try_opt!(parse_digits(&value[5..7]));
+    let day = try_opt!(parse_digits(&value[8..10]));
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    Some(days_from_civil(year, month, day))
+}
+
+fn parse_digits(s: &str) -> Option<i64> {
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Converts a date in the proleptic Gregorian calendar to days since the epoch.
+/// See http://howardhinnant.github.io/date_algorithms.html.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+fn to_unix(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                nanos => (-(d.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        },
+    }
+}
+
+fn from_unix(secs: i64, nanos: u32) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nanos)
+    } else {
+        UNIX_EPOCH - Duration::new((-secs) as u64, 0) + Duration::new(0, nanos)
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn parse__rfc3339__success() {
+        let now = UNIX_EPOCH;
+
+        assert_eq!(Some(UNIX_EPOCH + Duration::from_secs(1709292600)), parse("2024-03-01T12:30:00+01:00", now));
+        assert_eq!(Some(UNIX_EPOCH + Duration::new(1709296200, 500_000_000)), parse("2024-03-01 12:30:00.5Z", now));
+        assert_eq!(None, parse("2024-03-01T25:00:00Z", now));
+        assert_eq!(None, parse("2024-03-01T12:30:00", now));
+    }
+
+    #[test]
+    fn parse__dates_and_relative__success() {
+        let now = UNIX_EPOCH + Duration::from_secs(1709296200);
+
+        assert_eq!("2024-02-29T00:00:00Z", format_rfc3339(parse("2024-02-29", now).unwrap()));
+        assert_eq!(None, parse("2023-02-29", now));
+        assert_eq!("2024-02-29T00:00:00Z", format_rfc3339(parse("Yesterday", now).unwrap()));
+        assert_eq!("2024-03-01T10:30:00Z", format_rfc3339(parse("2 hours ago", now).unwrap()));
+        assert_eq!("2024-02-23T12:30:00Z", format_rfc3339(parse("1 week ago", now).unwrap()));
+        assert_eq!(None, parse("2 fortnights ago", now));
+    }
+
+    #[test]
+    fn format_rfc3339__before_epoch__success() {
+        assert_eq!("1969-12-31T23:59:59.5Z", format_rfc3339(UNIX_EPOCH - Duration::from_millis(500)));
+        assert_eq!("1900-01-01T00:00:00Z", format_rfc3339(parse("1900-01-01", UNIX_EPOCH).unwrap()));
+    }
+}
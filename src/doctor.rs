@@ -0,0 +1,140 @@
+//! A framework for `doctor`-style self-check commands, which diagnose common
+//! problems with the application's environment and say how to fix them.
+//!
+//! Applications register named checks with a `Doctor`, and run it from the
+//! handler of a `doctor` command:
+//!
+//! ```no_run
+//! # extern crate command_cli;
+//! use std::env;
+//! use command_cli::{Arguments, CommandResult, Context};
+//! use command_cli::doctor::{Doctor, Outcome};
+//!
+//! fn doctor_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+//!     let mut doctor = Doctor::new();
+//!     doctor.add_check("home directory is known", || match env::home_dir() {
+//!         Some(_) => Outcome::Pass,
+//!         None => Outcome::Fail("Set the HOME environment variable".to_string()),
+//!     });
+//!     doctor.run(ctx)
+//! }
+//! # fn main() {}
+//! ```
+
+use io_providers::stream;
+use CommandResult;
+
+/// The outcome of a single check.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// Nothing is wrong.
+    Pass,
+    /// Something may be wrong, but the application can still work; holds remediation text.
+    Warn(String),
+    /// The application won't work properly; holds remediation text.
+    Fail(String),
+}
+
+/// A named check.
+struct Check<'a> {
+    name: String,
+    run: Box<Fn() -> Outcome + 'a>,
+}
+
+/// A collection of checks, which are run in the order they were added.
+#[derive(Default)]
+pub struct Doctor<'a> {
+    checks: Vec<Check<'a>>,
+}
+
+impl<'a> Doctor<'a> {
+    /// Constructs a `Doctor` with no checks.
+    pub fn new() -> Doctor<'a> {
+        Doctor { checks: Vec::new() }
+    }
+
+    /// Adds a check, described by `name`.
+    pub fn add_check<F: Fn() -> Outcome + 'a>(&mut self, name: &str, check: F) {
+        self.checks.push(Check { name: name.to_string(), run: Box::new(check) });
+    }
+
+    /// Runs every check, printing a report to stdout.
+    ///
+    /// Yields an `ExecutionError` if any check failed; warnings alone don't
+    /// affect the result.
+    pub fn run(&self, sp: &mut stream::Provider) -> CommandResult {
+        let (mut passed, mut warnings, mut failed) = (0, 0, 0);
+
+        for check in &self.checks {
+            let outcome = (check.run)();
+            let (label, remediation) = match outcome {
+                Outcome::Pass => {
+                    passed += 1;
+                    ("ok", None)
+                },
+                Outcome::Warn(ref text) => {
+                    warnings += 1;
+                    ("warn", Some(text))
+                },
+                Outcome::Fail(ref text) => {
+                    failed += 1;
+                    ("FAIL", Some(text))
+                },
+            };
+
+            writeln!(sp.output(), "[{: <4}] {}", label, check.name).unwrap();
+            if let Some(text) = remediation {
+                for line in text.lines() {
+                    writeln!(sp.output(), "       {}", line).unwrap();
+                }
+            }
+        }
+
+        writeln!(sp.output(), "\n{} passed, {} warnings, {} failed", passed, warnings, failed).unwrap();
+
+        if failed > 0 {
+            CommandResult::ExecutionError(None)
+        } else {
+            CommandResult::Success
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use io_providers::stream;
+    use CommandResult;
+
+    #[test]
+    fn doctor__run__prints_report() {
+        let mut sp = stream::Virtual::new();
+        let mut doctor = Doctor::new();
+        doctor.add_check("first", || Outcome::Pass);
+        doctor.add_check("second", || Outcome::Warn("Try this\nor that".to_string()));
+
+        let result = doctor.run(&mut sp);
+
+        assert!(matches!(result, CommandResult::Success));
+        assert_eq!("\
+            [ok  ] first\n\
+            [warn] second\n       \
+                   Try this\n       \
+                   or that\n\
+            \n\
+            1 passed, 1 warnings, 0 failed\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn doctor__run_with_failure__execution_error() {
+        let mut sp = stream::Virtual::new();
+        let mut doctor = Doctor::new();
+        doctor.add_check("broken", || Outcome::Fail("Fix it".to_string()));
+
+        let result = doctor.run(&mut sp);
+
+        assert!(matches!(result, CommandResult::ExecutionError(None)));
+    }
+}
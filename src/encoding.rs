@@ -0,0 +1,261 @@
+//! Conversion between UTF-8, which handlers work in, and other text encodings.
+//!
+//! The global `--encoding NAME` option applies a `Decoder` to stdin and an
+//! `Encoder` to stdout and stderr, so that handlers can read and write e.g.
+//! Latin-1 or UTF-16 streams as ordinary UTF-8.
+
+use std::char;
+use std::str;
+use filter::Filter;
+
+/// A text encoding.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    /// ISO-8859-1, in which each byte is the Unicode code point of the same value.
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    /// Gets an encoding from its (case-insensitive) name, e.g. `utf-16le` or `latin1`.
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match &name.to_lowercase()[..] {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+}
+
+/// A `Filter` which converts text in some encoding to UTF-8.
+///
+/// Malformed input (e.g. an unpaired UTF-16 surrogate) is replaced with U+FFFD.
+pub struct Decoder {
+    encoding: Encoding,
+    /// An odd trailing byte of UTF-16 input.
+    pending: Option<u8>,
+    high_surrogate: Option<u16>,
+}
+
+impl Decoder {
+    /// Constructs a `Decoder` from the given encoding.
+    pub fn new(encoding: Encoding) -> Decoder {
+        Decoder { encoding, pending: None, high_surrogate: None }
+    }
+
+    fn decode_unit(&mut self, unit: u16, out: &mut Vec<u8>) {
+        if let Some(high) = self.high_surrogate.take() {
+            if (0xdc00..=0xdfff).contains(&unit) {
+                let c = 0x10000 + (((high - 0xd800) as u32) << 10) + (unit - 0xdc00) as u32;
+                push_char(char::from_u32(c).unwrap(), out);
+                return;
+            }
+            push_char(char::REPLACEMENT_CHARACTER, out);
+        }
+
+        match unit {
+            0xd800..=0xdbff => self.high_surrogate = Some(unit),
+            0xdc00..=0xdfff => push_char(char::REPLACEMENT_CHARACTER, out),
+            _ => push_char(char::from_u32(unit as u32).unwrap(), out),
+        }
+    }
+}
+
+impl Filter for Decoder {
+    fn filter(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        match self.encoding {
+            Encoding::Utf8 => out.extend_from_slice(buf),
+            Encoding::Latin1 => {
+                for &b in buf {
+                    push_char(b as char, out);
+                }
+            },
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut bytes = self.pending.take().into_iter().chain(buf.iter().cloned());
+                while let Some(first) = bytes.next() {
+                    let second = match bytes.next() {
+                        Some(b) => b,
+                        None => {
+                            self.pending = Some(first);
+                            break;
+                        },
+                    };
+                    let unit = match self.encoding {
+                        Encoding::Utf16Le => (second as u16) << 8 | first as u16,
+                        _ => (first as u16) << 8 | second as u16,
+                    };
+                    self.decode_unit(unit, out);
+                }
+            },
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        if self.pending.take().is_some() || self.high_surrogate.take().is_some() {
+            push_char(char::REPLACEMENT_CHARACTER, out);
+        }
+    }
+}
+
+/// A `Filter` which converts UTF-8 text to some encoding.
+///
+/// Invalid UTF-8 is replaced with U+FFFD, and characters which can't be
+/// represented in Latin-1 with `?`.
+pub struct Encoder {
+    encoding: Encoding,
+    /// An incomplete UTF-8 sequence at the end of the input so far.
+    pending: Vec<u8>,
+}
+
+impl Encoder {
+    /// Constructs an `Encoder` to the given encoding.
+    pub fn new(encoding: Encoding) -> Encoder {
+        Encoder { encoding, pending: Vec::new() }
+    }
+
+    fn encode_str(&self, s: &str, out: &mut Vec<u8>) {
+        for c in s.chars() {
+            self.encode_char(c, out);
+        }
+    }
+
+    fn encode_char(&self, c: char, out: &mut Vec<u8>) {
+        match self.encoding {
+            Encoding::Utf8 => push_char(c, out),
+            Encoding::Latin1 => out.push(if (c as u32) <= 0xff { c as u8 } else { b'?' }),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut units = [0u16; 2];
+                for &unit in c.encode_utf16(&mut units).iter() {
+                    let (high, low) = ((unit >> 8) as u8, unit as u8);
+                    match self.encoding {
+                        Encoding::Utf16Le => out.extend_from_slice(&[low, high]),
+                        _ => out.extend_from_slice(&[high, low]),
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl Filter for Encoder {
+    fn filter(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        if self.encoding == Encoding::Utf8 {
+            out.extend_from_slice(buf);
+            return;
+        }
+
+        let mut data = ::std::mem::take(&mut self.pending);
+        data.extend_from_slice(buf);
+        let mut rest = &data[..];
+
+        loop {
+            match str::from_utf8(rest) {
+                Ok(s) => {
+                    self.encode_str(s, out);
+                    break;
+                },
+                Err(e) => {
+                    let valid = e.valid_up_to();
+                    self.encode_str(str::from_utf8(&rest[..valid]).unwrap(), out);
+                    match e.error_len() {
+                        Some(len) => {
+                            self.encode_char(char::REPLACEMENT_CHARACTER, out);
+                            rest = &rest[valid + len..];
+                        },
+                        None => {
+                            self.pending = rest[valid..].to_vec();
+                            break;
+                        },
+                    }
+                },
+            }
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            self.encode_char(char::REPLACEMENT_CHARACTER, out);
+        }
+    }
+}
+
+fn push_char(c: char, out: &mut Vec<u8>) {
+    let mut buf = [0u8; 4];
+    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use filter::Filter;
+
+    #[test]
+    fn encoding__from_name__success() {
+        assert_eq!(Some(Encoding::Utf16Le), Encoding::from_name("UTF-16LE"));
+        assert_eq!(Some(Encoding::Latin1), Encoding::from_name("iso-8859-1"));
+        assert_eq!(None, Encoding::from_name("ebcdic"));
+    }
+
+    #[test]
+    fn decoder__latin1__success() {
+        let mut decoder = Decoder::new(Encoding::Latin1);
+        let mut out = Vec::new();
+
+        decoder.filter(b"caf\xe9", &mut out);
+
+        assert_eq!("café", ::std::str::from_utf8(&out).unwrap());
+    }
+
+    #[test]
+    fn decoder__utf16le_split_across_writes__success() {
+        let mut decoder = Decoder::new(Encoding::Utf16Le);
+        let mut out = Vec::new();
+
+        // "a😀" is 61 00 3D D8 00 DE
+        decoder.filter(b"\x61\x00\x3d", &mut out);
+        decoder.filter(b"\xd8\x00", &mut out);
+        decoder.filter(b"\xde", &mut out);
+        decoder.finish(&mut out);
+
+        assert_eq!("a😀", ::std::str::from_utf8(&out).unwrap());
+    }
+
+    #[test]
+    fn decoder__utf16be_unpaired_surrogate__replaced() {
+        let mut decoder = Decoder::new(Encoding::Utf16Be);
+        let mut out = Vec::new();
+
+        decoder.filter(b"\xd8\x3d\x00\x62", &mut out);
+        decoder.finish(&mut out);
+
+        assert_eq!("\u{fffd}b", ::std::str::from_utf8(&out).unwrap());
+    }
+
+    #[test]
+    fn encoder__utf16be__success() {
+        let mut encoder = Encoder::new(Encoding::Utf16Be);
+        let mut out = Vec::new();
+
+        encoder.filter("a😀".as_bytes(), &mut out);
+
+        assert_eq!(b"\x00\x61\xd8\x3d\xde\x00", &out[..]);
+    }
+
+    #[test]
+    fn encoder__latin1_split_sequence__held_back() {
+        let mut encoder = Encoder::new(Encoding::Latin1);
+        let mut out = Vec::new();
+
+        encoder.filter(b"caf\xc3", &mut out);
+        encoder.filter(b"\xa9 \xe2\x82\xac", &mut out);
+        encoder.finish(&mut out);
+
+        assert_eq!(b"caf\xe9 ?", &out[..]);
+    }
+}
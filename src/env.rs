@@ -0,0 +1,528 @@
+//! Access to the process environment (working directory, environment variables
+//! and file system), behind a trait so that it can be simulated in tests.
+//!
+//! This mirrors `io_providers::stream`: `Std` is backed by the real process
+//! environment, and `Virtual` by in-memory state.
+
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, IsTerminal};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Identifies one of the standard streams.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stream {
+    Input,
+    Output,
+    Error,
+}
+
+/// Provides access to the process environment.
+pub trait Provider {
+    /// Returns the current working directory.
+    fn current_dir(&self) -> io::Result<PathBuf>;
+
+    /// Changes the current working directory; relative paths are resolved against the current one.
+    fn set_current_dir(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Returns the value of an environment variable, if it is set to valid Unicode.
+    fn var(&self, key: &str) -> Option<String>;
+
+    /// Returns whether a standard stream is connected to a terminal (rather than
+    /// e.g. a pipe or file).
+    fn is_terminal(&self, stream: Stream) -> bool;
+
+    /// Returns the absolute form of `path`, with all symbolic links resolved, or
+    /// an error if it doesn't exist.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// Turns echoing of what's typed at the terminal on or off, e.g. while a
+    /// password is entered.
+    fn set_echo(&mut self, echo: bool) -> io::Result<()>;
+
+    /// Reads the whole of a file; relative paths are resolved against the
+    /// working directory.
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Writes `contents` to a file, replacing it if it exists; relative paths are
+    /// resolved against the working directory.
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Creates a directory, along with any of the directories containing it
+    /// which don't exist yet.
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Renames a file, replacing `to` if it exists, e.g. to move a file written
+    /// elsewhere into place all at once.
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Returns the current time, against which e.g. `yesterday` is resolved.
+    fn now(&self) -> SystemTime;
+
+    /// Waits for `duration` to pass, e.g. between runs of `Application::watch`.
+    fn sleep(&mut self, duration: Duration);
+
+    /// Returns a random number, e.g. to pick a hint or to add jitter to a
+    /// retry. It isn't fit for cryptography.
+    fn random(&mut self) -> u64;
+
+    /// Creates a new, empty directory which nothing else is using, and returns
+    /// its path.
+    fn create_temp_dir(&mut self) -> io::Result<PathBuf>;
+
+    /// Removes a directory along with everything in it.
+    fn remove_dir_all(&mut self, path: &Path) -> io::Result<()>;
+}
+
+/// Provides access to the real process environment.
+#[derive(Default)]
+pub struct Std;
+
+impl Std {
+    /// Constructs a new `Std` provider.
+    pub fn new() -> Std {
+        Std
+    }
+}
+
+impl Provider for Std {
+    fn current_dir(&self) -> io::Result<PathBuf> {
+        env::current_dir()
+    }
+
+    /// Under WASI, which has no working directory to change, this always fails.
+    fn set_current_dir(&mut self, path: &Path) -> io::Result<()> {
+        if cfg!(target_os = "wasi") {
+            return Err(io::Error::other("Changing directory isn't supported under WASI"));
+        }
+        env::set_current_dir(path)
+    }
+
+    fn var(&self, key: &str) -> Option<String> {
+        env::var(key).ok()
+    }
+
+    fn is_terminal(&self, stream: Stream) -> bool {
+        isatty(stream)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn set_echo(&mut self, echo: bool) -> io::Result<()> {
+        set_echo(echo)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration)
+    }
+
+    /// Each `RandomState` is keyed differently, from a seed the OS provides.
+    fn random(&mut self) -> u64 {
+        RandomState::new().build_hasher().finish()
+    }
+
+    /// The directory is made in the system's temporary directory, and on Unix
+    /// can only be accessed by the current user.
+    fn create_temp_dir(&mut self) -> io::Result<PathBuf> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+
+        loop {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+            let name = format!("command-cli-{}-{}-{}", process::id(), COUNT.fetch_add(1, Ordering::SeqCst), nanos);
+            let path = env::temp_dir().join(name);
+            match builder.create(&path) {
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                result => return result.map(|_| path),
+            }
+        }
+    }
+
+    fn remove_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+}
+
+fn isatty(stream: Stream) -> bool {
+    match stream {
+        Stream::Input => io::stdin().is_terminal(),
+        Stream::Output => io::stdout().is_terminal(),
+        Stream::Error => io::stderr().is_terminal(),
+    }
+}
+
+#[cfg(unix)]
+fn set_echo(echo: bool) -> io::Result<()> {
+    use std::process;
+
+    let status = try!(process::Command::new("stty")
+        .arg(if echo { "echo" } else { "-echo" })
+        .stdin(process::Stdio::inherit())
+        .status());
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other("Unable to change terminal echo"))
+    }
+}
+
+/// Elsewhere (including WASI, which can't run `stty`) echo can't be changed yet,
+/// so input is echoed as usual.
+#[cfg(not(unix))]
+fn set_echo(_: bool) -> io::Result<()> {
+    Ok(())
+}
+
+/// Provides access to a simulated environment, for testing.
+pub struct Virtual {
+    current_dir: PathBuf,
+    vars: HashMap<String, String>,
+    terminals: Vec<Stream>,
+    /// Paths which exist, along with their ancestors.
+    paths: Vec<PathBuf>,
+    files: HashMap<PathBuf, Vec<u8>>,
+    echo: bool,
+    now: SystemTime,
+    temp_dirs: usize,
+    random_state: u64,
+}
+
+impl Virtual {
+    /// Constructs a `Virtual` environment with no variables, whose working directory is `/`,
+    /// none of whose streams are terminals, whose file system has only `/`, whose
+    /// clock is stopped at the Unix epoch, and whose random numbers are the
+    /// sequence for seed 0 (see `set_random_seed`).
+    pub fn new() -> Virtual {
+        Virtual {
+            current_dir: PathBuf::from("/"),
+            vars: HashMap::new(),
+            terminals: Vec::new(),
+            paths: Vec::new(),
+            files: HashMap::new(),
+            echo: true,
+            now: UNIX_EPOCH,
+            temp_dirs: 0,
+            random_state: 0,
+        }
+    }
+
+    /// Returns whether terminal input is being echoed; see `Provider::set_echo`.
+    pub fn echo(&self) -> bool {
+        self.echo
+    }
+
+    /// Makes `path` (resolved against the working directory) exist, along with
+    /// the directories containing it.
+    pub fn add_path(&mut self, path: &Path) {
+        let path = normalize(&self.current_dir.join(path));
+        self.paths.push(path);
+    }
+
+    /// Makes `path` (resolved against the working directory) a file with the
+    /// given contents, as `add_path` does.
+    pub fn add_file(&mut self, path: &Path, contents: &[u8]) {
+        let path = normalize(&self.current_dir.join(path));
+        self.paths.push(path.clone());
+        self.files.insert(path, contents.to_vec());
+    }
+
+    /// Sets the time returned by `Provider::now`.
+    pub fn set_now(&mut self, now: SystemTime) {
+        self.now = now;
+    }
+
+    /// Starts the sequence of numbers returned by `Provider::random` over, from
+    /// `seed`. Each seed gives its own sequence, which is always the same.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random_state = seed;
+    }
+
+    /// Sets whether a stream is reported to be a terminal.
+    pub fn set_terminal(&mut self, stream: Stream, is_terminal: bool) {
+        self.terminals.retain(|&s| s != stream);
+        if is_terminal {
+            self.terminals.push(stream);
+        }
+    }
+
+    /// Sets an environment variable.
+    pub fn set_var(&mut self, key: &str, value: &str) {
+        self.vars.insert(key.to_string(), value.to_string());
+    }
+
+    /// Unsets an environment variable.
+    pub fn remove_var(&mut self, key: &str) {
+        self.vars.remove(key);
+    }
+}
+
+impl Default for Virtual {
+    fn default() -> Virtual {
+        Virtual::new()
+    }
+}
+
+impl Provider for Virtual {
+    fn current_dir(&self) -> io::Result<PathBuf> {
+        Ok(self.current_dir.clone())
+    }
+
+    fn set_current_dir(&mut self, path: &Path) -> io::Result<()> {
+        self.current_dir = self.current_dir.join(path);
+        Ok(())
+    }
+
+    fn var(&self, key: &str) -> Option<String> {
+        self.vars.get(key).cloned()
+    }
+
+    fn is_terminal(&self, stream: Stream) -> bool {
+        self.terminals.contains(&stream)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = normalize(&self.current_dir.join(path));
+        if path.parent().is_none() || self.paths.iter().any(|p| p.starts_with(&path)) {
+            Ok(path)
+        } else {
+            Err(io::Error::new(io::ErrorKind::NotFound, "No such file or directory"))
+        }
+    }
+
+    fn set_echo(&mut self, echo: bool) -> io::Result<()> {
+        self.echo = echo;
+        Ok(())
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.files.get(&normalize(&self.current_dir.join(path))) {
+            Some(contents) => Ok(contents.clone()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "No such file or directory")),
+        }
+    }
+
+    fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.add_file(path, contents);
+        Ok(())
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        self.add_path(path);
+        Ok(())
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        let from = normalize(&self.current_dir.join(from));
+        match self.files.remove(&from) {
+            Some(contents) => {
+                self.paths.retain(|p| *p != from);
+                self.add_file(to, &contents);
+                Ok(())
+            },
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "No such file or directory")),
+        }
+    }
+
+    fn now(&self) -> SystemTime {
+        self.now
+    }
+
+    /// Time passes at once: `now` moves on by `duration`.
+    fn sleep(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+
+    /// The numbers come from SplitMix64.
+    fn random(&mut self) -> u64 {
+        self.random_state = self.random_state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.random_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Temporary directories are `/tmp/1`, `/tmp/2` and so on.
+    fn create_temp_dir(&mut self) -> io::Result<PathBuf> {
+        self.temp_dirs += 1;
+        let path = PathBuf::from(format!("/tmp/{}", self.temp_dirs));
+        self.paths.push(path.clone());
+        Ok(path)
+    }
+
+    fn remove_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        let path = try!(self.canonicalize(path));
+        self.paths.retain(|p| !p.starts_with(&path));
+        self.files.retain(|p, _| !p.starts_with(&path));
+        Ok(())
+    }
+}
+
+/// Removes `.` and `..` components from `path`, without looking at the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => { normalized.pop(); },
+            c => normalized.push(c.as_os_str()),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn virtual__set_current_dir_relative__joins() {
+        let mut ep = Virtual::new();
+
+        ep.set_current_dir(Path::new("/a")).unwrap();
+        ep.set_current_dir(Path::new("b")).unwrap();
+
+        assert_eq!(PathBuf::from("/a/b"), ep.current_dir().unwrap());
+    }
+
+    #[test]
+    fn virtual__set_current_dir_absolute__replaces() {
+        let mut ep = Virtual::new();
+
+        ep.set_current_dir(Path::new("/a")).unwrap();
+        ep.set_current_dir(Path::new("/c")).unwrap();
+
+        assert_eq!(PathBuf::from("/c"), ep.current_dir().unwrap());
+    }
+
+    #[test]
+    fn virtual__remove_dir_all__removes_contents_only() {
+        let mut ep = Virtual::new();
+        let dir = ep.create_temp_dir().unwrap();
+        ep.add_file(&dir.join("file.txt"), b"text");
+        ep.add_path(Path::new("/tmp/other"));
+
+        ep.remove_dir_all(&dir).unwrap();
+
+        assert!(ep.canonicalize(&dir).is_err());
+        assert!(ep.read_file(&dir.join("file.txt")).is_err());
+        assert!(ep.canonicalize(Path::new("/tmp/other")).is_ok());
+    }
+
+    #[test]
+    fn virtual__random__fixed_sequence_per_seed() {
+        let mut ep = Virtual::new();
+        let first: Vec<u64> = (0..3).map(|_| ep.random()).collect();
+        ep.set_random_seed(0);
+        let again: Vec<u64> = (0..3).map(|_| ep.random()).collect();
+        ep.set_random_seed(1);
+
+        assert_eq!(vec![0xe220a8397b1dcdaf, 0x6e789e6aa1b965f4, 0x06c45d188009454f], first);
+        assert_eq!(first, again);
+        assert!(ep.random() != first[0]);
+    }
+
+    #[test]
+    fn std__random__varies() {
+        let mut ep = Std::new();
+
+        assert!(ep.random() != ep.random());
+    }
+
+    #[test]
+    fn std__create_temp_dir__distinct_and_removable() {
+        let mut ep = Std::new();
+
+        let first = ep.create_temp_dir().unwrap();
+        let second = ep.create_temp_dir().unwrap();
+
+        assert!(first != second);
+        assert!(first.is_dir() && second.is_dir());
+        ep.remove_dir_all(&first).unwrap();
+        ep.remove_dir_all(&second).unwrap();
+        assert!(!first.exists() && !second.exists());
+    }
+
+    #[test]
+    fn virtual__canonicalize__only_added_paths_and_ancestors() {
+        let mut ep = Virtual::new();
+        ep.set_current_dir(Path::new("/a")).unwrap();
+        ep.add_path(Path::new("b/file.txt"));
+
+        assert_eq!(PathBuf::from("/a/b/file.txt"), ep.canonicalize(Path::new("./c/../b/file.txt")).unwrap());
+        assert_eq!(PathBuf::from("/a/b"), ep.canonicalize(Path::new("/a/b")).unwrap());
+        assert_eq!(PathBuf::from("/"), ep.canonicalize(Path::new("..")).unwrap());
+        assert!(ep.canonicalize(Path::new("b/other.txt")).is_err());
+    }
+
+    #[test]
+    fn virtual__read_file__only_added_files() {
+        let mut ep = Virtual::new();
+        ep.set_current_dir(Path::new("/a")).unwrap();
+        ep.add_file(Path::new("b/file.txt"), b"contents");
+
+        assert_eq!(b"contents".to_vec(), ep.read_file(Path::new("/a/b/file.txt")).unwrap());
+        assert_eq!(PathBuf::from("/a/b"), ep.canonicalize(Path::new("b")).unwrap());
+        assert!(ep.read_file(Path::new("b")).is_err());
+    }
+
+    #[test]
+    fn virtual__rename__moves_file() {
+        let mut ep = Virtual::new();
+        ep.add_file(Path::new("/a/state.tmp"), b"contents");
+
+        ep.rename(Path::new("/a/state.tmp"), Path::new("/a/state")).unwrap();
+
+        assert_eq!(b"contents".to_vec(), ep.read_file(Path::new("/a/state")).unwrap());
+        assert!(ep.read_file(Path::new("/a/state.tmp")).is_err());
+        assert!(ep.rename(Path::new("/a/state.tmp"), Path::new("/a/state")).is_err());
+    }
+
+    #[test]
+    fn virtual__vars__success() {
+        let mut ep = Virtual::new();
+
+        ep.set_var("A", "1");
+        ep.set_var("B", "2");
+        ep.remove_var("B");
+
+        assert_eq!(Some("1".to_string()), ep.var("A"));
+        assert_eq!(None, ep.var("B"));
+    }
+}
@@ -0,0 +1,112 @@
+//! Shell-style expansion of `~` and environment variables in argument values,
+//! for platforms (or invocations) where the shell doesn't do it.
+//!
+//! Set `Application::expand_paths` to `Some(expand::expand)` to apply it to
+//! `ParamKind::Path` parameters.
+
+use env;
+
+/// Expands a leading `~` (alone or followed by `/`) to `$HOME`, and `$VAR` and
+/// `${VAR}` to the value of the variable `VAR`.
+///
+/// References to unset variables, and a `~` when `$HOME` is unset, are left as
+/// they are, so that the user sees them in any resulting error.
+pub fn expand(value: &str, ep: &env::Provider) -> String {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+
+    if rest == "~" || rest.starts_with("~/") {
+        if let Some(home) = ep.var("HOME") {
+            expanded.push_str(&home);
+            rest = &rest[1..];
+        }
+    }
+
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let (name, len) = match variable_name(rest) {
+            Some(v) => v,
+            None => {
+                expanded.push('$');
+                rest = &rest[1..];
+                continue;
+            },
+        };
+
+        match ep.var(name) {
+            Some(v) => expanded.push_str(&v),
+            None => expanded.push_str(&rest[..len]),
+        }
+        rest = &rest[len..];
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Given text starting with `$`, returns the name of the variable it refers to
+/// and the length of the reference, if it is one.
+fn variable_name(s: &str) -> Option<(&str, usize)> {
+    let braced = s[1..].starts_with('{');
+    let start = if braced { 2 } else { 1 };
+    let len = s[start..]
+        .char_indices()
+        .take_while(|&(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()))
+        .count();
+
+    if len == 0 {
+        return None;
+    }
+
+    let name = &s[start..start + len];
+    if !braced {
+        Some((name, start + len))
+    } else if s[start + len..].starts_with('}') {
+        Some((name, start + len + 1))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use env;
+
+    fn test_env() -> env::Virtual {
+        let mut ep = env::Virtual::new();
+        ep.set_var("HOME", "/home/me");
+        ep.set_var("DIR", "data");
+        ep
+    }
+
+    #[test]
+    fn expand__tilde__home() {
+        let ep = test_env();
+
+        assert_eq!("/home/me", expand("~", &ep));
+        assert_eq!("/home/me/x", expand("~/x", &ep));
+        assert_eq!("~user/x", expand("~user/x", &ep));
+        assert_eq!("a/~", expand("a/~", &ep));
+    }
+
+    #[test]
+    fn expand__variables__substituted() {
+        let ep = test_env();
+
+        assert_eq!("/data/x", expand("/$DIR/x", &ep));
+        assert_eq!("/datax", expand("/${DIR}x", &ep));
+    }
+
+    #[test]
+    fn expand__unset_or_invalid__unchanged() {
+        let ep = test_env();
+
+        assert_eq!("$NOPE/x", expand("$NOPE/x", &ep));
+        assert_eq!("${DIR", expand("${DIR", &ep));
+        assert_eq!("cost: $5 $", expand("cost: $5 $", &ep));
+    }
+}
@@ -0,0 +1,179 @@
+//! Helpers for commands like `app env`, which print environment variable
+//! assignments for the user to `eval` in their shell.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # extern crate command_cli;
+//! use command_cli::{Arguments, CommandResult, Context};
+//! use command_cli::export::{self, Shell};
+//!
+//! // Handler for `app env [SHELL]`, used as `eval "$(app env)"`
+//! fn env_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+//!     let shell = match args["SHELL"].iter().next() {
+//!         Some(name) => match Shell::from_name(name) {
+//!             Some(shell) => shell,
+//!             None => return CommandResult::ArgumentError,
+//!         },
+//!         None => Shell::from_env(ctx.env()),
+//!     };
+//!
+//!     match export::print_exports(ctx, shell, &[("API_TOKEN", "s3cr3t"), ("API_REGION", "eu-west")]) {
+//!         Ok(()) => CommandResult::Success,
+//!         Err(message) => CommandResult::argument_error(message),
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+
+use std::path::Path;
+use io_providers::stream;
+use env;
+use quote;
+
+/// A shell whose syntax `export` lines can be printed in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shell {
+    /// POSIX-compatible shells such as sh, bash, zsh and dash.
+    Posix,
+    /// The fish shell.
+    Fish,
+    /// Windows PowerShell and PowerShell Core.
+    PowerShell,
+}
+
+impl Shell {
+    /// Looks up a shell by name (e.g. "bash", "fish" or "pwsh"), as a user would
+    /// pass it to a `--shell` option.
+    pub fn from_name(name: &str) -> Option<Shell> {
+        match name {
+            "sh" | "bash" | "zsh" | "dash" | "ksh" | "posix" => Some(Shell::Posix),
+            "fish" => Some(Shell::Fish),
+            "powershell" | "pwsh" => Some(Shell::PowerShell),
+            _ => None,
+        }
+    }
+
+    /// Guesses the user's shell from the `SHELL` environment variable, falling
+    /// back to `Posix` if it is unset or unrecognized.
+    pub fn from_env(ep: &env::Provider) -> Shell {
+        ep.var("SHELL")
+            .and_then(|path| {
+                Path::new(&path).file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(Shell::from_name)
+            })
+            .unwrap_or(Shell::Posix)
+    }
+
+    /// Returns a line which, when evaluated by this shell, exports `key` with the
+    /// value `value`, or an error if `key` isn't a valid variable name (letters,
+    /// digits and `_`, not starting with a digit), since it can't be quoted.
+    pub fn export_line(&self, key: &str, value: &str) -> Result<String, String> {
+        try!(check_key(key));
+        Ok(match *self {
+            Shell::Posix => format!("export {}={}", key, quote::posix(value)),
+            Shell::Fish => format!("set -gx {} {}", key, quote::fish(value)),
+            Shell::PowerShell => format!("$Env:{} = {}", key, quote::powershell(value)),
+        })
+    }
+}
+
+/// Prints an export line for each of `vars` to stdout. Nothing is printed if any
+/// of the keys is invalid; see `Shell::export_line`.
+pub fn print_exports(sp: &mut stream::Provider, shell: Shell, vars: &[(&str, &str)]) -> Result<(), String> {
+    let lines: Vec<String> = try!(vars.iter().map(|&(key, value)| shell.export_line(key, value)).collect());
+    for line in lines {
+        writeln!(sp.output(), "{}", line).unwrap();
+    }
+    Ok(())
+}
+
+fn check_key(key: &str) -> Result<(), String> {
+    let valid = key.chars().enumerate()
+        .all(|(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+    if valid && !key.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("'{}' isn't a valid environment variable name", key))
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use io_providers::stream;
+    use env;
+
+    #[test]
+    fn shell__from_name__known__success() {
+        assert_eq!(Some(Shell::Posix), Shell::from_name("bash"));
+        assert_eq!(Some(Shell::Fish), Shell::from_name("fish"));
+        assert_eq!(Some(Shell::PowerShell), Shell::from_name("pwsh"));
+    }
+
+    #[test]
+    fn shell__from_name__unknown__returns_none() {
+        assert_eq!(None, Shell::from_name("tcsh"));
+    }
+
+    #[test]
+    fn shell__export_line_posix__escapes_quotes() {
+        assert_eq!("export KEY='it'\\''s'", Shell::Posix.export_line("KEY", "it's").unwrap());
+    }
+
+    #[test]
+    fn shell__export_line_posix_plain__unquoted() {
+        assert_eq!("export KEY=value", Shell::Posix.export_line("KEY", "value").unwrap());
+    }
+
+    #[test]
+    fn shell__export_line_fish__escapes_quotes_and_backslashes() {
+        assert_eq!("set -gx KEY 'a\\\\b\\'c'", Shell::Fish.export_line("KEY", "a\\b'c").unwrap());
+    }
+
+    #[test]
+    fn shell__export_line_powershell__escapes_quotes() {
+        assert_eq!("$Env:KEY = 'it''s'", Shell::PowerShell.export_line("KEY", "it's").unwrap());
+    }
+
+    #[test]
+    fn shell__export_line_invalid_key__error() {
+        assert_eq!(
+            Err("'A;rm -rf ~' isn't a valid environment variable name".to_string()),
+            Shell::Posix.export_line("A;rm -rf ~", "1"));
+        assert!(Shell::Fish.export_line("1A", "1").is_err());
+        assert!(Shell::PowerShell.export_line("", "1").is_err());
+        assert!(Shell::Posix.export_line("_A1", "1").is_ok());
+    }
+
+    #[test]
+    fn shell__from_env__uses_shell_var() {
+        let mut ep = env::Virtual::new();
+        assert_eq!(Shell::Posix, Shell::from_env(&ep));
+
+        ep.set_var("SHELL", "/usr/bin/fish");
+        assert_eq!(Shell::Fish, Shell::from_env(&ep));
+    }
+
+    #[test]
+    fn print_exports__invalid_key__prints_nothing() {
+        let mut sp = stream::Virtual::new();
+
+        let result = print_exports(&mut sp, Shell::Posix, &[("A", "1"), ("B C", "2")]);
+
+        assert!(result.is_err());
+        assert_eq!(0, sp.read_output().len());
+    }
+
+    #[test]
+    fn print_exports__success() {
+        let mut sp = stream::Virtual::new();
+
+        print_exports(&mut sp, Shell::Posix, &[("A", "1"), ("B", "two words")]).unwrap();
+
+        assert_eq!("export A=1\nexport B='two words'\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!(0, sp.read_error().len());
+    }
+}
@@ -0,0 +1,96 @@
+//! Switches for the behaviors the framework adds to an application.
+//!
+//! Applications choose their features with `Application::features`, and users
+//! can turn individual ones off with environment variables named after the
+//! application and the feature, e.g. `MY_APP_NO_ROUTE=1`.
+
+use std::ops::BitOr;
+use env;
+
+/// A set of framework features.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FrameworkFeatures(u32);
+
+impl FrameworkFeatures {
+    pub const NONE: FrameworkFeatures = FrameworkFeatures(0);
+    /// The `help` built-in command.
+    pub const HELP_COMMAND: FrameworkFeatures = FrameworkFeatures(1);
+    /// The hidden `commands` built-in command.
+    pub const COMMANDS_COMMAND: FrameworkFeatures = FrameworkFeatures(1 << 1);
+    /// The hidden `__route` built-in command.
+    pub const ROUTE_COMMAND: FrameworkFeatures = FrameworkFeatures(1 << 2);
+    /// Decorations such as styled help and escape sequences in handler output;
+    /// see `Context::fancy`. Turning this off (or passing `--no-fancy`) is meant
+    /// for screen readers.
+    pub const FANCY_OUTPUT: FrameworkFeatures = FrameworkFeatures(1 << 3);
+    pub const ALL: FrameworkFeatures = FrameworkFeatures((1 << 4) - 1);
+
+    /// Returns whether every feature in `other` is in this set.
+    pub fn contains(self, other: FrameworkFeatures) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns this set without the features in `other`.
+    pub fn without(self, other: FrameworkFeatures) -> FrameworkFeatures {
+        FrameworkFeatures(self.0 & !other.0)
+    }
+
+    /// Returns this set without the features the user has turned off, by setting
+    /// `APP_NO_FEATURE` to anything other than an empty string or `0`. `APP` is
+    /// the application's name in upper case, with `-` replaced by `_`, and
+    /// `FEATURE` is `HELP`, `COMMANDS`, `ROUTE` or `FANCY`.
+    pub fn with_env_overrides(self, app_name: &str, ep: &env::Provider) -> FrameworkFeatures {
+        let prefix = app_name.to_uppercase().replace('-', "_");
+        NAMES.iter().fold(self, |features, &(feature, name)| {
+            match ep.var(&format!("{}_NO_{}", prefix, name)) {
+                Some(ref value) if !value.is_empty() && value != "0" => features.without(feature),
+                _ => features,
+            }
+        })
+    }
+}
+
+impl BitOr for FrameworkFeatures {
+    type Output = FrameworkFeatures;
+
+    fn bitor(self, other: FrameworkFeatures) -> FrameworkFeatures {
+        FrameworkFeatures(self.0 | other.0)
+    }
+}
+
+/// The name of each feature, as used in environment variables.
+const NAMES: &[(FrameworkFeatures, &str)] = &[
+    (FrameworkFeatures::HELP_COMMAND, "HELP"),
+    (FrameworkFeatures::COMMANDS_COMMAND, "COMMANDS"),
+    (FrameworkFeatures::ROUTE_COMMAND, "ROUTE"),
+    (FrameworkFeatures::FANCY_OUTPUT, "FANCY"),
+];
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use env;
+
+    #[test]
+    fn features__set_operations__success() {
+        let features = FrameworkFeatures::HELP_COMMAND | FrameworkFeatures::ROUTE_COMMAND;
+
+        assert!(features.contains(FrameworkFeatures::HELP_COMMAND));
+        assert!(!features.contains(FrameworkFeatures::COMMANDS_COMMAND));
+        assert!(FrameworkFeatures::ALL.contains(features));
+        assert_eq!(FrameworkFeatures::ROUTE_COMMAND, features.without(FrameworkFeatures::HELP_COMMAND));
+    }
+
+    #[test]
+    fn features__with_env_overrides__disables_set_vars() {
+        let mut ep = env::Virtual::new();
+        ep.set_var("MY_APP_NO_ROUTE", "1");
+        ep.set_var("MY_APP_NO_HELP", "0");
+
+        let result = FrameworkFeatures::ALL.with_env_overrides("my-app", &ep);
+
+        assert_eq!(FrameworkFeatures::HELP_COMMAND | FrameworkFeatures::COMMANDS_COMMAND | FrameworkFeatures::FANCY_OUTPUT,
+            result);
+    }
+}
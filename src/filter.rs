@@ -0,0 +1,418 @@
+//! Byte-level transformation of what handlers write to stdout and stderr.
+
+use std::io::{self, Read, Write};
+use io_providers::stream;
+
+/// Transforms bytes written to a stream.
+pub trait Filter {
+    /// Transforms `buf`, appending the result to `out`.
+    ///
+    /// A filter may hold back the end of `buf` (e.g. an incomplete escape sequence)
+    /// until it sees more bytes or is finished.
+    fn filter(&mut self, buf: &[u8], out: &mut Vec<u8>);
+
+    /// Appends any held-back bytes to `out`; called once writing is done.
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        let _ = out;
+    }
+}
+
+/// How what's written to stdout is held back before being written out, to
+/// avoid many small writes to the underlying stream. Held-back output is
+/// written when the stream is flushed, before stdin is read, and when writing
+/// is done.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Buffering {
+    /// Output is written as soon as it's filtered.
+    Off,
+    /// Only complete lines are written.
+    Line,
+    /// Output is written in blocks of at least `BLOCK_SIZE` bytes.
+    Block,
+    /// `Line` if stdout is a terminal, and `Block` otherwise; see `Buffering::resolve`.
+    Auto,
+}
+
+/// The number of bytes held back by `Buffering::Block`.
+pub const BLOCK_SIZE: usize = 8192;
+
+impl Buffering {
+    /// Returns the buffering to use, given whether stdout is a terminal.
+    pub fn resolve(self, is_terminal: bool) -> Buffering {
+        match self {
+            Buffering::Auto if is_terminal => Buffering::Line,
+            Buffering::Auto => Buffering::Block,
+            buffering => buffering,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Output,
+    Error,
+}
+
+/// A stream provider which passes stdout and stderr through chains of `Filter`s
+/// before writing them to an inner provider, and likewise stdin after reading it.
+pub struct Filtered<'a> {
+    writer: FilteredWriter<'a>,
+    reader: FilteredReader,
+}
+
+struct FilteredWriter<'a> {
+    inner: &'a mut stream::Provider,
+    target: Target,
+    output_filters: Vec<Box<Filter>>,
+    error_filters: Vec<Box<Filter>>,
+    buffering: Buffering,
+    /// Filtered stdout bytes held back by `buffering`.
+    pending: Vec<u8>,
+}
+
+/// Reads from the `FilteredWriter`'s inner provider; kept separate so that both can be borrowed at once.
+struct FilteredReader {
+    filters: Vec<Box<Filter>>,
+    /// Filtered bytes which haven't been read yet.
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<'a> Filtered<'a> {
+    /// Constructs a `Filtered` provider with no filters, wrapping `inner`.
+    pub fn new(inner: &'a mut stream::Provider) -> Filtered<'a> {
+        Filtered {
+            writer: FilteredWriter {
+                inner,
+                target: Target::Output,
+                output_filters: Vec::new(),
+                error_filters: Vec::new(),
+                buffering: Buffering::Off,
+                pending: Vec::new(),
+            },
+            reader: FilteredReader { filters: Vec::new(), buf: Vec::new(), eof: false },
+        }
+    }
+
+    /// Appends a filter to the chain applied to stdin.
+    pub fn add_input_filter(&mut self, filter: Box<Filter>) {
+        self.reader.filters.push(filter);
+    }
+
+    /// Appends a filter to the chain applied to stdout.
+    pub fn add_output_filter(&mut self, filter: Box<Filter>) {
+        self.writer.output_filters.push(filter);
+    }
+
+    /// Appends a filter to the chain applied to stderr.
+    pub fn add_error_filter(&mut self, filter: Box<Filter>) {
+        self.writer.error_filters.push(filter);
+    }
+
+    /// Sets how stdout is buffered; `Buffering::Auto` is treated as `Block`
+    /// unless resolved first.
+    pub fn set_output_buffering(&mut self, buffering: Buffering) {
+        self.writer.buffering = buffering;
+    }
+
+    /// Gets the wrapped provider, e.g. to write bytes which shouldn't be filtered.
+    /// Call `finish` first, so that bytes held back by the filters come before them.
+    pub fn inner(&mut self) -> &mut stream::Provider {
+        &mut *self.writer.inner
+    }
+
+    /// Writes out any bytes held back by the filters. Also done when dropped, ignoring errors.
+    pub fn finish(&mut self) -> io::Result<()> {
+        try!(self.writer.finish(Target::Output));
+        self.writer.finish(Target::Error)
+    }
+}
+
+impl<'a> Drop for Filtered<'a> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+impl<'a> stream::Provider for Filtered<'a> {
+    fn input(&mut self) -> &mut Read {
+        // Whatever was written is likely a prompt for what's about to be read
+        let _ = self.writer.write_pending();
+        if self.reader.filters.is_empty() {
+            return self.writer.inner.input();
+        }
+
+        self
+    }
+
+    fn output(&mut self) -> &mut Write {
+        self.writer.target = Target::Output;
+        &mut self.writer
+    }
+
+    fn error(&mut self) -> &mut Write {
+        self.writer.target = Target::Error;
+        &mut self.writer
+    }
+}
+
+impl<'a> Read for Filtered<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let reader = &mut self.reader;
+        while reader.buf.is_empty() && !reader.eof {
+            let mut raw = [0u8; 4096];
+            let len = try!(self.writer.inner.input().read(&mut raw));
+            reader.eof = len == 0;
+
+            let mut data = raw[..len].to_vec();
+            for filter in reader.filters.iter_mut() {
+                let mut out = Vec::with_capacity(data.len());
+                filter.filter(&data, &mut out);
+                if reader.eof {
+                    filter.finish(&mut out);
+                }
+                data = out;
+            }
+            reader.buf = data;
+        }
+
+        let len = ::std::cmp::min(buf.len(), reader.buf.len());
+        buf[..len].copy_from_slice(&reader.buf[..len]);
+        reader.buf.drain(..len);
+        Ok(len)
+    }
+}
+
+impl<'a> FilteredWriter<'a> {
+    fn finish(&mut self, target: Target) -> io::Result<()> {
+        let data = {
+            let filters = match target {
+                Target::Output => &mut self.output_filters,
+                Target::Error => &mut self.error_filters,
+            };
+
+            // Held-back bytes from each filter still need to pass through the ones after it
+            let mut data = Vec::new();
+            for filter in filters.iter_mut() {
+                let mut out = Vec::new();
+                filter.filter(&data, &mut out);
+                filter.finish(&mut out);
+                data = out;
+            }
+            data
+        };
+
+        try!(self.write_inner(target, &data));
+        match target {
+            Target::Output => self.write_pending(),
+            Target::Error => Ok(()),
+        }
+    }
+
+    fn write_inner(&mut self, target: Target, data: &[u8]) -> io::Result<()> {
+        if let Target::Error = target {
+            return if data.is_empty() { Ok(()) } else { self.inner.error().write_all(data) };
+        }
+
+        self.pending.extend_from_slice(data);
+        let len = match self.buffering {
+            Buffering::Off => self.pending.len(),
+            Buffering::Line => self.pending.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1),
+            Buffering::Block | Buffering::Auto if self.pending.len() >= BLOCK_SIZE => self.pending.len(),
+            Buffering::Block | Buffering::Auto => 0,
+        };
+        if len == 0 {
+            return Ok(());
+        }
+
+        let result = self.inner.output().write_all(&self.pending[..len]);
+        self.pending.drain(..len);
+        result
+    }
+
+    /// Writes out all of the stdout bytes held back by buffering.
+    fn write_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let result = self.inner.output().write_all(&self.pending);
+        self.pending.clear();
+        result
+    }
+}
+
+impl<'a> Write for FilteredWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let target = self.target;
+        let data = {
+            let filters = match target {
+                Target::Output => &mut self.output_filters,
+                Target::Error => &mut self.error_filters,
+            };
+
+            let mut data = buf.to_vec();
+            for filter in filters.iter_mut() {
+                let mut out = Vec::with_capacity(data.len());
+                filter.filter(&data, &mut out);
+                data = out;
+            }
+            data
+        };
+
+        try!(self.write_inner(target, &data));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.target {
+            Target::Output => {
+                try!(self.write_pending());
+                self.inner.output().flush()
+            },
+            Target::Error => self.inner.error().flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use io_providers::stream::{self, Provider};
+
+    /// Uppercases ASCII, holding back a trailing '!' until the next write.
+    struct Shout {
+        held: bool,
+    }
+
+    impl Filter for Shout {
+        fn filter(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+            if self.held {
+                out.push(b'!');
+                self.held = false;
+            }
+
+            let (body, held) = match buf.last() {
+                Some(&b'!') => (&buf[..buf.len() - 1], true),
+                _ => (buf, false),
+            };
+            out.extend(body.iter().map(|b| b.to_ascii_uppercase()));
+            self.held = held;
+        }
+
+        fn finish(&mut self, out: &mut Vec<u8>) {
+            if self.held {
+                out.extend(b"!!");
+                self.held = false;
+            }
+        }
+    }
+
+    #[test]
+    fn filtered__output_filter__applies_to_output_only() {
+        let mut sp = stream::Virtual::new();
+        {
+            let mut filtered = Filtered::new(&mut sp);
+            filtered.add_output_filter(Box::new(Shout { held: false }));
+
+            write!(filtered.output(), "out").unwrap();
+            write!(filtered.error(), "err").unwrap();
+        }
+
+        assert_eq!("OUT", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!("err", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn filtered__input_filter__applies_to_input() {
+        let mut sp = stream::Virtual::new();
+        sp.write_input(b"abc!");
+        let mut filtered = Filtered::new(&mut sp);
+        filtered.add_input_filter(Box::new(Shout { held: false }));
+
+        let mut input = String::new();
+        filtered.input().read_to_string(&mut input).unwrap();
+
+        assert_eq!("ABC!", input);
+    }
+
+    #[test]
+    fn filtered__line_buffering__complete_lines_until_flushed() {
+        let mut sp = stream::Virtual::new();
+        {
+            let mut filtered = Filtered::new(&mut sp);
+            filtered.set_output_buffering(Buffering::Line);
+
+            write!(filtered.output(), "a\nb").unwrap();
+            assert_eq!(b"b", &filtered.writer.pending[..]);
+            write!(filtered.output(), "c\nd\ne").unwrap();
+            assert_eq!(b"e", &filtered.writer.pending[..]);
+            filtered.output().flush().unwrap();
+            assert!(filtered.writer.pending.is_empty());
+        }
+
+        assert_eq!("a\nbc\nd\ne", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn filtered__block_buffering__blocks_then_rest_when_finished() {
+        let mut sp = stream::Virtual::new();
+        {
+            let mut filtered = Filtered::new(&mut sp);
+            filtered.set_output_buffering(Buffering::Block);
+
+            filtered.output().write_all(&[b'x'; BLOCK_SIZE - 1]).unwrap();
+            write!(filtered.error(), "progress").unwrap();
+            assert_eq!(BLOCK_SIZE - 1, filtered.writer.pending.len());
+            filtered.output().write_all(b"yz").unwrap();
+            assert!(filtered.writer.pending.is_empty());
+            filtered.output().write_all(b"!").unwrap();
+            assert_eq!(1, filtered.writer.pending.len());
+        }
+
+        assert_eq!(BLOCK_SIZE + 2, sp.read_output().len());
+        assert_eq!(b'!', *sp.read_output().last().unwrap());
+        assert_eq!(b"progress", sp.read_error());
+    }
+
+    #[test]
+    fn filtered__buffering_then_input__prompt_written_first() {
+        let mut sp = stream::Virtual::new();
+        sp.write_input(b"y\n");
+        {
+            let mut filtered = Filtered::new(&mut sp);
+            filtered.set_output_buffering(Buffering::Line);
+
+            write!(filtered.output(), "Continue? ").unwrap();
+            let mut answer = String::new();
+            filtered.input().read_to_string(&mut answer).unwrap();
+
+            assert!(filtered.writer.pending.is_empty());
+        }
+
+        assert_eq!(b"Continue? ", sp.read_output());
+    }
+
+    #[test]
+    fn buffering__resolve__auto_by_terminal() {
+        assert_eq!(Buffering::Line, Buffering::Auto.resolve(true));
+        assert_eq!(Buffering::Block, Buffering::Auto.resolve(false));
+        assert_eq!(Buffering::Off, Buffering::Off.resolve(true));
+    }
+
+    #[test]
+    fn filtered__drop__finishes_filters_in_order() {
+        let mut sp = stream::Virtual::new();
+        {
+            let mut filtered = Filtered::new(&mut sp);
+            filtered.add_error_filter(Box::new(Shout { held: false }));
+            filtered.add_error_filter(Box::new(Shout { held: false }));
+
+            write!(filtered.error(), "a!").unwrap();
+        }
+
+        // The first filter's held-back '!' reaches the second, which holds it back again until finished
+        assert_eq!("A!!", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+}
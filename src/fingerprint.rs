@@ -0,0 +1,129 @@
+//! Fingerprinting of an application's command-line interface, so that
+//! downstream tests can catch unintended changes to it:
+//!
+//! ```no_run
+//! # extern crate command_cli;
+//! # use command_cli::{Application, StaticApplication};
+//! # const APP: StaticApplication = Application { name: "app", ..Application::DEFAULT };
+//! # fn main() {
+//! // Update this whenever the CLI is changed on purpose
+//! assert_eq!("9cc1cb1d4ec06a4d", APP.fingerprint());
+//! # }
+//! ```
+
+use std::fmt::Write;
+use super::{Application, Parameter};
+use super::{ARGUMENT_ERROR_EXIT_CODE, EXECUTION_ERROR_EXIT_CODE, SUCCESS_EXIT_CODE};
+
+impl<'c, 'p> Application<'c, 'p> {
+    /// Returns a hash of everything about the application which callers can
+    /// depend on: its name, commands, parameters, global options and exit codes.
+    ///
+    /// Descriptions and help text aren't included. The hash is stable across
+    /// runs, platforms and versions of this crate, as long as the interface's
+    /// features stay the same.
+    pub fn fingerprint(&self) -> String {
+        format!("{:016x}", fnv1a(self.surface().as_bytes()))
+    }
+
+    /// Describes the interface, one item per line.
+    fn surface(&self) -> String {
+        let mut s = String::new();
+        writeln!(s, "app {}", self.name).unwrap();
+        writeln!(s, "exit-codes {} {} {} {} {}",
+            SUCCESS_EXIT_CODE, ARGUMENT_ERROR_EXIT_CODE, EXECUTION_ERROR_EXIT_CODE,
+            self.partial_failure_exit_code, self.panic_exit_code).unwrap();
+        writeln!(s, "reserved-names {:?}", self.reserved_names).unwrap();
+        for &&(option, _) in &self.global_options() {
+            writeln!(s, "option {}", option).unwrap();
+        }
+
+        if let Some(var) = self.opts_var {
+            writeln!(s, "opts-var {}", var).unwrap();
+        }
+
+        if self.confirm_mutating {
+            writeln!(s, "confirm-mutating").unwrap();
+        }
+
+        for &(old, new) in self.renamed_commands {
+            writeln!(s, "renamed {} {}", old, new).unwrap();
+        }
+
+        for cmd in self.commands {
+            writeln!(s, "command {}", cmd.name).unwrap();
+            if let Some(name) = cmd.stdin_param {
+                writeln!(s, "stdin-param {}", name).unwrap();
+            }
+            if cmd.reads_stdin {
+                writeln!(s, "reads-stdin").unwrap();
+            }
+            if cmd.mutates {
+                writeln!(s, "mutates").unwrap();
+            }
+            if cmd.requires_network {
+                writeln!(s, "requires-network").unwrap();
+            }
+            if let Some(cooldown) = cmd.cooldown {
+                writeln!(s, "cooldown {:?}", cooldown).unwrap();
+            }
+            for param in cmd.all_params() {
+                write_param(&mut s, param);
+            }
+            for form in cmd.forms {
+                writeln!(s, "form").unwrap();
+                for param in form.iter() {
+                    write_param(&mut s, param);
+                }
+            }
+        }
+
+        s
+    }
+}
+
+fn write_param(s: &mut String, param: &Parameter) {
+    writeln!(s, "param {} required={} repeating={} kind={:?} delimiter={:?} path-policy={:?} url-schemes={:?} \
+        range={:?} float-range={:?} allow-non-finite={} indirect={}",
+        param.name, param.required, param.repeating, param.kind, param.value_delimiter, param.path_policy,
+        param.url_schemes, param.range, param.float_range.map(|r| (r.min, r.max)),
+        param.allow_non_finite, param.indirect).unwrap();
+}
+
+/// The 64-bit FNV-1a hash, which unlike `std`'s hashers is guaranteed not to change.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        hash = (hash ^ b as u64).wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use {Command, Parameter};
+
+    #[test]
+    fn fnv1a__known_values__success() {
+        assert_eq!(0xcbf29ce484222325, fnv1a(b""));
+        assert_eq!(0xaf63dc4c8601ec8c, fnv1a(b"a"));
+    }
+
+    #[test]
+    fn application__fingerprint__changes_with_interface_only() {
+        let params = [Parameter { name: "FILE", ..Parameter::DEFAULT }];
+        let renamed = [Parameter { name: "PATH", ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "cmd", short_desc: "desc", params: &params, ..Command::DEFAULT }];
+        let redescribed = [Command { name: "cmd", short_desc: "other", params: &params, ..Command::DEFAULT }];
+        let changed = [Command { name: "cmd", short_desc: "desc", params: &renamed, ..Command::DEFAULT }];
+        let app = |cmds| Application { name: "app", commands: cmds, ..Application::DEFAULT };
+
+        let fingerprint = app(&cmds).fingerprint();
+
+        assert_eq!(16, fingerprint.len());
+        assert_eq!(fingerprint, app(&redescribed).fingerprint());
+        assert!(fingerprint != app(&changed).fingerprint());
+    }
+}
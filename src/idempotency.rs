@@ -0,0 +1,122 @@
+//! Skipping operations which are already done, so that re-running a command
+//! after e.g. a partial failure of a script doesn't repeat its work.
+//!
+//! A handler derives a key from its arguments with `key`, and does its work
+//! inside `run`, which remembers in the application's state (see
+//! `Context::state`) each key whose work succeeded:
+//!
+//! ```
+//! # extern crate command_cli;
+//! use std::io::Write;
+//! use command_cli::{Arguments, CommandResult, Context};
+//! use command_cli::idempotency;
+//!
+//! fn migrate_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+//!     let key = idempotency::key("migrate", args);
+//!     let force = args.count("--force") > 0;
+//!     idempotency::run(ctx, &key, force, |ctx| {
+//!         writeln!(ctx.output(), "Migrating {}", args["DATABASE"][0]).unwrap();
+//!         CommandResult::Success
+//!     })
+//! }
+//! # fn main() {}
+//! ```
+
+use std::time::UNIX_EPOCH;
+use fingerprint::fnv1a;
+use {Arguments, CommandResult, Context};
+
+/// Returns a key identifying the operation of running the command `cmd_name`
+/// with `args`. It's the same for the same values, in any order between
+/// parameters, but not within one.
+pub fn key(cmd_name: &str, args: &Arguments) -> String {
+    let mut names: Vec<&String> = args.param_to_args.keys().collect();
+    names.sort();
+
+    let mut s = cmd_name.to_string();
+    for name in names {
+        s.push('\0');
+        s.push_str(name);
+        for value in &args.param_to_args[name] {
+            s.push('\x01');
+            s.push_str(value);
+        }
+    }
+    format!("{:016x}", fnv1a(s.as_bytes()))
+}
+
+/// Runs `f` unless the operation identified by `key` already succeeded, in
+/// which case that's said on stderr and the result is `Success`. With `force`,
+/// `f` is run regardless. A `Success` from `f` is recorded, but other results
+/// aren't, so that the operation can be tried again.
+pub fn run<F>(ctx: &mut Context, key: &str, force: bool, f: F) -> CommandResult
+    where F: FnOnce(&mut Context) -> CommandResult
+{
+    let state_key = format!("done.{}", key);
+    let done = match ctx.state() {
+        Ok(state) => state.get(&state_key).is_some(),
+        Err(e) => {
+            writeln!(ctx.error(), "Error: Unable to load state: {}", e).unwrap();
+            return CommandResult::ExecutionError(None);
+        },
+    };
+    if done && !force {
+        writeln!(ctx.error(), "Already done (use --force to repeat)").unwrap();
+        return CommandResult::Success;
+    }
+
+    let result = f(ctx);
+    if let CommandResult::Success = result {
+        let now = ctx.env().now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        if let Err(e) = ctx.set_state(&state_key, &now.as_secs().to_string()) {
+            ctx.warn(&format!("Unable to record that the operation is done: {}", e));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use io_providers::stream;
+    use {env, Parameter};
+
+    const PARAMS: &[Parameter] = &[
+        Parameter { name: "FROM", required: true, ..Parameter::DEFAULT },
+        Parameter { name: "TO", required: true, ..Parameter::DEFAULT },
+    ];
+
+    fn arguments(values: &[&str]) -> Arguments {
+        let args = ["app", "cmd"].iter().chain(values).map(|s| s.to_string()).collect();
+        Arguments::new(PARAMS.iter(), args).unwrap()
+    }
+
+    #[test]
+    fn key__values__differ_by_value_and_position() {
+        assert_eq!(key("copy", &arguments(&["a", "b"])), key("copy", &arguments(&["a", "b"])));
+        assert!(key("copy", &arguments(&["a", "b"])) != key("copy", &arguments(&["b", "a"])));
+        assert!(key("copy", &arguments(&["a", "b"])) != key("move", &arguments(&["a", "b"])));
+    }
+
+    #[test]
+    fn run__repeated__skipped_unless_forced() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        {
+            let mut ctx = Context::new(&mut sp, &mut ep);
+            let attempt = |ctx: &mut Context, force: bool, result: CommandResult| run(ctx, "k", force, |ctx| {
+                writeln!(ctx.output(), "ran").unwrap();
+                result
+            });
+
+            assert!(matches!(attempt(&mut ctx, false, CommandResult::ExecutionError(None)), CommandResult::ExecutionError(None)));
+            assert!(matches!(attempt(&mut ctx, false, CommandResult::Success), CommandResult::Success));
+            assert!(matches!(attempt(&mut ctx, false, CommandResult::Success), CommandResult::Success));
+            assert!(matches!(attempt(&mut ctx, true, CommandResult::Success), CommandResult::Success));
+        }
+
+        assert_eq!("ran\nran\nran\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!("Already done (use --force to repeat)\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+}
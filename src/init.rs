@@ -0,0 +1,121 @@
+//! A standard `init` command, which starts a project off with a template
+//! config file.
+//!
+//! The application supplies the file's name and contents, and what to do next,
+//! and calls `Template::run` from the handler of an `init` command taking
+//! `PARAMS`:
+//!
+//! ```
+//! # extern crate command_cli;
+//! use command_cli::{Arguments, Command, CommandResult, Context};
+//! use command_cli::init::{self, Template};
+//!
+//! const TEMPLATE: Template = Template {
+//!     path: "app.conf",
+//!     contents: "# Settings for app\nserver = localhost\n",
+//!     next_steps: "Set 'server' in app.conf, then run 'app sync'.",
+//! };
+//!
+//! fn init_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+//!     TEMPLATE.run(ctx, args)
+//! }
+//!
+//! const INIT: Command<'static> = Command {
+//!     name: "init",
+//!     short_desc: "writes a template app.conf",
+//!     params: init::PARAMS,
+//!     handler: init_handler,
+//!     mutates: true,
+//!     ..Command::DEFAULT
+//! };
+//! # fn main() {
+//! # assert_eq!("Usage: app init [--force]", INIT.usage_string("app"));
+//! # }
+//! ```
+
+use std::io;
+use std::path::Path;
+use {Arguments, CommandResult, Context, Parameter};
+
+/// The name of the parameter which allows an existing file to be overwritten.
+pub const FORCE: &str = "--force";
+
+/// The parameters of an `init` command: just an optional `--force`.
+pub const PARAMS: &[Parameter] = &[Parameter { name: FORCE, ..Parameter::DEFAULT }];
+
+/// A config file written by an `init` command.
+#[derive(Clone, Copy, Debug)]
+pub struct Template {
+    /// Where the file is written, relative to the working directory.
+    pub path: &'static str,
+    pub contents: &'static str,
+    /// Printed once the file is written, to tell the user what to do next.
+    pub next_steps: &'static str,
+}
+
+impl Template {
+    /// Writes the file, unless it already exists and `--force` wasn't given,
+    /// and prints the next steps to stdout.
+    pub fn run(&self, ctx: &mut Context, args: &Arguments) -> CommandResult {
+        let force = match args.count(FORCE) {
+            0 => false,
+            _ if args[FORCE][0] == FORCE => true,
+            _ => return CommandResult::ArgumentErrorWithMessage(
+                format!("Unrecognized argument '{}'", args[FORCE][0])),
+        };
+
+        let path = Path::new(self.path);
+        match ctx.env().read_file(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            _ if force => (),
+            _ => {
+                writeln!(ctx.error(), "Error: '{}' already exists; pass {} to overwrite it", self.path, FORCE).unwrap();
+                return CommandResult::ExecutionError(None);
+            },
+        }
+
+        if let Err(e) = ctx.write_file(path, self.contents.as_bytes()) {
+            writeln!(ctx.error(), "Error: Unable to write '{}': {}", self.path, e).unwrap();
+            return CommandResult::ExecutionError(None);
+        }
+
+        writeln!(ctx.output(), "Wrote {}\n\nNext steps:\n{}", self.path, self.next_steps).unwrap();
+        CommandResult::Success
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use io_providers::stream;
+    use env::{self, Provider};
+    use {Application, Command};
+
+    const TEMPLATE: Template = Template { path: "app.conf", contents: "server = localhost\n", next_steps: "Run 'app sync'." };
+
+    fn init_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        TEMPLATE.run(ctx, args)
+    }
+
+    #[test]
+    fn template__run__writes_unless_exists() {
+        let cmds = [Command { name: "init", params: PARAMS, handler: init_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let mut ep = env::Virtual::new();
+        let mut run = |args: &[&str]| {
+            let mut sp = stream::Virtual::new();
+            let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, args.iter().map(|s| s.to_string()).collect());
+            (exit_code, String::from_utf8(sp.read_output().to_vec()).unwrap(), String::from_utf8(sp.read_error().to_vec()).unwrap())
+        };
+
+        assert_eq!((0, "Wrote app.conf\n\nNext steps:\nRun 'app sync'.\n".to_string(), String::new()), run(&["app", "init"]));
+        assert_eq!((2, String::new(), "Error: 'app.conf' already exists; pass --force to overwrite it\n".to_string()),
+            run(&["app", "init"]));
+        assert_eq!(0, run(&["app", "init", "--force"]).0);
+        assert_eq!((1, String::new(), "Error: Unrecognized argument '--forse'\nUsage: app init [--force]\n".to_string()),
+            run(&["app", "init", "--forse"]));
+        assert_eq!(b"server = localhost\n".to_vec(), ep.read_file(Path::new("app.conf")).unwrap());
+    }
+}
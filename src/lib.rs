@@ -11,7 +11,7 @@
 //! use std::env;
 //! use std::io::Write;
 //! use std::process;
-//! use command_cli::{Application, Arguments, Command, CommandResult, Parameter, StaticApplication};
+//! use command_cli::{Application, Arguments, Command, CommandResult, Context, Parameter, StaticApplication};
 //! use io_providers::stream;
 //! 
 //! const APP: StaticApplication = Application {
@@ -25,14 +25,17 @@
 //!                     name: "FOO",
 //!                     required: true,
 //!                     repeating: false,
+//!                     ..Parameter::DEFAULT
 //!                 },
 //!                 Parameter {
 //!                     name: "BAR",
 //!                     required: true,
 //!                     repeating: true,
+//!                     ..Parameter::DEFAULT
 //!                 },
 //!             ],
 //!             handler: cmd1_handler,
+//!             ..Command::DEFAULT
 //!         },
 //!         Command {
 //!             name: "cmd2",
@@ -42,9 +45,11 @@
 //!                     name: "THING",
 //!                     required: false,
 //!                     repeating: false,
+//!                     ..Parameter::DEFAULT
 //!                 },
 //!             ],
 //!             handler: cmd2_handler,
+//!             ..Command::DEFAULT
 //!         },
 //!         Command {
 //!             name: "cmd3",
@@ -54,27 +59,30 @@
 //!                     name: "FILE",
 //!                     required: false,
 //!                     repeating: true,
+//!                     ..Parameter::DEFAULT
 //!                 },
 //!             ],
 //!             handler: cmd3_handler,
+//!             ..Command::DEFAULT
 //!         },
 //!     ],
+//!     ..Application::DEFAULT
 //! };
 //! 
-//! fn cmd1_handler(sp: &mut stream::Provider, args: &Arguments) -> CommandResult {
+//! fn cmd1_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
 //!     let foo: &String = &args["FOO"][0];
 //!     let bars: &Vec<String> = &args["BAR"];
-//!     let home_dir = cmd_expect!(sp, env::home_dir(), "Error: Unable to get home directory");
+//!     let home_dir = cmd_expect!(ctx, env::home_dir(), "Error: Unable to get home directory");
 //!     CommandResult::Success
 //! }
 //! 
-//! fn cmd2_handler(sp: &mut stream::Provider, args: &Arguments) -> CommandResult {
+//! fn cmd2_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
 //!     let thing: Option<&String> = args["THING"].iter().next();
-//!     let var = cmd_try!(sp, env::var("ENV_VAR"), "Error: Unable to get 'ENV_VAR' environment variable");
+//!     let var = cmd_try!(ctx, env::var("ENV_VAR"), "Error: Unable to get 'ENV_VAR' environment variable");
 //!     CommandResult::ArgumentError
 //! }
 //! 
-//! fn cmd3_handler(sp: &mut stream::Provider, args: &Arguments) -> CommandResult {
+//! fn cmd3_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
 //!     CommandResult::ExecutionError(None)
 //! }
 //! 
@@ -114,22 +122,177 @@ macro_rules! cmd_expect {
     }
 }
 
+/// Builds the standard commands for a kind of object the application manages,
+/// given the object's name, a `ParamGroup` describing its fields and a handler
+/// for each of `list`, `get`, `create`, `update` and `delete` wanted. These become
+/// `list-NOUN`, `get-NOUN ID`, `create-NOUN FIELDS...`, `update-NOUN ID FIELDS...`
+/// and `delete-NOUN ID`, with matching descriptions and `verb` and `noun` set;
+/// the last three are marked as `mutates`.
+///
+/// This expands to an array, which can be combined with other commands at run
+/// time if need be:
+///
+/// ```
+/// # #[macro_use] extern crate command_cli;
+/// # use command_cli::{Arguments, Command, CommandResult, Context, ParamGroup, Parameter};
+/// const HOST_FIELDS: ParamGroup = ParamGroup {
+///     params: &[Parameter { name: "ADDRESS", required: true, ..Parameter::DEFAULT }],
+/// };
+/// const HOST_COMMANDS: [Command<'static>; 3] = resource_commands!("host", HOST_FIELDS, {
+///     list: list_hosts,
+///     create: create_host,
+///     delete: delete_host,
+/// });
+///
+/// # fn list_hosts(_: &mut Context, _: &Arguments) -> CommandResult { CommandResult::Success }
+/// # fn create_host(_: &mut Context, _: &Arguments) -> CommandResult { CommandResult::Success }
+/// # fn delete_host(_: &mut Context, _: &Arguments) -> CommandResult { CommandResult::Success }
+/// # fn main() {
+/// assert_eq!("Usage: app create-host ADDRESS", HOST_COMMANDS[1].usage_string("app"));
+/// # }
+/// ```
+#[macro_export]
+macro_rules! resource_commands {
+    ($noun:tt, $fields:expr, { $($verb:ident: $handler:expr),* $(,)* }) => {
+        [$(resource_commands!(@command $verb, $noun, $fields, $handler)),*]
+    };
+    (@command list, $noun:tt, $fields:expr, $handler:expr) => {
+        $crate::Command {
+            name: concat!("list-", $noun),
+            short_desc: concat!("lists every ", $noun),
+            handler: $handler,
+            verb: Some("list"),
+            noun: Some($noun),
+            ..$crate::Command::DEFAULT
+        }
+    };
+    (@command get, $noun:tt, $fields:expr, $handler:expr) => {
+        $crate::Command {
+            name: concat!("get-", $noun),
+            short_desc: concat!("shows the ", $noun, " with the given ID"),
+            params: &[$crate::Parameter { name: "ID", required: true, ..$crate::Parameter::DEFAULT }],
+            handler: $handler,
+            verb: Some("get"),
+            noun: Some($noun),
+            ..$crate::Command::DEFAULT
+        }
+    };
+    (@command create, $noun:tt, $fields:expr, $handler:expr) => {
+        $crate::Command {
+            name: concat!("create-", $noun),
+            short_desc: concat!("creates a ", $noun),
+            params_from: &[&$fields],
+            handler: $handler,
+            verb: Some("create"),
+            noun: Some($noun),
+            mutates: true,
+            ..$crate::Command::DEFAULT
+        }
+    };
+    (@command update, $noun:tt, $fields:expr, $handler:expr) => {
+        $crate::Command {
+            name: concat!("update-", $noun),
+            short_desc: concat!("changes the ", $noun, " with the given ID"),
+            params: &[$crate::Parameter { name: "ID", required: true, ..$crate::Parameter::DEFAULT }],
+            params_from: &[&$fields],
+            handler: $handler,
+            verb: Some("update"),
+            noun: Some($noun),
+            mutates: true,
+            ..$crate::Command::DEFAULT
+        }
+    };
+    (@command delete, $noun:tt, $fields:expr, $handler:expr) => {
+        $crate::Command {
+            name: concat!("delete-", $noun),
+            short_desc: concat!("deletes the ", $noun, " with the given ID"),
+            params: &[$crate::Parameter { name: "ID", required: true, ..$crate::Parameter::DEFAULT }],
+            handler: $handler,
+            verb: Some("delete"),
+            noun: Some($noun),
+            mutates: true,
+            ..$crate::Command::DEFAULT
+        }
+    };
+}
+
 extern crate io_providers;
 
+use std::any::Any;
 use std::borrow::Borrow;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::error;
 use std::fmt;
-use std::hash::Hash;
-use std::io::Write;
+use std::hash::{Hash, Hasher};
+use std::io;
 use std::iter::IntoIterator;
 use std::ops::Index;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use io_providers::stream;
+use features::FrameworkFeatures;
+
+pub use context::Context;
+
+pub mod batch;
+mod builtin;
+pub mod concurrent;
+mod context;
+pub mod datetime;
+pub mod doctor;
+pub mod encoding;
+pub mod env;
+pub mod expand;
+pub mod export;
+pub mod features;
+pub mod filter;
+mod fingerprint;
+pub mod idempotency;
+pub mod init;
+pub mod lines;
+pub mod locale;
+mod markup;
+pub mod output;
+pub mod quote;
+pub mod sanitize;
+pub mod state;
+mod stats;
+pub mod sysexits;
+pub mod testing;
+pub mod watch;
 
 const SUCCESS_EXIT_CODE: i32 = 0;
 const ARGUMENT_ERROR_EXIT_CODE: i32 = 1;
 const EXECUTION_ERROR_EXIT_CODE: i32 = 2;
 
+/// Shown in place of the values of `ParamKind::Secret` parameters.
+const SECRET_MASK: &str = "***";
+
+/// Options handled by the framework itself, given before the command name, with their descriptions.
+const GLOBAL_OPTIONS: &[(&str, &str)] = &[
+    ("-C DIR", "run as if started in DIR"),
+    ("--env KEY=VALUE", "set an environment variable for the command"),
+    ("--encoding NAME", "read and write text in encoding NAME"),
+    ("--strict", "treat warnings as errors"),
+    ("--no-fancy", "write plain, linear output for screen readers"),
+    ("--offline", "refuse to use the network"),
+    ("--sandbox DIR", "only let the command write files in DIR"),
+    ("--confirm=COMMAND", "run COMMAND without asking, though it modifies state"),
+    ("--resume", "carry on with an interrupted batch"),
+    ("--debug-parse", "explain how arguments are assigned to parameters"),
+    ("--print-exit-status", "finish with a status line on stderr"),
+    ("--stats", "finish with resource usage on stderr"),
+    ("--help", "print this usage information"),
+];
+
+/// Checks the syntax of a regular expression, returning a description of any
+/// error; see `Application::check_pattern`.
+pub type PatternCheck = fn(&str) -> Result<(), String>;
+
 /// Describes an application and the commands it supports.
 pub struct Application<'c, 'p:'c> {
     /// The name of the application.
@@ -137,17 +300,213 @@ pub struct Application<'c, 'p:'c> {
 
     /// A collection of commands the application supports.
     pub commands: &'c [Command<'p>],
+
+    /// How control characters in handler output are treated, to guard against
+    /// terminal injection by untrusted data.
+    pub sanitize: sanitize::Mode,
+
+    /// What to do when a command's name is reserved for a built-in command. By
+    /// default the command overrides the built-in.
+    pub reserved_names: ReservedNamePolicy,
+
+    /// A function applied to each argument to a `ParamKind::Path` parameter before
+    /// the command runs, e.g. `expand::expand` to expand `~` and `$VAR` on
+    /// platforms whose shell doesn't.
+    pub expand_paths: Option<fn(&str, &env::Provider) -> String>,
+
+    /// A function which checks the syntax of each argument to a `ParamKind::Regex`
+    /// parameter before the command runs, returning a description of any error,
+    /// e.g. `|p| regex::Regex::new(p).map(|_| ()).map_err(|e| e.to_string())`.
+    pub check_pattern: Option<PatternCheck>,
+
+    /// Whether warnings written with `Context::warn` are treated as errors; this
+    /// can also be enabled for a single run with the global `--strict` option.
+    pub strict: bool,
+
+    /// The exit code used when a command yields `CommandResult::PartialFailure`.
+    pub partial_failure_exit_code: i32,
+
+    /// The exit code used when a command's handler panics, so that crashes can be
+    /// told apart from handled failures. On targets where panics abort, such as
+    /// `wasm32-wasip1`, the process aborts instead.
+    pub panic_exit_code: i32,
+
+    /// The line endings written to stdout and stderr by handlers.
+    pub line_endings: lines::Ending,
+
+    /// Conceptual documentation printed by `app help TOPIC`.
+    pub topics: &'c [HelpTopic],
+
+    /// Whether escape sequences (colors, styles, etc.) are removed from handler
+    /// output written to a stream which isn't a terminal, so that logs and pipes
+    /// stay clean.
+    pub strip_ansi_when_redirected: bool,
+
+    /// Old names of commands which have been renamed, along with their new names.
+    /// Invoking a command by its old name prints a deprecation notice and runs it.
+    pub renamed_commands: &'c [(&'static str, &'static str)],
+
+    /// A function applied to the command-line arguments (including the application
+    /// name) before anything else, e.g. to rewrite legacy spellings or insert
+    /// defaults.
+    pub rewrite_args: Option<fn(Vec<String>) -> Vec<String>>,
+
+    /// An environment variable, e.g. `"APP_OPTS"`, holding default arguments.
+    /// Its value is split into words as a shell would (see `quote::split_posix`)
+    /// and inserted after the application name, after `rewrite_args` is applied.
+    /// Handlers can see what was inserted with `Context::injected_args`.
+    pub opts_var: Option<&'static str>,
+
+    /// The built-in behaviors to enable. Users can turn these off individually
+    /// with environment variables; see `FrameworkFeatures::with_env_overrides`.
+    pub features: features::FrameworkFeatures,
+
+    /// The name of the locale returned by `Context::locale`, e.g. `"C"`, instead
+    /// of the one given by the environment.
+    pub locale: Option<&'static str>,
+
+    /// How handlers' output to stdout is buffered; see `filter::Buffering`.
+    /// Handlers can write buffered output out early with `Context::flush`.
+    pub output_buffering: filter::Buffering,
+
+    /// Whether the framework's output is kept the same from run to run and
+    /// machine to machine, for golden tests: durations and memory use are
+    /// printed as `-`, and output isn't `fancy`. Users can also turn this on by
+    /// setting `APP_DETERMINISTIC` (named as in `FrameworkFeatures`) to anything
+    /// other than an empty string or `0`. Handlers can check
+    /// `Context::deterministic` to do likewise.
+    pub deterministic: bool,
+
+    /// Whether commands which are marked `mutates` ask before running. When
+    /// stdin isn't a terminal, as in CI, they refuse to run instead, unless
+    /// given `--confirm=NAME` with the command's name.
+    pub confirm_mutating: bool,
+
+    /// Whether any command keeps a `batch::Checkpoint`, so that `--resume` is
+    /// listed in the usage.
+    pub resumable: bool,
 }
 
 impl<'c, 'p> Application<'c, 'p> {
+    /// An application with no name or commands and default settings, for use as
+    /// the base of a struct update:
+    ///
+    /// ```
+    /// # use command_cli::{Application, StaticApplication};
+    /// const APP: StaticApplication = Application { name: "app", commands: &[], ..Application::DEFAULT };
+    /// ```
+    pub const DEFAULT: Application<'c, 'p> = Application {
+        name: "",
+        commands: &[],
+        sanitize: sanitize::Mode::Off,
+        reserved_names: ReservedNamePolicy::Override,
+        expand_paths: None,
+        check_pattern: None,
+        strict: false,
+        partial_failure_exit_code: 3,
+        panic_exit_code: 101,
+        line_endings: lines::Ending::Lf,
+        topics: &[],
+        strip_ansi_when_redirected: false,
+        renamed_commands: &[],
+        rewrite_args: None,
+        opts_var: None,
+        features: features::FrameworkFeatures::ALL,
+        locale: None,
+        output_buffering: filter::Buffering::Off,
+        deterministic: false,
+        confirm_mutating: false,
+        resumable: false,
+    };
+
+    /// Checks that the application's commands are consistent with its settings,
+    /// returning a description of the problem if not.
+    ///
+    /// This is done by `run`, but can also be called from a unit test to catch
+    /// mistakes early.
+    pub fn validate(&self) -> Result<(), String> {
+        for &(old, new) in self.renamed_commands {
+            if !self.commands.iter().any(|cmd| cmd.name == new) {
+                return Err(format!("Command '{}' is renamed to '{}', which doesn't exist", old, new));
+            }
+        }
+
+        if self.check_pattern.is_none() {
+            for cmd in self.commands {
+                let mut params = cmd.all_params().into_iter().chain(cmd.forms.iter().flat_map(|form| form.iter()));
+                if params.any(|param| param.kind == ParamKind::Regex) {
+                    return Err(format!("Command '{}' has a Regex parameter, but check_pattern isn't set", cmd.name));
+                }
+            }
+        }
+
+        for cmd in self.commands {
+            if let Some(name) = cmd.stdin_param {
+                let params: Vec<&Parameter> = cmd.all_params().into_iter()
+                    .chain(cmd.forms.iter().flat_map(|form| form.iter()))
+                    .filter(|param| param.name == name)
+                    .collect();
+                if params.is_empty() {
+                    return Err(format!("Command '{}' reads stdin into '{}', which isn't one of its parameters", cmd.name, name));
+                } else if params.iter().any(|param| param.required || !param.repeating) {
+                    return Err(format!("Command '{}' reads stdin into '{}', which isn't optional and repeating", cmd.name, name));
+                }
+            }
+        }
+
+        for (i, cmd) in self.commands.iter().enumerate() {
+            match (cmd.verb, cmd.noun) {
+                (Some(verb), Some(noun)) => {
+                    if [verb, noun].iter().any(|word| word.is_empty() || word.contains(char::is_whitespace)) {
+                        return Err(format!("Command '{}' has a verb or noun which isn't a single word", cmd.name));
+                    }
+                    if let Some(other) = self.commands[..i].iter().find(|other| other.verb == cmd.verb && other.noun == cmd.noun) {
+                        return Err(format!("Commands '{}' and '{}' both {} {}", other.name, cmd.name, verb, noun));
+                    }
+                },
+                (None, None) => (),
+                _ => return Err(format!("Command '{}' has a verb or a noun, but not both", cmd.name)),
+            }
+        }
+
+        if self.reserved_names == ReservedNamePolicy::Error {
+            for cmd in self.commands {
+                if builtin::RESERVED_NAMES.contains(&cmd.name) {
+                    return Err(format!("Command '{}' uses a name reserved for a built-in command", cmd.name));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Prints usage information for the application.
     pub fn print_usage(&self, sp: &mut stream::Provider) {
-        writeln!(sp.error(), "Usage: {} COMMAND [ARGS]\n", self.name).unwrap();
-        writeln!(sp.error(), "commands:").unwrap();
+        writeln!(sp.error(), "{}", self.usage_string()).unwrap();
+    }
+
+    /// Returns the usage information printed by `print_usage`, without a trailing newline.
+    pub fn usage_string(&self) -> String {
+        let mut usage = format!("Usage: {} [OPTIONS] COMMAND [ARGS]\n\ncommands:", self.name);
 
         for cmd in self.commands {
-            cmd.print_short_desc(sp);
+            usage.push('\n');
+            usage.push_str(&cmd.short_desc_string());
+        }
+
+        if !self.topics.is_empty() {
+            usage.push_str("\n\nhelp topics:");
+            for topic in self.topics {
+                usage.push_str(&format!("\n{: <22}  {}", topic.name, topic.title));
+            }
+        }
+
+        usage.push_str("\n\noptions:");
+        for &(option, desc) in self.global_options() {
+            usage.push_str(&format!("\n{: <22}  {}", option, desc));
         }
+
+        usage
     }
 
     /// Given the command-line arguments, parses them and runs a command if applicable.
@@ -157,49 +516,537 @@ impl<'c, 'p> Application<'c, 'p> {
     pub fn run(&self, sp: &mut stream::Provider, args: Vec<String>)
         -> (i32, Option<&'c Command<'p>>)
     {
+        self.run_with_env(sp, &mut env::Std::new(), args)
+    }
+
+    /// Like `run`, but accesses the process environment (e.g. to handle `-C DIR`)
+    /// through `ep` rather than directly.
+    pub fn run_with_env(&self, sp: &mut stream::Provider, ep: &mut env::Provider, args: Vec<String>)
+        -> (i32, Option<&'c Command<'p>>)
+    {
+        if let Err(msg) = self.validate() {
+            writeln!(sp.error(), "Error: {}", msg).unwrap();
+            return (EXECUTION_ERROR_EXIT_CODE, None);
+        }
+
+        let raw_args = args.clone();
+        let mut args = match self.rewrite_args {
+            Some(rewrite) => rewrite(args),
+            None => args,
+        };
+
+        let mut injected = Vec::new();
+        if let Some(key) = self.opts_var {
+            if let Some(value) = ep.var(key) {
+                injected = match quote::split_posix(&value) {
+                    Ok(words) => words,
+                    Err(msg) => {
+                        writeln!(sp.error(), "Error: Unable to parse {}: {}", key, msg).unwrap();
+                        return (ARGUMENT_ERROR_EXIT_CODE, None);
+                    },
+                };
+                let at = args.len().min(1);
+                args.splice(at..at, injected.iter().cloned());
+            }
+        }
+
+        let (options, args) = match GlobalOptions::parse(args) {
+            Ok(parsed) => parsed,
+            Err(msg) => {
+                writeln!(sp.error(), "Error: {}", msg).unwrap();
+                return (ARGUMENT_ERROR_EXIT_CODE, None);
+            },
+        };
+
+        let deterministic = self.is_deterministic(ep);
+        // Timed by the environment's clock, so that tests can control it
+        let start = ep.now();
+        let (exit_code, cmd_opt, byte_counts) = if options.stats {
+            let mut counted = stats::Counted::new(sp);
+            let (exit_code, cmd_opt) = self.dispatch(&mut counted, ep, &options, &raw_args, &injected, args);
+            (exit_code, cmd_opt, Some((counted.output_bytes, counted.error_bytes)))
+        } else {
+            let (exit_code, cmd_opt) = self.dispatch(sp, ep, &options, &raw_args, &injected, args);
+            (exit_code, cmd_opt, None)
+        };
+        let elapsed = ep.now().duration_since(start).unwrap_or_default();
+        let elapsed_ms = if deterministic {
+            "-".to_string()
+        } else {
+            (elapsed.as_secs() * 1000 + elapsed.subsec_nanos() as u64 / 1_000_000).to_string()
+        };
+
+        if let Some((output_bytes, error_bytes)) = byte_counts {
+            let peak_rss = match stats::peak_rss_kib() {
+                Some(kib) if !deterministic => kib.to_string(),
+                _ => "-".to_string(),
+            };
+            writeln!(sp.error(), "{}: wall_ms={} peak_rss_kib={} stdout_bytes={} stderr_bytes={}",
+                self.name, elapsed_ms, peak_rss, output_bytes, error_bytes).unwrap();
+        }
+        if options.print_exit_status {
+            writeln!(sp.error(), "{}: exit={} command={} duration_ms={}",
+                self.name, exit_code, cmd_opt.map_or("-", |cmd| cmd.name), elapsed_ms).unwrap();
+        }
+        (exit_code, cmd_opt)
+    }
+
+    /// Runs the command named in `args`, once the global options have been parsed.
+    fn dispatch(&self, sp: &mut stream::Provider, ep: &mut env::Provider, options: &GlobalOptions,
+        raw_args: &[String], injected: &[String], args: Vec<String>) -> (i32, Option<&'c Command<'p>>)
+    {
+        if options.help {
+            writeln!(sp.output(), "{}", self.usage_string()).unwrap();
+            return (SUCCESS_EXIT_CODE, None);
+        }
+
         if args.len() <= 1 {
             self.print_usage(sp);
             return (ARGUMENT_ERROR_EXIT_CODE, None);
         }
 
-        let cmd_str = args[1].clone();
+        let features = self.features.with_env_overrides(self.name, ep);
+        let deterministic = self.is_deterministic(ep);
+        let fancy = features.contains(FrameworkFeatures::FANCY_OUTPUT) && !options.no_fancy && !deterministic;
+
+        let mut cmd_str = args[1].clone();
+        if let Some(&(old, new)) = self.renamed_commands.iter().find(|&&(old, _)| cmd_str == old) {
+            writeln!(sp.error(), "Warning: '{}' has been renamed to '{}'", old, new).unwrap();
+            cmd_str = new.to_string();
+        }
 
         for cmd in self.commands {
             if cmd_str == cmd.name {
-                let arguments = match Arguments::new(cmd.params, args) {
-                    Some(a) => a,
+                // Secrets left out can be prompted for, so they needn't be given to match
+                let defer_secrets = ep.is_terminal(env::Stream::Input);
+                let parsed = if options.debug_parse {
+                    let mut trace = Vec::new();
+                    let parsed = cmd.parse_arguments_traced(args, defer_secrets, Some(&mut trace));
+                    for step in trace {
+                        writeln!(sp.error(), "parse: {}", step).unwrap();
+                    }
+                    parsed
+                } else {
+                    cmd.parse_arguments_traced(args, defer_secrets, None)
+                };
+
+                let (params, mut arguments) = match parsed {
+                    Some(parsed) => parsed,
                     None => {
                         cmd.print_usage(sp, self.name);
                         return (ARGUMENT_ERROR_EXIT_CODE, Some(cmd));
                     },
                 };
 
-                let result = (cmd.handler)(sp, &arguments);
+                if cmd.requires_network && options.offline {
+                    writeln!(sp.error(), "Error: '{}' needs network access, which --offline rules out", cmd.name).unwrap();
+                    return (ARGUMENT_ERROR_EXIT_CODE, Some(cmd));
+                }
+
+                if cmd.reads_stdin && ep.is_terminal(env::Stream::Input) {
+                    writeln!(sp.error(), "Error: '{}' expects data on stdin, e.g. piped from another command", cmd.name)
+                        .unwrap();
+                    return (ARGUMENT_ERROR_EXIT_CODE, Some(cmd));
+                }
+
+                // The working directory is restored afterwards, since the application may be embedded
+                let original_dir = match change_dirs(ep, &options.dirs) {
+                    Ok(dir) => dir,
+                    Err(msg) => {
+                        writeln!(sp.error(), "Error: {}", msg).unwrap();
+                        return (EXECUTION_ERROR_EXIT_CODE, Some(cmd));
+                    },
+                };
+
+                let sandbox = match options.sandbox {
+                    Some(ref dir) => match ep.canonicalize(Path::new(dir)) {
+                        Ok(root) => Some(root),
+                        Err(e) => {
+                            writeln!(sp.error(), "Error: Unable to use sandbox directory '{}': {}", dir, e).unwrap();
+                            if let Some(dir) = original_dir {
+                                let _ = ep.set_current_dir(&dir);
+                            }
+                            return (EXECUTION_ERROR_EXIT_CODE, Some(cmd));
+                        },
+                    },
+                    None => None,
+                };
+
+                let result = {
+                    let mut filtered = filter::Filtered::new(sp);
+                    self.add_filters(&mut filtered, options, ep, fancy);
+
+                    let mut ctx = Context::filtered(filtered, ep);
+                    let strict = self.strict || options.strict;
+                    ctx.set_strict(strict);
+                    ctx.set_raw_args(raw_args.to_vec());
+                    ctx.set_original_dir(original_dir.clone());
+                    ctx.set_injected_args(injected.to_vec());
+                    for (key, value) in &options.env {
+                        ctx.env().set_var(key, value);
+                    }
+                    let state_path = state::default_path(self.name, ctx.env());
+                    ctx.set_state_path(state_path);
+                    ctx.set_locale(self.locale);
+                    ctx.set_fancy(fancy);
+                    ctx.set_deterministic(deterministic);
+                    ctx.set_offline(options.offline);
+                    ctx.set_resume(options.resume);
+                    ctx.set_sandbox(sandbox);
+
+                    let mut stdin_error = None;
+                    // The matched form may not have the parameter; see `Application::validate`
+                    if let Some(param) = cmd.stdin_param.and_then(|name| params.iter().find(|p| p.name == name)) {
+                        if arguments.count(param.name) == 0 && !ctx.env().is_terminal(env::Stream::Input) {
+                            let mut input = String::new();
+                            match ctx.input().read_to_string(&mut input) {
+                                Ok(_) => {
+                                    let values = input.lines().filter(|l| !l.is_empty()).map(String::from).collect();
+                                    arguments.insert(param, values);
+                                },
+                                Err(e) => stdin_error = Some(e),
+                            }
+                        }
+                    }
+
+                    let mut indirect_error = None;
+                    for param in params.iter().filter(|p| p.indirect || p.kind == ParamKind::Secret) {
+                        let values = arguments[param.name].clone();
+                        let resolved: Result<Vec<String>, String> = values.iter()
+                            .map(|value| resolve_indirect(&mut ctx, value))
+                            .collect();
+                        match resolved {
+                            Ok(values) => arguments.insert(param, values),
+                            Err(message) => indirect_error = Some(message),
+                        }
+                    }
+
+                    for param in params.iter().filter(|p| p.kind == ParamKind::Secret) {
+                        if stdin_error.is_none() && arguments.count(param.name) == 0
+                            && ctx.env().is_terminal(env::Stream::Input)
+                        {
+                            match prompt_secret(&mut ctx, param.name) {
+                                Ok(value) => arguments.insert(param, vec![value]),
+                                Err(e) => stdin_error = Some(e),
+                            }
+                        }
+                    }
+
+                    if let Some(expand) = self.expand_paths {
+                        arguments.map_kind(params.iter().cloned(), ParamKind::Path, |arg| expand(arg, ctx.env()));
+                    }
+
+                    match (stdin_error, indirect_error) {
+                        (Some(e), _) => {
+                            writeln!(ctx.error(), "Error: Unable to read stdin: {}", e).unwrap();
+                            Ok(ExecutionError(None))
+                        },
+                        (None, Some(message)) => Ok(ArgumentErrorWithMessage(message)),
+                        (None, None) => match arguments.check_values(params.iter().cloned(), ctx.env(), self.check_pattern) {
+                            Err(message) => Ok(ArgumentErrorWithMessage(message)),
+                            Ok(()) => match self.check_gates(&mut ctx, cmd, options) {
+                                Some(result) => Ok(result),
+                                None => match catch_unwind_silently(AssertUnwindSafe(|| (cmd.handler)(&mut ctx, &arguments))) {
+                                    Ok(Success) if strict && ctx.warnings() > 0 => Ok(ExecutionError(None)),
+                                    Ok(result) => Ok(result),
+                                    Err(payload) => Err(panic_message(&*payload)),
+                                },
+                            },
+                        },
+                    }
+                };
+
+                if let Some(dir) = original_dir {
+                    if let Err(e) = ep.set_current_dir(&dir) {
+                        writeln!(sp.error(), "Error: Unable to restore working directory: {}", e).unwrap();
+                    }
+                }
+
+                let secrets: Vec<&String> = params.iter()
+                    .filter(|p| p.kind == ParamKind::Secret)
+                    .flat_map(|p| arguments[p.name].iter())
+                    .filter(|value| !value.is_empty())
+                    .collect();
+                let scrub = |message: String| secrets.iter().fold(message, |m, secret| m.replace(&secret[..], SECRET_MASK));
 
                 let exit_code = match result {
-                    Success => SUCCESS_EXIT_CODE,
-                    ArgumentError => {
+                    Err(message) => {
+                        writeln!(sp.error(), "Error: '{}' panicked: {}", cmd.name, scrub(message)).unwrap();
+                        self.panic_exit_code
+                    },
+                    Ok(Success) => SUCCESS_EXIT_CODE,
+                    Ok(ArgumentError) => {
+                        cmd.print_usage(sp, self.name);
+                        ARGUMENT_ERROR_EXIT_CODE
+                    },
+                    Ok(ArgumentErrorWithMessage(message)) => {
+                        writeln!(sp.error(), "Error: {}", scrub(message)).unwrap();
                         cmd.print_usage(sp, self.name);
                         ARGUMENT_ERROR_EXIT_CODE
                     },
-                    ExecutionError(err_opt) => {
+                    Ok(ExecutionError(err_opt)) => {
                         if let Some(err) = err_opt {
-                            writeln!(sp.error(), "Inner error: {}", err.description()).unwrap();
+                            writeln!(sp.error(), "Inner error: {}", scrub(err.description().to_string())).unwrap();
                         }
 
                         EXECUTION_ERROR_EXIT_CODE
                     },
+                    Ok(PartialFailure { failed, total }) => {
+                        writeln!(sp.error(), "Error: {} of {} items failed", failed, total).unwrap();
+                        self.partial_failure_exit_code
+                    },
                 };
 
                 return (exit_code, Some(cmd));
             }
         }
 
+        let builtin_name = match self.reserved_names {
+            ReservedNamePolicy::Rename if cmd_str.starts_with(builtin::RENAME_PREFIX) => {
+                let name = &cmd_str[builtin::RENAME_PREFIX.len()..];
+                if self.commands.iter().any(|cmd| cmd.name == name) { name } else { &cmd_str[..] }
+            },
+            _ => &cmd_str[..],
+        };
+
+        if builtin_name == builtin::COMMANDS && features.contains(FrameworkFeatures::COMMANDS_COMMAND) {
+            return (builtin::commands(self, sp, &args[2..]), None);
+        } else if builtin_name == builtin::HELP && features.contains(FrameworkFeatures::HELP_COMMAND) {
+            return (builtin::help(self, sp, ep, &args[2..], fancy), None);
+        } else if builtin_name == builtin::ROUTE && features.contains(FrameworkFeatures::ROUTE_COMMAND) {
+            return (builtin::route(self, sp, &args[2..]), None);
+        }
+
         writeln!(sp.error(), "Error: Unrecognized command '{}'", cmd_str).unwrap();
         (ARGUMENT_ERROR_EXIT_CODE, None)
     }
 }
 
+/// A page of documentation about a concept rather than a command, printed by
+/// `app help NAME`.
+pub struct HelpTopic {
+    pub name: &'static str,
+
+    /// A one-line title, shown in the application's usage.
+    pub title: &'static str,
+
+    pub text: &'static str,
+}
+
+impl<'c, 'p> Application<'c, 'p> {
+    /// Returns whether output is to be kept the same from run to run; see
+    /// `Application::deterministic`.
+    fn is_deterministic(&self, ep: &env::Provider) -> bool {
+        let key = format!("{}_DETERMINISTIC", self.name.to_uppercase().replace('-', "_"));
+        self.deterministic || ep.var(&key).is_some_and(|value| !value.is_empty() && value != "0")
+    }
+
+    /// Returns the global options which apply given the application's settings
+    /// and commands, with their descriptions. The others are still accepted, but
+    /// aren't listed in the usage.
+    fn global_options(&self) -> Vec<&'static (&'static str, &'static str)> {
+        GLOBAL_OPTIONS.iter()
+            .filter(|&&(option, _)| match option {
+                "--no-fancy" => self.features.contains(FrameworkFeatures::FANCY_OUTPUT),
+                "--offline" => self.commands.iter().any(|cmd| cmd.requires_network),
+                "--confirm=COMMAND" => self.confirm_mutating && self.commands.iter().any(|cmd| cmd.mutates),
+                "--resume" => self.resumable,
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Checks that `cmd` may run: that it's confirmed if need be (see
+    /// `confirm_mutating`), and isn't within its `cooldown`. If not, returns the
+    /// result to finish with instead of running it.
+    fn check_gates(&self, ctx: &mut Context, cmd: &Command, options: &GlobalOptions) -> Option<CommandResult> {
+        if self.confirm_mutating && cmd.mutates {
+            if let Err(message) = confirm(ctx, cmd.name, options.confirm.as_deref()) {
+                return Some(ArgumentErrorWithMessage(message));
+            }
+        }
+
+        if let Some(cooldown) = cmd.cooldown {
+            if let Err(message) = check_cooldown(ctx, cmd.name, cooldown) {
+                writeln!(ctx.error(), "Error: {}", message).unwrap();
+                return Some(ExecutionError(None));
+            }
+        }
+
+        None
+    }
+
+    /// Adds the filters called for by the application's settings and the global
+    /// options to the streams given to handlers. Without `fancy` output, escape
+    /// sequences are always removed.
+    fn add_filters(&self, filtered: &mut filter::Filtered, options: &GlobalOptions, ep: &env::Provider, fancy: bool) {
+        if !fancy {
+            filtered.add_output_filter(Box::new(sanitize::Sanitizer::new(sanitize::Mode::StripEscapes)));
+            filtered.add_error_filter(Box::new(sanitize::Sanitizer::new(sanitize::Mode::StripEscapes)));
+        } else if self.strip_ansi_when_redirected {
+            if !ep.is_terminal(env::Stream::Output) {
+                filtered.add_output_filter(Box::new(sanitize::Sanitizer::new(sanitize::Mode::StripEscapes)));
+            }
+            if !ep.is_terminal(env::Stream::Error) {
+                filtered.add_error_filter(Box::new(sanitize::Sanitizer::new(sanitize::Mode::StripEscapes)));
+            }
+        }
+
+        if self.sanitize != sanitize::Mode::Off {
+            filtered.add_output_filter(Box::new(sanitize::Sanitizer::new(self.sanitize)));
+            filtered.add_error_filter(Box::new(sanitize::Sanitizer::new(self.sanitize)));
+        }
+
+        if self.line_endings.resolve() == lines::Ending::CrLf {
+            filtered.add_output_filter(Box::new(lines::CrLf::new()));
+            filtered.add_error_filter(Box::new(lines::CrLf::new()));
+        }
+
+        filtered.set_output_buffering(self.output_buffering.resolve(ep.is_terminal(env::Stream::Output)));
+
+        // Encoding comes last, since the other filters work on UTF-8
+        if let Some(encoding) = options.encoding {
+            filtered.add_input_filter(Box::new(encoding::Decoder::new(encoding)));
+            filtered.add_output_filter(Box::new(encoding::Encoder::new(encoding)));
+            filtered.add_error_filter(Box::new(encoding::Encoder::new(encoding)));
+        }
+    }
+}
+
+/// Describes what happens when a command's name is reserved for a built-in
+/// command (such as `commands`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReservedNamePolicy {
+    /// Running the application fails with an error.
+    Error,
+    /// The command replaces the built-in.
+    Override,
+    /// The command takes the name, and the built-in is renamed by prefixing `__`
+    /// (e.g. to `__commands`).
+    Rename,
+}
+
+/// Options handled by the framework, given before the command name.
+struct GlobalOptions {
+    /// Directories to change to before running the command, in order (`-C DIR`).
+    dirs: Vec<String>,
+
+    /// Environment variables to set for the command (`--env KEY=VALUE`).
+    env: Vec<(String, String)>,
+
+    /// The encoding of text read and written by the command (`--encoding NAME`).
+    encoding: Option<encoding::Encoding>,
+
+    /// Whether warnings are treated as errors (`--strict`).
+    strict: bool,
+
+    /// Whether to leave out decorations for screen readers (`--no-fancy`).
+    no_fancy: bool,
+
+    /// Whether commands may not use the network (`--offline`).
+    offline: bool,
+
+    /// The directory outside of which commands may not write files (`--sandbox DIR`).
+    sandbox: Option<String>,
+
+    /// The command which may run without asking, though it modifies state (`--confirm=COMMAND`).
+    confirm: Option<String>,
+
+    /// Whether to carry on with an interrupted batch (`--resume`).
+    resume: bool,
+
+    /// Whether to explain how arguments are assigned to parameters (`--debug-parse`).
+    debug_parse: bool,
+
+    /// Whether to print usage information instead of running a command (`--help` or `--usage`).
+    help: bool,
+
+    /// Whether to finish with a machine-readable status line (`--print-exit-status`).
+    print_exit_status: bool,
+
+    /// Whether to finish with a report of the resources used (`--stats`).
+    stats: bool,
+}
+
+impl GlobalOptions {
+    /// Parses the global options from `args`, returning them along with the
+    /// remaining arguments (i.e. `args` without the options).
+    fn parse(args: Vec<String>) -> Result<(GlobalOptions, Vec<String>), String> {
+        let mut options = GlobalOptions { dirs: Vec::new(), env: Vec::new(), encoding: None, strict: false, no_fancy: false, offline: false, sandbox: None, confirm: None, resume: false, debug_parse: false, help: false,
+            print_exit_status: false, stats: false };
+        let mut args_iter = args.into_iter();
+        let mut remaining: Vec<String> = args_iter.next().into_iter().collect();
+
+        while let Some(arg) = args_iter.next() {
+            if !arg.starts_with('-') || arg == "-" {
+                remaining.push(arg);
+                break;
+            }
+
+            match &arg[..] {
+                "-C" => match args_iter.next() {
+                    Some(dir) => options.dirs.push(dir),
+                    None => return Err("Missing value for option '-C'".to_string()),
+                },
+                "--env" => match args_iter.next() {
+                    Some(ref var) if var.find('=').is_some_and(|i| i > 0) => {
+                        let i = var.find('=').unwrap();
+                        options.env.push((var[..i].to_string(), var[i + 1..].to_string()));
+                    },
+                    Some(var) => return Err(
+                        format!("Invalid value '{}' for option '--env': expected KEY=VALUE", var)),
+                    None => return Err("Missing value for option '--env'".to_string()),
+                },
+                "--encoding" => match args_iter.next() {
+                    Some(name) => match encoding::Encoding::from_name(&name) {
+                        Some(encoding) => options.encoding = Some(encoding),
+                        None => return Err(
+                            format!("Invalid value '{}' for option '--encoding': unknown encoding", name)),
+                    },
+                    None => return Err("Missing value for option '--encoding'".to_string()),
+                },
+                "--strict" => options.strict = true,
+                "--no-fancy" => options.no_fancy = true,
+                "--offline" => options.offline = true,
+                "--sandbox" => match args_iter.next() {
+                    Some(dir) => options.sandbox = Some(dir),
+                    None => return Err("Missing value for option '--sandbox'".to_string()),
+                },
+                _ if arg.starts_with("--confirm=") => options.confirm = Some(arg["--confirm=".len()..].to_string()),
+                "--resume" => options.resume = true,
+                "--debug-parse" => options.debug_parse = true,
+                "--print-exit-status" => options.print_exit_status = true,
+                "--stats" => options.stats = true,
+                "--help" | "--usage" => options.help = true,
+                _ => return Err(format!("Unrecognized option '{}'", arg)),
+            }
+        }
+
+        remaining.extend(args_iter);
+        Ok((options, remaining))
+    }
+}
+
+/// Changes to each of `dirs` in turn, returning the original working directory
+/// if any change was made.
+fn change_dirs(ep: &mut env::Provider, dirs: &[String]) -> Result<Option<PathBuf>, String> {
+    if dirs.is_empty() {
+        return Ok(None);
+    }
+
+    let original_dir = try!(ep.current_dir()
+        .map_err(|e| format!("Unable to get working directory: {}", e)));
+
+    for dir in dirs {
+        if let Err(e) = ep.set_current_dir(Path::new(dir)) {
+            let _ = ep.set_current_dir(&original_dir);
+            return Err(format!("Unable to change directory to '{}': {}", dir, e));
+        }
+    }
+
+    Ok(Some(original_dir))
+}
+
 /// Type synonym for applications with static-lifetime commands and parameters,
 /// which is how `Application` will typically be used.
 pub type StaticApplication = Application<'static, 'static>;
@@ -212,21 +1059,175 @@ pub struct Command<'p> {
     /// A one-line description of what the command does.
     pub short_desc: &'static str,
 
+    /// A longer description shown by `app help COMMAND`, in which blank lines separate
+    /// paragraphs, lines starting with `- ` or `* ` are list items, and text in
+    /// backticks is code.
+    pub long_desc: &'static str,
+
     /// A description of the parameters the command takes.
     pub params: &'p [Parameter],
 
-    /// A function which, given the command arguments and i/o handles, executes the command.
-    pub handler: fn(&mut stream::Provider, &Arguments) -> CommandResult,
+    /// A function which, given the command arguments and its context, executes the command.
+    pub handler: fn(&mut Context, &Arguments) -> CommandResult,
+
+    /// Shared groups of parameters the command also takes, following `params` in order.
+    pub params_from: &'p [&'p ParamGroup<'p>],
+
+    /// A synopsis shown in the command's usage instead of the one generated from
+    /// its parameters, e.g. to show alternative forms on separate lines.
+    pub usage_override: Option<&'static str>,
+
+    /// Alternative parameter layouts, for commands which can be called in
+    /// genuinely different ways. Arguments which don't match `params` (with
+    /// `params_from`) are matched against each form in turn; the handler can tell
+    /// which layout matched from `Arguments::form`.
+    pub forms: &'p [&'p [Parameter]],
+
+    /// The name of an optional, repeating parameter whose values are read from
+    /// stdin, one per line, when none are given on the command line and stdin
+    /// isn't a terminal (e.g. `find . | app cmd`).
+    pub stdin_param: Option<&'static str>,
+
+    /// Whether the handler reads data from stdin. If so, running the command with
+    /// stdin connected to a terminal is an argument error, rather than waiting
+    /// for input which is unlikely to be typed.
+    pub reads_stdin: bool,
+
+    /// The action the command takes, e.g. `get`, for applications whose commands
+    /// pair a few actions with a few kinds of object. `app help --matrix` shows
+    /// which commands do what to which kind of object. Set both this and `noun`,
+    /// or neither.
+    pub verb: Option<&'static str>,
+
+    /// The kind of object the command acts on, e.g. `pod`; see `verb`.
+    pub noun: Option<&'static str>,
+
+    /// Whether the command changes anything (files, remote resources, the
+    /// application's state) rather than only reporting on it. The command's help
+    /// says so, and safety features such as confirmation key off this.
+    pub mutates: bool,
+
+    /// Whether the command can't work without network access. Running it with
+    /// `--offline` is an error, rather than letting it fail partway through.
+    pub requires_network: bool,
+
+    /// The least time allowed between runs of the command, e.g. because it uses
+    /// a rate-limited API. When it ran more recently, as recorded in the
+    /// application's state (see `Context::state`), running it is an error which
+    /// says how long is left to wait.
+    pub cooldown: Option<Duration>,
 }
 
 impl<'p> Command<'p> {
+    /// A command with no name, description, parameters or handler (running it is
+    /// an execution error), for use as the base of a struct update.
+    pub const DEFAULT: Command<'p> = Command {
+        name: "",
+        short_desc: "",
+        long_desc: "",
+        params: &[],
+        handler: missing_handler,
+        params_from: &[],
+        usage_override: None,
+        forms: &[],
+        stdin_param: None,
+        reads_stdin: false,
+        verb: None,
+        noun: None,
+        mutates: false,
+        requires_network: false,
+        cooldown: None,
+    };
+
+    /// Returns the parameters declared in `params` followed by those in `params_from`.
+    pub fn all_params(&self) -> Vec<&'p Parameter> {
+        let mut params: Vec<&'p Parameter> = self.params.iter().collect();
+        for group in self.params_from {
+            params.extend(group.params);
+        }
+        params
+    }
+
+    /// Parses `args` against each of the command's parameter layouts in turn,
+    /// returning the parameters of the first which matches along with the arguments.
+    fn parse_arguments(&self, args: Vec<String>) -> Option<(Vec<&'p Parameter>, Arguments)> {
+        self.parse_arguments_traced(args, false, None)
+    }
+
+    /// Like `parse_arguments`, but also appends an account of the parsing to `trace`.
+    /// If `defer_secrets` is set, a layout which doesn't match as given is tried
+    /// again without its required `Secret` parameters, which are left empty.
+    fn parse_arguments_traced(&self, args: Vec<String>, defer_secrets: bool, mut trace: Option<&mut Vec<String>>)
+        -> Option<(Vec<&'p Parameter>, Arguments)>
+    {
+        let layouts = Some(self.all_params()).into_iter()
+            .chain(self.forms.iter().map(|form| form.iter().collect()));
+
+        for (i, params) in layouts.enumerate() {
+            let layout: Vec<String> = params.iter().map(|p| p.to_string()).collect();
+            note(&mut trace, format!("trying form {}: {} {}", i, self.name, layout.join(" ")));
+
+            if let Some(mut arguments) = Arguments::new_traced(params.iter().cloned(), args.clone(), trace.as_deref_mut()) {
+                arguments.form = i;
+                return Some((params, arguments));
+            }
+
+            let is_deferred = |p: &Parameter| p.required && p.kind == ParamKind::Secret;
+            if defer_secrets && params.iter().any(|p| is_deferred(p)) {
+                note(&mut trace, format!("trying form {} without required secrets", i));
+                let given = params.iter().cloned().filter(|p| !is_deferred(p));
+                if let Some(mut arguments) = Arguments::new_traced(given, args.clone(), trace.as_deref_mut()) {
+                    for param in params.iter().filter(|p| is_deferred(p)) {
+                        arguments.insert(param, Vec::new());
+                    }
+                    arguments.form = i;
+                    return Some((params, arguments));
+                }
+            }
+        }
+
+        None
+    }
+
     pub fn print_usage(&self, sp: &mut stream::Provider, app_name: &str) {
-        writeln!(sp.error(), "Usage: {} {}", app_name, self).unwrap();
+        writeln!(sp.error(), "{}", self.usage_string(app_name)).unwrap();
     }
 
     pub fn print_short_desc(&self, sp: &mut stream::Provider) {
-        writeln!(sp.error(), "{: <22}  {}", self.name, self.short_desc).unwrap();
+        writeln!(sp.error(), "{}", self.short_desc_string()).unwrap();
+    }
+
+    /// Returns the usage line printed by `print_usage`, without a trailing newline.
+    pub fn usage_string(&self, app_name: &str) -> String {
+        match self.usage_override {
+            Some(usage) => format!("Usage: {}", usage),
+            None => {
+                let mut usage = format!("Usage: {} {}", app_name, self);
+                for form in self.forms {
+                    usage.push_str(&format!("\n       {} {}", app_name, self.name));
+                    for param in form.iter() {
+                        usage.push_str(&format!(" {}", param));
+                    }
+                }
+                usage
+            },
+        }
     }
+
+    /// Returns the line printed by `print_short_desc`, without a trailing newline.
+    pub fn short_desc_string(&self) -> String {
+        format!("{: <22}  {}", self.name, self.short_desc)
+    }
+}
+
+fn missing_handler(_: &mut Context, _: &Arguments) -> CommandResult {
+    ExecutionError(None)
+}
+
+/// A set of parameters shared by several commands, which is included in each
+/// with `Command::params_from`.
+pub struct ParamGroup<'p> {
+    pub params: &'p [Parameter],
 }
 
 /// Describes the errors which can result from a command invocation.
@@ -235,16 +1236,28 @@ pub enum CommandResult {
     Success,
     /// The command was invoked incorrectly.
     ArgumentError,
+    /// The command was invoked incorrectly, with a message saying how, which is
+    /// printed before the command's usage.
+    ArgumentErrorWithMessage(String),
     /// An error occurred while executing the command.
     ExecutionError(Option<Box<error::Error>>),
+    /// The command operates on several items, and some of them failed.
+    PartialFailure { failed: usize, total: usize },
 }
 use CommandResult::*;
 
+impl CommandResult {
+    /// Constructs an `ArgumentErrorWithMessage`, saying what was wrong.
+    pub fn argument_error<S: Into<String>>(message: S) -> CommandResult {
+        ArgumentErrorWithMessage(message.into())
+    }
+}
+
 impl<'p> fmt::Display for Command<'p> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         try!(f.write_str(self.name));
 
-        for param in self.params {
+        for param in self.all_params() {
             try!(write!(f, " {}", param));
         }
 
@@ -258,6 +1271,130 @@ pub struct Parameter {
     pub name: &'static str,
     pub required: bool,
     pub repeating: bool,
+
+    /// What kind of value the parameter takes.
+    pub kind: ParamKind,
+
+    /// For a repeating parameter, a character at which each argument is split into
+    /// several values, e.g. `Some(',')` so that `a,b c` yields `a`, `b` and `c`.
+    pub value_delimiter: Option<char>,
+
+    /// Whether a repeating parameter's values are sorted, for commands which
+    /// treat them as a set. The original order is available from `Arguments::raw`.
+    pub sort: bool,
+
+    /// Whether repeated values of a repeating parameter are dropped, keeping the
+    /// first of each. The original values are available from `Arguments::raw`.
+    pub dedup: bool,
+
+    /// For a `Path` parameter, what must be true of the paths given, which is
+    /// checked before the handler runs.
+    pub path_policy: PathPolicy,
+
+    /// For a `Path` parameter, whether paths are made absolute with symbolic
+    /// links resolved before being given to the handler.
+    pub canonicalize: bool,
+
+    /// For a `Url` parameter, the schemes allowed, e.g. `&["https", "ssh"]`, or
+    /// any scheme if empty.
+    pub url_schemes: &'static [&'static str],
+
+    /// For an `Integer` parameter, the smallest and largest values allowed.
+    pub range: Option<(i64, i64)>,
+
+    /// For a `Float` parameter, the smallest and largest values allowed.
+    pub float_range: Option<FloatRange>,
+
+    /// For a `Float` parameter, whether `inf`, `-inf` and `NaN` are allowed.
+    pub allow_non_finite: bool,
+
+    /// Whether values may be read from elsewhere, so that long text or secrets
+    /// needn't appear on the command line: `@FILE` is replaced by the contents of
+    /// `FILE` (relative to the working directory), `-` by what's read from stdin,
+    /// and a leading `@@` by `@`. This is always the case for `Secret` parameters.
+    pub indirect: bool,
+}
+
+impl Parameter {
+    /// An optional, non-repeating text parameter with no name, for use as the
+    /// base of a struct update.
+    pub const DEFAULT: Parameter = Parameter {
+        name: "",
+        required: false,
+        repeating: false,
+        kind: ParamKind::Text,
+        value_delimiter: None,
+        sort: false,
+        dedup: false,
+        path_policy: PathPolicy::Any,
+        canonicalize: false,
+        url_schemes: &[],
+        range: None,
+        float_range: None,
+        allow_non_finite: false,
+        indirect: false,
+    };
+}
+
+/// Describes what kind of value a parameter takes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum ParamKind {
+    /// Arbitrary text.
+    Text,
+    /// A file system path, which is subject to `Application::expand_paths`.
+    Path,
+    /// A URL, whose syntax and scheme (see `Parameter::url_schemes`) are checked
+    /// before the handler runs.
+    Url,
+    /// A date and time in any of the forms described in the `datetime` module,
+    /// which the handler is given as an RFC 3339 timestamp in UTC.
+    DateTime,
+    /// A regular expression, whose syntax is checked before the handler runs
+    /// with `Application::check_pattern`.
+    Regex,
+    /// A whole number, optionally limited to `Parameter::range`.
+    Integer,
+    /// A number, optionally limited to `Parameter::float_range`.
+    Float,
+    /// A password, token or the like, which is masked in traces and scrubbed
+    /// from error messages. If a secret parameter is omitted and stdin is a
+    /// terminal, the user is prompted for it without echo. Values are resolved as
+    /// described for `Parameter::indirect`, so a secret which really starts with
+    /// `@` must be given as `@@`.
+    Secret,
+}
+
+/// The smallest and largest values allowed for a `Float` parameter.
+#[derive(Clone, Copy, Debug)]
+pub struct FloatRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+// Ranges are compared bitwise so that `Parameter` can be `Eq` and `Hash`
+impl PartialEq for FloatRange {
+    fn eq(&self, other: &FloatRange) -> bool {
+        self.min.to_bits() == other.min.to_bits() && self.max.to_bits() == other.max.to_bits()
+    }
+}
+
+impl Eq for FloatRange {}
+
+impl Hash for FloatRange {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.min.to_bits().hash(state);
+        self.max.to_bits().hash(state);
+    }
+}
+
+/// Describes what must be true of the paths given to a `Path` parameter.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum PathPolicy {
+    Any,
+    MustExist,
+    MustNotExist,
+    /// The path needn't exist, but the directory containing it must, e.g. for an output file.
+    ParentMustExist,
 }
 
 impl fmt::Display for Parameter {
@@ -271,36 +1408,224 @@ impl fmt::Display for Parameter {
     }
 }
 
-/// Describes the arguments to a command.
-pub struct Arguments {
-    /// A mapping from `Parameter` to the associated arguments for that parameter.
-    param_to_args: HashMap<String, Vec<String>>,
+/// Appends `message` to `trace`, if there is one.
+fn note(trace: &mut Option<&mut Vec<String>>, message: String) {
+    if let Some(ref mut trace) = *trace {
+        trace.push(message);
+    }
 }
 
-impl Arguments {
-    /// Constructs a new `Arguments`, yielding `None` if the arguments do not
-    /// match the provided parameter specification.
-    fn new(params: &[Parameter], args: Vec<String>) -> Option<Arguments> {
-        let mut param_to_args: HashMap<String, Vec<String>> = HashMap::new();
-        let mut min_remaining = params.iter().filter(|p| p.required).count();
-        let mut remaining = args.len() - 2;
-        let mut args_iter = args.into_iter();
+/// Quotes each of `args` for a POSIX shell, separated by spaces, or returns `(none)`.
+fn quote_all(args: &[String]) -> String {
+    if args.is_empty() {
+        return "(none)".to_string();
+    }
 
-        // Pop the application name and command off the iterator
-        args_iter.next().unwrap();
-        args_iter.next().unwrap();
+    let quoted: Vec<String> = args.iter().map(|arg| quote::posix(arg)).collect();
+    quoted.join(" ")
+}
 
-        for param in params {
-            if remaining < min_remaining {
-                return None;
-            }
+/// Resolves `@FILE` and `-` in the value of an indirect parameter; see
+/// `Parameter::indirect`. A single trailing newline is removed from what's read.
+fn resolve_indirect(ctx: &mut Context, value: &str) -> Result<String, String> {
+    let mut contents = String::new();
+    if value == "-" {
+        try!(ctx.input().read_to_string(&mut contents).map_err(|e| format!("Unable to read stdin: {}", e)));
+    } else if value.starts_with("@@") {
+        return Ok(value[1..].to_string());
+    } else if let Some(path) = value.strip_prefix('@') {
+        let bytes = try!(ctx.env().read_file(Path::new(path)).map_err(|e| format!("Unable to read '{}': {}", path, e)));
+        contents = try!(String::from_utf8(bytes).map_err(|_| format!("'{}' isn't valid UTF-8", path)));
+    } else {
+        return Ok(value.to_string());
+    }
 
-            if param.required {
-                min_remaining = min_remaining - 1;
-            }
+    if contents.ends_with('\n') {
+        contents.pop();
+        if contents.ends_with('\r') {
+            contents.pop();
+        }
+    }
+    Ok(contents)
+}
 
-            // Have to loop here instead of using .take(x).collect() because Vec::IntoIter
-            // isn't clonable
+/// Prompts on stderr for the value of a secret parameter, and reads it from
+/// stdin with echo turned off.
+fn prompt_secret(ctx: &mut Context, name: &str) -> io::Result<String> {
+    write!(ctx.error(), "{}: ", name).unwrap();
+    try!(ctx.env().set_echo(false));
+    let result = read_line(ctx);
+    try!(ctx.env().set_echo(true));
+    writeln!(ctx.error()).unwrap();
+    result
+}
+
+/// Reads a line from stdin, without its line ending.
+fn read_line(ctx: &mut Context) -> io::Result<String> {
+    // Read a byte at a time so that nothing after the line is consumed
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    loop {
+        match try!(ctx.input().read(&mut byte)) {
+            0 => break,
+            _ if byte[0] == b'\n' => break,
+            _ => line.push(byte[0]),
+        }
+    }
+
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Asks whether to run a command which modifies state, unless `--confirm=NAME`
+/// was given for it; see `Application::confirm_mutating`.
+fn confirm(ctx: &mut Context, cmd_name: &str, confirmed: Option<&str>) -> Result<(), String> {
+    match confirmed {
+        Some(name) if name == cmd_name => return Ok(()),
+        Some(name) => return Err(format!("--confirm={} doesn't match the command '{}'", name, cmd_name)),
+        None => (),
+    }
+
+    if !ctx.env().is_terminal(env::Stream::Input) {
+        return Err(format!("'{}' modifies state, so it needs --confirm={} when stdin isn't a terminal", cmd_name, cmd_name));
+    }
+    write!(ctx.error(), "'{}' modifies state. Continue? [y/N] ", cmd_name).unwrap();
+    match read_line(ctx) {
+        Ok(ref answer) if ["y", "Y", "yes", "Yes"].contains(&answer.trim()) => Ok(()),
+        Ok(_) => Err("Not confirmed".to_string()),
+        Err(e) => Err(format!("Unable to read confirmation: {}", e)),
+    }
+}
+
+/// Records that the command `cmd_name` runs now, unless it last ran less than
+/// `cooldown` ago; see `Command::cooldown`.
+fn check_cooldown(ctx: &mut Context, cmd_name: &str, cooldown: Duration) -> Result<(), String> {
+    let key = format!("cooldown.{}", cmd_name);
+    let now = ctx.env().now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let last = {
+        let state = try!(ctx.state().map_err(|e| format!("Unable to load state: {}", e)));
+        state.get(&key).and_then(|value| value.parse().ok())
+    };
+
+    if let Some(last) = last {
+        let next = Duration::from_secs(last) + cooldown;
+        if now < next {
+            return Err(format!("'{}' can be run at most once every {}; try again in {}",
+                cmd_name, format_wait(cooldown), format_wait(next - now)));
+        }
+    }
+
+    ctx.set_state(&key, &now.as_secs().to_string()).map_err(|e| format!("Unable to save state: {}", e))
+}
+
+/// Formats a duration in whole seconds, rounded up, e.g. `1h 5m` or `42s`.
+fn format_wait(duration: Duration) -> String {
+    let secs = duration.as_secs() + if duration.subsec_nanos() > 0 { 1 } else { 0 };
+    let parts: Vec<String> = [(secs / 3600, "h"), (secs / 60 % 60, "m"), (secs % 60, "s")].iter()
+        .filter(|&&(n, _)| n > 0)
+        .map(|&(n, unit)| format!("{}{}", n, unit))
+        .collect();
+    if parts.is_empty() { "0s".to_string() } else { parts.join(" ") }
+}
+
+thread_local! {
+    /// Whether panics on this thread are kept from the panic hook; see `catch_unwind_silently`.
+    static SILENCED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Like `panic::catch_unwind`, but a panic on this thread isn't reported by the
+/// panic hook, which would write it (unscrubbed) straight to the process's stderr.
+/// Panics on other threads are reported as usual.
+///
+/// The hook is wrapped only once per process, since replacing it on every call
+/// would race with other threads doing the same, and could lose the hook.
+fn catch_unwind_silently<F: FnOnce() -> R + panic::UnwindSafe, R>(f: F) -> thread::Result<R> {
+    static WRAP_HOOK: Once = Once::new();
+    WRAP_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !SILENCED.with(|silenced| silenced.get()) {
+                previous(info);
+            }
+        }));
+    });
+
+    let was_silenced = SILENCED.with(|silenced| silenced.replace(true));
+    let result = panic::catch_unwind(f);
+    SILENCED.with(|silenced| silenced.set(was_silenced));
+    result
+}
+
+/// Gets the message a handler panicked with, if it was a string.
+fn panic_message(payload: &(Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "(no message)".to_string(),
+        },
+    }
+}
+
+/// Like `quote_all`, but masks the values of secret parameters.
+fn quote_values(param: &Parameter, args: &[String]) -> String {
+    if param.kind == ParamKind::Secret && !args.is_empty() {
+        vec![SECRET_MASK; args.len()].join(" ")
+    } else {
+        quote_all(args)
+    }
+}
+
+/// Describes the arguments to a command.
+pub struct Arguments {
+    /// A mapping from `Parameter` to the associated arguments for that parameter.
+    param_to_args: HashMap<String, Vec<String>>,
+
+    /// The index of the parameter layout which matched; see `form`.
+    form: usize,
+
+    /// The arguments as given to parameters whose values were sorted or deduplicated.
+    raw: HashMap<String, Vec<String>>,
+}
+
+impl Arguments {
+    /// Constructs a new `Arguments`, yielding `None` if the arguments do not
+    /// match the provided parameter specification.
+    #[cfg(test)]
+    fn new<'a, I>(params: I, args: Vec<String>) -> Option<Arguments>
+        where I: IntoIterator<Item=&'a Parameter> + Clone
+    {
+        Arguments::new_traced(params, args, None)
+    }
+
+    /// Like `new`, but also appends an account of how the arguments were assigned to `trace`.
+    fn new_traced<'a, I>(params: I, args: Vec<String>, mut trace: Option<&mut Vec<String>>) -> Option<Arguments>
+        where I: IntoIterator<Item=&'a Parameter> + Clone
+    {
+        let mut arguments = Arguments { param_to_args: HashMap::new(), form: 0, raw: HashMap::new() };
+        let mut min_remaining = params.clone().into_iter().filter(|p| p.required).count();
+        let mut remaining = args.len() - 2;
+        let mut args_iter = args.into_iter();
+
+        // Pop the application name and command off the iterator
+        args_iter.next().unwrap();
+        args_iter.next().unwrap();
+
+        for param in params {
+            if remaining < min_remaining {
+                note(&mut trace, format!(
+                    "too few arguments: {} left for {} required parameters", remaining, min_remaining));
+                return None;
+            }
+
+            if param.required {
+                min_remaining = min_remaining - 1;
+            }
+
+            // Have to loop here instead of using .take(x).collect() because Vec::IntoIter
+            // isn't clonable
             let param_args_count =
                 if remaining == min_remaining {
                     0
@@ -313,148 +1638,1704 @@ impl Arguments {
             }
             remaining = remaining - param_args_count;
 
-            param_to_args.insert(String::from(param.name), param_args);
+            if param_args.is_empty() && remaining > 0 {
+                note(&mut trace, format!(
+                    "{} <- (none; {} arguments reserved for required parameters)", param, remaining));
+            } else {
+                note(&mut trace, format!("{} <- {}", param, quote_values(param, &param_args)));
+            }
+
+            if let (true, Some(delimiter)) = (param.repeating, param.value_delimiter) {
+                param_args = param_args.iter()
+                    .flat_map(|arg| arg.split(delimiter).map(String::from))
+                    .collect();
+                note(&mut trace, format!("{} split at '{}' into {}", param, delimiter, quote_values(param, &param_args)));
+            }
+
+            if param.repeating && (param.sort || param.dedup) {
+                note(&mut trace, format!("{} normalized to {}", param, quote_values(param, &normalize(param, param_args.clone()))));
+            }
+            arguments.insert(param, param_args);
         }
 
         if remaining > 0 {
+            note(&mut trace, format!("too many arguments: {} left over", remaining));
             None
         } else {
-            Some(Arguments { param_to_args: param_to_args })
+            Some(arguments)
+        }
+    }
+
+    /// Returns which of the command's parameter layouts the arguments matched: 0 for
+    /// `Command::params`, or `i + 1` for `Command::forms[i]`.
+    pub fn form(&self) -> usize {
+        self.form
+    }
+
+    /// Returns the values of the named parameter as they were given, before any
+    /// sorting or deduplication (see `Parameter::sort`).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the command has no such parameter, like indexing.
+    pub fn raw(&self, name: &str) -> &[String] {
+        match self.raw.get(name) {
+            Some(values) => values,
+            None => &self.param_to_args[name],
+        }
+    }
+
+    /// Returns the values of the named `ParamKind::DateTime` parameter as times.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the command has no such parameter, like indexing, or if it isn't a
+    /// `DateTime` parameter.
+    pub fn times(&self, name: &str) -> Vec<SystemTime> {
+        self[name].iter()
+            .map(|value| datetime::parse(value, UNIX_EPOCH).expect("not a DateTime parameter"))
+            .collect()
+    }
+
+    /// Checks the arguments to `Path` parameters against their `path_policy`
+    /// (canonicalizing them if called for), those to `Url` parameters against
+    /// their `url_schemes`, and those to `Regex` parameters with `check_pattern`,
+    /// and converts those to `DateTime` parameters to RFC 3339.
+    fn check_values<'a, I>(&mut self, params: I, ep: &env::Provider, check_pattern: Option<PatternCheck>)
+        -> Result<(), String>
+        where I: IntoIterator<Item=&'a Parameter>
+    {
+        for param in params.into_iter().filter(|p| p.kind != ParamKind::Text) {
+            try!(self.update_values(param, |arg| {
+                match param.kind {
+                    ParamKind::Text | ParamKind::Secret => (),
+                    ParamKind::Path => *arg = try!(check_path(param, arg, ep)),
+                    ParamKind::Url => try!(check_url(param, arg)),
+                    ParamKind::DateTime => match datetime::parse(arg, ep.now()) {
+                        Some(time) => *arg = datetime::format_rfc3339(time),
+                        None => return Err(format!("'{}' is not a valid date or time", arg)),
+                    },
+                    ParamKind::Regex => if let Some(check) = check_pattern {
+                        try!(check(arg).map_err(|e| format!("Invalid pattern '{}': {}", arg, e)));
+                    },
+                    ParamKind::Integer => try!(check_integer(param, arg)),
+                    ParamKind::Float => try!(check_float(param, arg)),
+                }
+                Ok(())
+            }));
+        }
+        Ok(())
+    }
+
+    /// Applies `f` to each value of `param` as given, then sorts and deduplicates
+    /// them again, so that e.g. `./a` and `a` are taken to be the same path.
+    fn update_values<F>(&mut self, param: &Parameter, mut f: F) -> Result<(), String>
+        where F: FnMut(&mut String) -> Result<(), String>
+    {
+        if let Some(raw) = self.raw.get_mut(param.name) {
+            for arg in raw.iter_mut() {
+                try!(f(arg));
+            }
+            self.param_to_args.insert(param.name.to_string(), normalize(param, raw.clone()));
+        } else if let Some(args) = self.param_to_args.get_mut(param.name) {
+            for arg in args.iter_mut() {
+                try!(f(arg));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the values of `param`, normalizing them as it calls for.
+    fn insert(&mut self, param: &Parameter, values: Vec<String>) {
+        if param.repeating && (param.sort || param.dedup) {
+            self.param_to_args.insert(param.name.to_string(), normalize(param, values.clone()));
+            self.raw.insert(param.name.to_string(), values);
+        } else {
+            self.raw.remove(param.name);
+            self.param_to_args.insert(param.name.to_string(), values);
+        }
+    }
+
+    /// Returns the number of arguments given for the named parameter, or 0 if the
+    /// command has no such parameter.
+    pub fn count(&self, name: &str) -> usize {
+        self.param_to_args.get(name).map_or(0, |args| args.len())
+    }
+
+    /// Replaces each argument to a parameter of the given kind with the result of `f`.
+    fn map_kind<'a, I, F>(&mut self, params: I, kind: ParamKind, mut f: F)
+        where I: IntoIterator<Item=&'a Parameter>, F: FnMut(&str) -> String
+    {
+        for param in params.into_iter().filter(|p| p.kind == kind) {
+            let _ = self.update_values(param, |arg| {
+                *arg = f(arg);
+                Ok(())
+            });
         }
     }
 }
 
-impl<'a, S: ?Sized> Index<&'a S> for Arguments
-    where String: Borrow<S>, S: Eq + Hash
-{
-    type Output = Vec<String>;
+/// Checks `path` against `param.path_policy`, returning it canonicalized if called for.
+fn check_path(param: &Parameter, path: &str, ep: &env::Provider) -> Result<String, String> {
+    let canonical = ep.canonicalize(Path::new(path));
+    let parent = match Path::new(path).parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+
+    match param.path_policy {
+        PathPolicy::MustExist if canonical.is_err() => return Err(format!("'{}' does not exist", path)),
+        PathPolicy::MustNotExist if canonical.is_ok() => return Err(format!("'{}' already exists", path)),
+        PathPolicy::ParentMustExist if ep.canonicalize(parent).is_err() => {
+            return Err(format!("The directory containing '{}' does not exist", path))
+        },
+        _ => (),
+    }
+
+    if !param.canonicalize {
+        return Ok(path.to_string());
+    }
+    let canonical = match (canonical, Path::new(path).file_name()) {
+        (Ok(canonical), _) => canonical,
+        (Err(_), Some(name)) => match ep.canonicalize(parent) {
+            Ok(dir) => dir.join(name),
+            Err(_) => return Ok(path.to_string()),
+        },
+        (Err(_), None) => return Ok(path.to_string()),
+    };
+    Ok(canonical.to_str().map_or(path.to_string(), String::from))
+}
+
+/// Checks that `url` is an absolute URL with one of `param.url_schemes`.
+fn check_url(param: &Parameter, url: &str) -> Result<(), String> {
+    let scheme = match url.find(':') {
+        Some(i) if i + 1 < url.len() => &url[..i],
+        _ => return Err(format!("'{}' is not a valid URL", url)),
+    };
+    let valid_scheme = scheme.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+    if !valid_scheme || url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(format!("'{}' is not a valid URL", url));
+    }
+
+    if !param.url_schemes.is_empty() && !param.url_schemes.iter().any(|s| s.eq_ignore_ascii_case(scheme)) {
+        return Err(format!("'{}' must be a URL with scheme {}", url, param.url_schemes.join(" or ")));
+    }
+    Ok(())
+}
+
+/// Checks that `value` is an integer within `param.range`.
+fn check_integer(param: &Parameter, value: &str) -> Result<(), String> {
+    let n: i64 = try!(value.parse().map_err(|_| format!("{} must be an integer", param.name)));
+    match param.range {
+        Some((min, max)) if n < min || n > max => Err(format!("{} must be between {} and {}", param.name, min, max)),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that `value` is a number within `param.float_range`, and finite
+/// unless `param.allow_non_finite` is set.
+fn check_float(param: &Parameter, value: &str) -> Result<(), String> {
+    let x: f64 = try!(value.parse().map_err(|_| format!("{} must be a number", param.name)));
+    if !x.is_finite() {
+        return if param.allow_non_finite { Ok(()) } else { Err(format!("{} must be a finite number", param.name)) };
+    }
+    match param.float_range {
+        Some(FloatRange { min, max }) if x < min || x > max => {
+            Err(format!("{} must be between {} and {}", param.name, min, max))
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Sorts and/or deduplicates `values` as `param` calls for.
+fn normalize(param: &Parameter, mut values: Vec<String>) -> Vec<String> {
+    if param.dedup {
+        let mut seen = Vec::new();
+        values.retain(|value| if seen.contains(value) { false } else { seen.push(value.clone()); true });
+    }
+    if param.sort {
+        values.sort();
+    }
+    values
+}
+
+impl<'a, S: ?Sized> Index<&'a S> for Arguments
+    where String: Borrow<S>, S: Eq + Hash
+{
+    type Output = Vec<String>;
+
+    fn index(&self, index: &S) -> &Vec<String> {
+        &self.param_to_args[index]
+    }
+}
+
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use env::Provider;
+    use io_providers::stream;
+
+    #[test]
+    fn application__print_usage__success() {
+        let mut sp = stream::Virtual::new();
+        let params1: [Parameter; 2] = [
+            Parameter { name: "PARAM1", required: true, repeating: true, ..Parameter::DEFAULT },
+            Parameter { name: "PARAM2", required: false, repeating: false, ..Parameter::DEFAULT }];
+        let params2: [Parameter; 0] = [];
+        let cmds: [Command; 2] = [
+            Command { name: "cmd1", short_desc: "desc1", params: &params1, handler: dummy_success_handler, ..Command::DEFAULT },
+            Command { name: "cmd2", short_desc: "desc2", params: &params2, handler: dummy_success_handler, ..Command::DEFAULT }];
+        let app: Application = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let expected = format!("\
+            Usage: app [OPTIONS] COMMAND [ARGS]\n\n\
+            commands:\n\
+            cmd1                    desc1\n\
+            cmd2                    desc2\n\n\
+            options:\n\
+            -C DIR                  run as if started in DIR\n\
+            --env KEY=VALUE         set an environment variable for the command\n\
+            --encoding NAME         read and write text in encoding NAME\n\
+            --strict                treat warnings as errors\n\
+            --no-fancy              write plain, linear output for screen readers\n\
+            --sandbox DIR           only let the command write files in DIR\n\
+            --debug-parse           explain how arguments are assigned to parameters\n\
+            --print-exit-status     finish with a status line on stderr\n\
+            --stats                 finish with resource usage on stderr\n\
+            --help                  print this usage information\n");
+
+        app.print_usage(&mut sp);
+
+        assert_eq!(0, sp.read_output().len());
+        assert_eq!(&expected, ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__usage_string__success() {
+        let params: [Parameter; 0] = [];
+        let cmds: [Command; 2] = [
+            Command { name: "cmd1", short_desc: "desc1", params: &params, handler: dummy_success_handler, ..Command::DEFAULT },
+            Command { name: "cmd2", short_desc: "desc2", params: &params, handler: dummy_success_handler, ..Command::DEFAULT }];
+        let app: Application = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        let result = app.usage_string();
+
+        assert_eq!("\
+            Usage: app [OPTIONS] COMMAND [ARGS]\n\n\
+            commands:\n\
+            cmd1                    desc1\n\
+            cmd2                    desc2\n\n\
+            options:\n\
+            -C DIR                  run as if started in DIR\n\
+            --env KEY=VALUE         set an environment variable for the command\n\
+            --encoding NAME         read and write text in encoding NAME\n\
+            --strict                treat warnings as errors\n\
+            --no-fancy              write plain, linear output for screen readers\n\
+            --sandbox DIR           only let the command write files in DIR\n\
+            --debug-parse           explain how arguments are assigned to parameters\n\
+            --print-exit-status     finish with a status line on stderr\n\
+            --stats                 finish with resource usage on stderr\n\
+            --help                  print this usage information",
+            result);
+    }
+
+    #[test]
+    fn application__usage_string__lists_options_which_apply() {
+        let cmds = [Command { name: "push", mutates: true, requires_network: true, ..Command::DEFAULT }];
+        let plain = Application { name: "app", commands: &cmds, features: FrameworkFeatures::NONE, ..Application::DEFAULT };
+        let full = Application { confirm_mutating: true, resumable: true, features: FrameworkFeatures::ALL, ..plain };
+        let options = |app: &Application| -> Vec<String> {
+            app.usage_string().lines().skip_while(|line| *line != "options:").skip(1)
+                .map(|line| line.split(' ').next().unwrap().to_string())
+                .collect()
+        };
+
+        assert_eq!(vec!["-C", "--env", "--encoding", "--strict", "--offline", "--sandbox", "--debug-parse",
+            "--print-exit-status", "--stats", "--help"], options(&plain));
+        assert_eq!(vec!["-C", "--env", "--encoding", "--strict", "--no-fancy", "--offline", "--sandbox",
+            "--confirm=COMMAND", "--resume", "--debug-parse", "--print-exit-status", "--stats", "--help"], options(&full));
+    }
+
+    #[test]
+    fn application__run__empty_args__prints_usage() {
+        let args = vec!["app".to_string()];
+
+        let sp = test_application_run(1, None, args);
+
+        assert_eq!(0, sp.read_output().len());
+        assert_eq!("\
+            Usage: app [OPTIONS] COMMAND [ARGS]\n\n\
+            commands:\n\
+            cmd1                    desc1\n\
+            cmd2                    desc2\n\
+            cmd3                    desc3\n\
+            cmd4                    desc4\n\n\
+            options:\n\
+            -C DIR                  run as if started in DIR\n\
+            --env KEY=VALUE         set an environment variable for the command\n\
+            --encoding NAME         read and write text in encoding NAME\n\
+            --strict                treat warnings as errors\n\
+            --no-fancy              write plain, linear output for screen readers\n\
+            --sandbox DIR           only let the command write files in DIR\n\
+            --debug-parse           explain how arguments are assigned to parameters\n\
+            --print-exit-status     finish with a status line on stderr\n\
+            --stats                 finish with resource usage on stderr\n\
+            --help                  print this usage information\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__invalid_command__prints_unrecognized_command() {
+        let args = vec!["app".to_string(), "badcmd".to_string()];
+
+        let sp = test_application_run(1, None, args);
+
+        assert_eq!(
+            "Error: Unrecognized command 'badcmd'\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__invalid_args__prints_usage() {
+        let args = vec!["app".to_string(), "cmd1".to_string()];
+
+        let sp = test_application_run(1, Some("cmd1"), args);
+
+        assert_eq!(0, sp.read_output().len());
+        assert_eq!(
+            "Usage: app cmd1 param1\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__handler_success__success() {
+        let args = vec!["app".to_string(), "cmd1".to_string(), "arg1".to_string()];
+
+        let sp = test_application_run(0, Some("cmd1"), args);
+
+        assert_eq!(0, sp.read_output().len());
+        assert_eq!(0, sp.read_error().len());
+    }
+
+    #[test]
+    fn application__run__handler_arg_error__prints_usage() {
+        let args = vec!["app".to_string(), "cmd2".to_string(), "arg1".to_string()];
+
+        let sp = test_application_run(1, Some("cmd2"), args);
+
+        assert_eq!(0, sp.read_output().len());
+        assert_eq!(
+            "Usage: app cmd2 param1\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__handler_arg_error_with_message__prints_message_and_usage() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_arg_error_with_message_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!(1, exit_code);
+        assert_eq!(
+            "Error: '0' is not a valid count\nUsage: app cmd\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__handler_partial_failure__prints_summary() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_partial_failure_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, partial_failure_exit_code: 4, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!(4, exit_code);
+        assert_eq!("Error: 2 of 5 items failed\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__print_exit_status__prints_trailer() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_partial_failure_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app", "--print-exit-status", "cmd"].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(3, exit_code);
+        let error = ::std::str::from_utf8(sp.read_error()).unwrap();
+        assert!(error.starts_with("Error: 2 of 5 items failed\napp: exit=3 command=cmd duration_ms="));
+        assert!(error.ends_with('\n'));
+    }
+
+    #[test]
+    fn application__run_with_env__print_exit_status__timed_by_env_clock() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_sleep_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app", "--print-exit-status", "cmd"].into_iter().map(String::from).collect();
+
+        app.run_with_env(&mut sp, &mut env::Virtual::new(), args);
+
+        assert_eq!("app: exit=0 command=cmd duration_ms=1500\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__stats__prints_report() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_partial_failure_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app", "--stats", "cmd"].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(3, exit_code);
+        let error = ::std::str::from_utf8(sp.read_error()).unwrap();
+        assert!(error.starts_with("Error: 2 of 5 items failed\napp: wall_ms="));
+        assert!(error.ends_with(" stdout_bytes=0 stderr_bytes=27\n"));
+    }
+
+    #[test]
+    fn application__run__raw_args__before_rewriting() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_print_raw_args_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, rewrite_args: Some(legacy_rewrite), ..Application::DEFAULT };
+        let args = vec!["app", "--strict", "--old-cmd"].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("app --strict --old-cmd\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run__state__persists_between_runs() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("XDG_STATE_HOME", "/state");
+        let cmds = [Command { name: "cmd", handler: dummy_count_runs_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "cmd".to_string()]);
+        app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!("run 1\nrun 2\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!(b"runs\t2\n".to_vec(), ep.read_file(Path::new("/state/app/state")).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__cooldown__refuses_until_elapsed() {
+        let mut ep = env::Virtual::new();
+        ep.set_var("XDG_STATE_HOME", "/state");
+        let cmds = [Command { name: "publish", handler: dummy_success_handler, cooldown: Some(Duration::from_secs(60)), ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let mut run_at = |secs: u64| {
+            let mut sp = stream::Virtual::new();
+            ep.set_now(UNIX_EPOCH + Duration::from_secs(secs));
+            let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "publish".to_string()]);
+            (exit_code, String::from_utf8(sp.read_error().to_vec()).unwrap())
+        };
+
+        let results = vec![run_at(1000), run_at(1018), run_at(1060)];
+
+        assert_eq!(vec![
+            (0, String::new()),
+            (2, "Error: 'publish' can be run at most once every 1m; try again in 42s\n".to_string()),
+            (0, String::new()),
+        ], results);
+    }
+
+    #[test]
+    fn application__run_with_env__cooldown_outside_sandbox__state_not_written() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("XDG_STATE_HOME", "/state");
+        ep.add_path(Path::new("/work"));
+        let cmds = [Command { name: "publish", handler: dummy_success_handler, cooldown: Some(Duration::from_secs(60)), ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep,
+            vec!["app".to_string(), "--sandbox".to_string(), "/work".to_string(), "publish".to_string()]);
+
+        assert_eq!(2, exit_code);
+        assert_eq!("Error: Unable to save state: Refusing to write '/state/app', which is outside the sandbox '/work'\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+        assert!(ep.read_file(Path::new("/state/app/state")).is_err());
+    }
+
+    #[test]
+    fn application__run__handler_panics__panic_exit_code() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_panic_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, panic_exit_code: 70, ..Application::DEFAULT };
+
+        let (exit_code, cmd_opt) = app.run(&mut sp, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!(70, exit_code);
+        assert_eq!("cmd", cmd_opt.unwrap().name);
+        assert_eq!("Error: 'cmd' panicked: boom\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__handler_exec_error__success() {
+        let args = vec!["app".to_string(), "cmd3".to_string(), "arg1".to_string()];
+
+        let sp = test_application_run(2, Some("cmd3"), args);
+
+        assert_eq!(0, sp.read_output().len());
+        assert_eq!(0, sp.read_error().len());
+    }
+
+    #[test]
+    fn application__run__handler_exec_error_with_inner__prints_inner() {
+        let args = vec!["app".to_string(), "cmd4".to_string(), "arg1".to_string()];
+
+        let sp = test_application_run(2, Some("cmd4"), args);
+
+        assert_eq!(0, sp.read_output().len());
+        assert_eq!(
+            "Inner error: :(\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__change_dirs__changes_and_restores() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = DirLog { inner: env::Virtual::new(), log: Vec::new() };
+        ep.inner.set_current_dir(Path::new("/home")).unwrap();
+        let params: [Parameter; 0] = [];
+        let cmds = [Command { name: "cmd", short_desc: "desc", params: &params, handler: dummy_success_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app".to_string(), "-C".to_string(), "a".to_string(), "-C".to_string(), "b".to_string(), "cmd".to_string()];
+
+        let (exit_code, cmd_opt) = app.run_with_env(&mut sp, &mut ep, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("cmd", cmd_opt.unwrap().name);
+        assert_eq!(vec![PathBuf::from("/home/a"), PathBuf::from("/home/a/b"), PathBuf::from("/home")], ep.log);
+    }
+
+    #[test]
+    fn application__run_with_env__env_option__overlays_env() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("GREETING", "hi");
+        let params: [Parameter; 0] = [];
+        let cmds = [Command { name: "cmd", short_desc: "desc", params: &params, handler: dummy_print_env_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app".to_string(), "--env".to_string(), "GREETING=a=b".to_string(), "cmd".to_string()];
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("a=b\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!(Some("hi".to_string()), ep.var("GREETING"));
+    }
+
+    #[test]
+    fn application__run__params_from__parses_group_params() {
+        let mut sp = stream::Virtual::new();
+        let own = [Parameter { name: "TEXT", required: true, ..Parameter::DEFAULT }];
+        let shared = [Parameter { name: "PATH", required: true, ..Parameter::DEFAULT }];
+        let group = ParamGroup { params: &shared };
+        let groups = [&group];
+        let cmds = [Command { name: "cmd", params: &own, handler: dummy_print_args_handler, params_from: &groups, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app".to_string(), "cmd".to_string(), "a".to_string(), "b".to_string()];
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("a\nb\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__stdin_param__reads_piped_values() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        let params = [Parameter { name: "FILE", repeating: true, ..Parameter::DEFAULT }];
+        let cmds = [Command {
+            name: "cmd",
+            params: &params,
+            handler: dummy_print_files_handler,
+            stdin_param: Some("FILE"),
+            ..Command::DEFAULT
+        }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        sp.write_input(b"a\r\n\nb\n");
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("a,b\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__reads_stdin_with_terminal__argument_error() {
+        let cmds = [Command { name: "cmd", handler: dummy_echo_handler, reads_stdin: true, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let run = |ep: &mut env::Virtual| {
+            let mut sp = stream::Virtual::new();
+            sp.write_input(b"data\n");
+            let (exit_code, _) = app.run_with_env(&mut sp, ep, vec!["app".to_string(), "cmd".to_string()]);
+            (exit_code, String::from_utf8(sp.read_output().to_vec()).unwrap(), String::from_utf8(sp.read_error().to_vec()).unwrap())
+        };
+        let mut ep = env::Virtual::new();
+
+        assert_eq!((0, "data\n (5 chars)".to_string(), String::new()), run(&mut ep));
+        ep.set_terminal(env::Stream::Input, true);
+        assert_eq!((1, String::new(), "Error: 'cmd' expects data on stdin, e.g. piped from another command\n".to_string()),
+            run(&mut ep));
+    }
+
+    #[test]
+    fn application__run_with_env__stdin_param_with_terminal__not_read() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_terminal(env::Stream::Input, true);
+        let params = [Parameter { name: "FILE", repeating: true, ..Parameter::DEFAULT }];
+        let cmds = [Command {
+            name: "cmd",
+            params: &params,
+            handler: dummy_print_files_handler,
+            stdin_param: Some("FILE"),
+            ..Command::DEFAULT
+        }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        sp.write_input(b"a\n");
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__expand_paths__expands_path_params_only() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("HOME", "/home/me");
+        let params = [
+            Parameter { name: "TEXT", required: true, ..Parameter::DEFAULT },
+            Parameter { name: "PATH", required: true, kind: ParamKind::Path, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "cmd", short_desc: "desc", params: &params, handler: dummy_print_args_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, expand_paths: Some(expand::expand), ..Application::DEFAULT };
+        let args = vec!["app".to_string(), "cmd".to_string(), "~/a".to_string(), "~/b".to_string()];
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("~/a\n/home/me/b\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__path_policy__checked_in_working_dir() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.add_path(Path::new("/work/in.txt"));
+        let params = [
+            Parameter { name: "TEXT", required: true, ..Parameter::DEFAULT },
+            Parameter {
+                name: "PATH",
+                required: true,
+                kind: ParamKind::Path,
+                path_policy: PathPolicy::MustExist,
+                canonicalize: true,
+                ..Parameter::DEFAULT
+            }];
+        let cmds = [Command { name: "cmd", params: &params, handler: dummy_print_args_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = |path: &str| vec!["app", "-C", "/work", "cmd", "text", path].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, args("./in.txt"));
+        let (missing_exit_code, _) = app.run_with_env(&mut sp, &mut ep, args("out.txt"));
+
+        assert_eq!(0, exit_code);
+        assert_eq!(1, missing_exit_code);
+        assert_eq!("text\n/work/in.txt\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!(
+            "Error: 'out.txt' does not exist\nUsage: app cmd TEXT PATH\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn check_url__schemes__success() {
+        let param = Parameter { name: "REMOTE", kind: ParamKind::Url, url_schemes: &["https", "ssh"], ..Parameter::DEFAULT };
+
+        assert_eq!(Ok(()), check_url(&param, "HTTPS://example.com/repo"));
+        assert_eq!(Ok(()), check_url(&param, "ssh://git@example.com/repo"));
+        assert_eq!(
+            Err("'ftp://example.com' must be a URL with scheme https or ssh".to_string()),
+            check_url(&param, "ftp://example.com"));
+        assert_eq!(Err("'example.com' is not a valid URL".to_string()), check_url(&param, "example.com"));
+        assert_eq!(Err("'1http://x' is not a valid URL".to_string()), check_url(&param, "1http://x"));
+        assert_eq!(Err("'https://a b' is not a valid URL".to_string()), check_url(&param, "https://a b"));
+    }
+
+    #[test]
+    fn arguments__check_values__datetime_converted() {
+        let params = &[Parameter { name: "SINCE", repeating: true, kind: ParamKind::DateTime, ..Parameter::DEFAULT }];
+        let args = |since: &str| vec!["app", "cmd", since].into_iter().map(String::from).collect();
+        let mut ep = env::Virtual::new();
+        ep.set_now(UNIX_EPOCH + Duration::from_secs(1709296200));
+
+        let mut valid = Arguments::new(params, args("2024-03-01")).unwrap();
+        let mut relative = Arguments::new(params, args("yesterday")).unwrap();
+        let mut invalid = Arguments::new(params, args("last tuesday")).unwrap();
+
+        assert_eq!(Ok(()), valid.check_values(params, &ep, None));
+        assert_eq!(vec!["2024-03-01T00:00:00Z"], valid["SINCE"]);
+        assert_eq!(vec![UNIX_EPOCH + Duration::from_secs(1709251200)], valid.times("SINCE"));
+        assert_eq!(Ok(()), relative.check_values(params, &ep, None));
+        assert_eq!(vec!["2024-02-29T00:00:00Z"], relative["SINCE"]);
+        assert_eq!(Err("'last tuesday' is not a valid date or time".to_string()), invalid.check_values(params, &ep, None));
+    }
+
+    #[test]
+    fn check_integer__range__success() {
+        let param = Parameter { name: "PORT", kind: ParamKind::Integer, range: Some((1, 65535)), ..Parameter::DEFAULT };
+
+        assert_eq!(Ok(()), check_integer(&param, "8080"));
+        assert_eq!(Err("PORT must be between 1 and 65535".to_string()), check_integer(&param, "70000"));
+        assert_eq!(Err("PORT must be an integer".to_string()), check_integer(&param, "80.5"));
+    }
+
+    #[test]
+    fn check_float__range__success() {
+        let param = Parameter {
+            name: "RATIO",
+            kind: ParamKind::Float,
+            float_range: Some(FloatRange { min: 0.0, max: 1.0 }),
+            ..Parameter::DEFAULT
+        };
+
+        assert_eq!(Ok(()), check_float(&param, "0.25"));
+        assert_eq!(Err("RATIO must be between 0 and 1".to_string()), check_float(&param, "1.5"));
+        assert_eq!(Err("RATIO must be a number".to_string()), check_float(&param, "half"));
+        assert_eq!(Err("RATIO must be a finite number".to_string()), check_float(&param, "NaN"));
+        assert_eq!(Err("RATIO must be a finite number".to_string()), check_float(&param, "inf"));
+        assert_eq!(Err("RATIO must be a finite number".to_string()), check_float(&param, "-inf"));
+    }
+
+    #[test]
+    fn check_float__non_finite_allowed__success() {
+        let param = Parameter { name: "LIMIT", kind: ParamKind::Float, allow_non_finite: true, ..Parameter::DEFAULT };
+
+        assert_eq!(Ok(()), check_float(&param, "inf"));
+        assert_eq!(Ok(()), check_float(&param, "NaN"));
+        assert_eq!(Ok(()), check_float(&param, "2.5"));
+    }
+
+    #[test]
+    fn check_path__policies__success() {
+        let mut ep = env::Virtual::new();
+        ep.add_path(Path::new("/dir/file"));
+        let param = |policy| Parameter { name: "PATH", kind: ParamKind::Path, path_policy: policy, ..Parameter::DEFAULT };
+
+        assert!(check_path(&param(PathPolicy::MustNotExist), "/dir/new", &ep).is_ok());
+        assert_eq!(Err("'/dir/file' already exists".to_string()), check_path(&param(PathPolicy::MustNotExist), "/dir/file", &ep));
+        assert!(check_path(&param(PathPolicy::ParentMustExist), "/dir/new", &ep).is_ok());
+        assert_eq!(
+            Err("The directory containing '/other/new' does not exist".to_string()),
+            check_path(&param(PathPolicy::ParentMustExist), "/other/new", &ep));
+
+        let canonical = Parameter { canonicalize: true, ..param(PathPolicy::Any) };
+        assert_eq!(Ok("/dir/new".to_string()), check_path(&canonical, "/dir/../dir/new", &ep));
+    }
+
+    #[test]
+    fn application__run_with_env__canonicalized_dedup__normalized_first() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.add_path(Path::new("/work/a"));
+        ep.add_path(Path::new("/work/b"));
+        let params = [Parameter {
+            name: "FILE",
+            repeating: true,
+            kind: ParamKind::Path,
+            canonicalize: true,
+            sort: true,
+            dedup: true,
+            ..Parameter::DEFAULT
+        }];
+        let cmds = [Command { name: "cmd", params: &params, handler: dummy_print_files_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app", "-C", "/work", "cmd", "b", "./a", "a"].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("/work/a,/work/b\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run__strict_option_with_warning__execution_error() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_warning_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!(0, exit_code);
+
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "--strict".to_string(), "cmd".to_string()]);
+
+        assert_eq!(2, exit_code);
+        assert_eq!(
+            "Warning: careful\nError: careful\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__encoding_option__transcodes_streams() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_echo_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app".to_string(), "--encoding".to_string(), "latin1".to_string(), "cmd".to_string()];
+        sp.write_input(b"caf\xe9");
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!(b"caf\xe9 (4 chars)", sp.read_output());
+    }
+
+    #[test]
+    fn application__run_with_env__strip_ansi_when_redirected__strips_redirected_only() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_terminal(env::Stream::Error, true);
+        let cmds = [Command { name: "cmd", handler: dummy_styled_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, strip_ansi_when_redirected: true, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("red\ttab\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!("\x1b[31mred\x1b[0m\ttab\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__deterministic__stable_reports_and_plain_output() {
+        let cmds = [Command { name: "cmd", handler: dummy_fancy_handler, ..Command::DEFAULT }];
+        let app = Application { name: "my-app", commands: &cmds, ..Application::DEFAULT };
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("MY_APP_DETERMINISTIC", "1");
+        let args = vec!["my-app", "--stats", "--print-exit-status", "cmd"].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("fancy=false\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!("my-app: wall_ms=- peak_rss_kib=- stdout_bytes=12 stderr_bytes=0\n\
+            my-app: exit=0 command=cmd duration_ms=-\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__stdout_raw__bypasses_filters() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_binary_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, line_endings: lines::Ending::CrLf, ..Application::DEFAULT };
+        let args = vec!["app", "--no-fancy", "cmd"].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!(b"text\r\n\x1b[0m\n\xff\x00", sp.read_output());
+    }
+
+    #[test]
+    fn application__run_with_env__no_fancy__plain_output() {
+        let cmds = [Command { name: "cmd", handler: dummy_fancy_handler, ..Command::DEFAULT }];
+        let app = Application { name: "my-app", commands: &cmds, ..Application::DEFAULT };
+        let run = |args: &[&str], ep: &mut env::Virtual| {
+            let mut sp = stream::Virtual::new();
+            ep.set_terminal(env::Stream::Output, true);
+            app.run_with_env(&mut sp, ep, args.iter().map(|s| s.to_string()).collect());
+            String::from_utf8(sp.read_output().to_vec()).unwrap()
+        };
+
+        let mut ep = env::Virtual::new();
+        assert_eq!("\x1b[1mfancy=true\x1b[0m\n", run(&["my-app", "cmd"], &mut ep));
+        assert_eq!("fancy=false\n", run(&["my-app", "--no-fancy", "cmd"], &mut ep));
+        ep.set_var("MY_APP_NO_FANCY", "1");
+        assert_eq!("fancy=false\n", run(&["my-app", "cmd"], &mut ep));
+    }
+
+    #[test]
+    fn application__run__crlf_line_endings__converts_output() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_warning_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, line_endings: lines::Ending::CrLf, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("Warning: careful\r\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__secret_omitted__prompts_and_scrubs() {
+        let mut sp = stream::Virtual::new();
+        sp.write_input(b"hunter2\n");
+        let mut ep = env::Virtual::new();
+        ep.set_terminal(env::Stream::Input, true);
+        let params = [Parameter { name: "TOKEN", kind: ParamKind::Secret, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "login", params: &params, handler: dummy_reject_token_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "login".to_string()]);
+
+        assert_eq!(1, exit_code);
+        assert!(ep.echo());
+        assert_eq!(
+            "TOKEN: \nError: token '***' was rejected\nUsage: app login [TOKEN]\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__required_secret_omitted__prompts() {
+        let mut sp = stream::Virtual::new();
+        sp.write_input(b"hunter2\n");
+        let mut ep = env::Virtual::new();
+        ep.set_terminal(env::Stream::Input, true);
+        let params = [
+            Parameter { name: "USER", required: true, ..Parameter::DEFAULT },
+            Parameter { name: "TOKEN", required: true, kind: ParamKind::Secret, ..Parameter::DEFAULT },
+        ];
+        let cmds = [Command { name: "login", params: &params, handler: dummy_reject_token_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app", "login", "me"].into_iter().map(String::from).collect());
+
+        assert_eq!(1, exit_code);
+        assert_eq!(
+            "TOKEN: \nError: token '***' was rejected\nUsage: app login USER TOKEN\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__required_secret_omitted_not_terminal__argument_error() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        let params = [Parameter { name: "TOKEN", required: true, kind: ParamKind::Secret, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "login", params: &params, handler: dummy_reject_token_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "login".to_string()]);
+
+        assert_eq!(1, exit_code);
+        assert_eq!("Usage: app login TOKEN\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__indirect_stdin_and_escape__resolved() {
+        let mut sp = stream::Virtual::new();
+        sp.write_input(b"line 1\nline 2\n");
+        let params = [Parameter { name: "FILE", repeating: true, indirect: true, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "cmd", params: &params, handler: dummy_print_files_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app", "cmd", "-", "@@home", "plain"].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("line 1\nline 2,@home,plain\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__indirect_file__read_or_argument_error() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.add_file(Path::new("/work/token.txt"), b"from file\r\n");
+        let params = [Parameter { name: "FILE", indirect: true, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "cmd", params: &params, handler: dummy_print_files_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = |arg: &str| vec!["app", "-C", "/work", "cmd", arg].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, args("@token.txt"));
+        let (missing_exit_code, _) = app.run_with_env(&mut sp, &mut ep, args("@missing.txt"));
+
+        assert_eq!(0, exit_code);
+        assert_eq!(1, missing_exit_code);
+        assert_eq!("from file\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert!(::std::str::from_utf8(sp.read_error()).unwrap().starts_with("Error: Unable to read 'missing.txt'"));
+    }
+
+    #[test]
+    fn application__run__secret_from_stdin__resolved_and_scrubbed() {
+        let mut sp = stream::Virtual::new();
+        sp.write_input(b"hunter2\n");
+        let params = [Parameter { name: "TOKEN", kind: ParamKind::Secret, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "login", params: &params, handler: dummy_reject_token_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "login".to_string(), "-".to_string()]);
+
+        assert_eq!(1, exit_code);
+        assert_eq!(
+            "Error: token '***' was rejected\nUsage: app login [TOKEN]\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__debug_parse_secret__masked() {
+        let mut sp = stream::Virtual::new();
+        let params = [Parameter { name: "TOKEN", kind: ParamKind::Secret, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "login", params: &params, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app", "--debug-parse", "__route", "login", "hunter2"].into_iter().map(String::from).collect();
+
+        app.run(&mut sp, args);
+        let args = vec!["app", "--debug-parse", "login", "hunter2"].into_iter().map(String::from).collect();
+        app.run(&mut sp, args);
+
+        assert_eq!("command: login\nTOKEN: ***\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!(
+            "parse: trying form 0: login [TOKEN]\nparse: [TOKEN] <- ***\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__debug_parse_option__explains_assignment() {
+        let mut sp = stream::Virtual::new();
+        let params = [
+            Parameter { name: "OPT", ..Parameter::DEFAULT },
+            Parameter { name: "LIST", required: true, repeating: true, value_delimiter: Some(','), ..Parameter::DEFAULT },
+            Parameter { name: "LAST", required: true, ..Parameter::DEFAULT }];
+        let form = [Parameter { name: "ONLY", required: true, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "cmd", params: &params, forms: &[&form], ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app", "--debug-parse", "cmd", "a,b", "c"].into_iter().map(String::from).collect();
+
+        app.run(&mut sp, args);
+
+        assert_eq!("\
+            parse: trying form 0: cmd [OPT] LIST... LAST\n\
+            parse: [OPT] <- (none; 2 arguments reserved for required parameters)\n\
+            parse: LIST... <- a,b\n\
+            parse: LIST... split at ',' into a b\n\
+            parse: LAST <- c\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__help_option__prints_usage_to_stdout() {
+        for option in &["--help", "--usage"] {
+            let args = vec!["app".to_string(), option.to_string()];
+
+            let sp = test_application_run(0, None, args);
+
+            assert!(::std::str::from_utf8(sp.read_output()).unwrap().starts_with("Usage: app [OPTIONS] COMMAND [ARGS]\n"));
+            assert_eq!(0, sp.read_error().len());
+        }
+    }
+
+    #[test]
+    fn application__run__invalid_env_option__prints_error() {
+        let args = vec!["app".to_string(), "--env".to_string(), "=x".to_string(), "cmd1".to_string()];
+
+        let sp = test_application_run(1, None, args);
+
+        assert_eq!(
+            "Error: Invalid value '=x' for option '--env': expected KEY=VALUE\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__commands_builtin__lists_names() {
+        let args = vec!["app".to_string(), "commands".to_string()];
+
+        let sp = test_application_run(0, None, args);
+
+        assert_eq!("cmd1\ncmd2\ncmd3\ncmd4\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run__commands_builtin_with_params__lists_usage() {
+        let args = vec!["app".to_string(), "commands".to_string(), "--params".to_string()];
+
+        let sp = test_application_run(0, None, args);
+
+        assert_eq!(
+            "cmd1 param1\ncmd2 param1\ncmd3 param1\ncmd4 param1\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run__commands_builtin_json__prints_json() {
+        let mut sp = stream::Virtual::new();
+        let params = [Parameter { name: "FILE", required: false, repeating: true, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "cmd", short_desc: "desc", params: &params, handler: dummy_success_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app".to_string(), "commands".to_string(), "--json".to_string(), "--params".to_string()];
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!(
+            "[{\"name\":\"cmd\",\"params\":[{\"name\":\"FILE\",\"required\":false,\"repeating\":true}]}]\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__usage_string_with_topics__lists_topics() {
+        let topics = [HelpTopic { name: "config", title: "the configuration file format", text: "..." }];
+        let app = Application { name: "app", topics: &topics, ..Application::DEFAULT };
+
+        let result = app.usage_string();
+
+        assert!(result.contains("\n\nhelp topics:\nconfig                  the configuration file format\n\noptions:"));
+    }
+
+    #[test]
+    fn application__run__help_builtin__prints_topic_or_command() {
+        let mut sp = stream::Virtual::new();
+        let topics = [HelpTopic { name: "config", title: "the configuration file format", text: "Lines are KEY=VALUE.\n" }];
+        let cmds = [Command { name: "cmd", short_desc: "does things", ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, topics: &topics, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "help".to_string(), "config".to_string()]);
+        assert_eq!(0, exit_code);
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "help".to_string(), "cmd".to_string()]);
+        assert_eq!(0, exit_code);
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "help".to_string(), "nope".to_string()]);
+        assert_eq!(1, exit_code);
+
+        assert_eq!(
+            "the configuration file format\n\nLines are KEY=VALUE.\nUsage: app cmd\n\ndoes things\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!(
+            "Error: No command or help topic named 'nope'\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__help_builtin_json__prints_json() {
+        let mut sp = stream::Virtual::new();
+        let topics = [HelpTopic { name: "config", title: "the config file", text: "Lines are KEY=VALUE.\n" }];
+        let cmds = [Command { name: "cmd", short_desc: "does things", long_desc: "Uses `files`.", ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, topics: &topics, ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "help".to_string(), "--json".to_string()]);
+        assert_eq!(0, exit_code);
+        let output = ::std::str::from_utf8(sp.read_output()).unwrap().to_string();
+        let mut sp = stream::Virtual::new();
+        let args = vec!["app", "help", "cmd", "--json"].into_iter().map(String::from).collect();
+        let (exit_code, _) = app.run(&mut sp, args);
+        assert_eq!(0, exit_code);
+
+        assert!(output.starts_with("{\"name\":\"app\",\"usage\":\"Usage: app [OPTIONS] COMMAND [ARGS]\\n"));
+        assert!(output.ends_with(
+            "\"commands\":[{\"name\":\"cmd\",\"usage\":\"Usage: app cmd\",\"short_desc\":\"does things\",\
+            \"long_desc\":\"Uses `files`.\",\"mutates\":false,\"requires_network\":false}],\"topics\":[{\"name\":\"config\",\"title\":\"the config file\",\
+            \"text\":\"Lines are KEY=VALUE.\\n\"}]}\n"));
+        assert_eq!(
+            "{\"name\":\"cmd\",\"usage\":\"Usage: app cmd\",\"short_desc\":\"does things\",\"long_desc\":\"Uses `files`.\",\"mutates\":false,\"requires_network\":false}\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__help_offline_bundle__prints_from_bundle() {
+        let topics = [HelpTopic { name: "config", title: "the config file", text: "Lines are KEY=VALUE.\n" }];
+        let cmds = [Command { name: "cmd", short_desc: "does things", long_desc: "Uses `files`.", ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, topics: &topics, ..Application::DEFAULT };
+        let mut ep = env::Virtual::new();
+        let run = |args: &[&str], ep: &mut env::Virtual| {
+            let mut sp = stream::Virtual::new();
+            let (exit_code, _) = app.run_with_env(&mut sp, ep, args.iter().map(|s| s.to_string()).collect());
+            (exit_code, String::from_utf8(sp.read_output().to_vec()).unwrap(),
+                String::from_utf8(sp.read_error().to_vec()).unwrap())
+        };
+
+        let (exit_code, bundle, _) = run(&["app", "help", "--bundle"], &mut ep);
+        assert_eq!(0, exit_code);
+        assert_eq!(app.help_bundle(), bundle);
+        ep.add_file(Path::new("/app.help"), bundle.as_bytes());
+        ep.add_file(Path::new("/damaged.help"), bundle.replace("does things", "does other things").as_bytes());
+
+        for name in &["cmd", "config"] {
+            let expected = run(&["app", "help", name], &mut ep);
+            assert_eq!(expected, run(&["app", "help", "--offline-bundle", "/app.help", name], &mut ep));
+        }
+        assert_eq!(run(&["app", "help"], &mut ep), run(&["app", "help", "--offline-bundle", "/app.help"], &mut ep));
+        assert_eq!(
+            (1, String::new(), "Error: No command or help topic named 'nope'\n".to_string()),
+            run(&["app", "help", "--offline-bundle", "/app.help", "nope"], &mut ep));
+        assert_eq!(
+            (2, String::new(), "Error: '/damaged.help' isn't a help bundle, or is damaged\n".to_string()),
+            run(&["app", "help", "--offline-bundle", "/damaged.help"], &mut ep));
+        assert!(bundle.starts_with("command-cli-help-bundle app "));
+    }
+
+    #[test]
+    fn application__run_with_env__help_builtin_with_long_desc__renders_description() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("COLUMNS", "20");
+        let cmds = [Command {
+            name: "cmd",
+            short_desc: "does things",
+            long_desc: "Does things with `files`,\nwhich can be long.\n\n- one\n- two",
+            ..Command::DEFAULT
+        }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app".to_string(), "help".to_string(), "cmd".to_string()];
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!(
+            "Usage: app cmd\n\ndoes things\n\nDoes things with\n`files`, which can\nbe long.\n\n  - one\n  - two\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run__help_mutating_command__says_so() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "rm", short_desc: "removes things", mutates: true, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app", "help", "rm"].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("Usage: app rm\n\nremoves things\n\nThis command modifies state.\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run__offline__network_commands_refused() {
+        let cmds = [
+            Command { name: "fetch", handler: dummy_offline_handler, requires_network: true, ..Command::DEFAULT },
+            Command { name: "local", handler: dummy_offline_handler, ..Command::DEFAULT },
+        ];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let run = |args: &[&str]| {
+            let mut sp = stream::Virtual::new();
+            let (exit_code, _) = app.run(&mut sp, args.iter().map(|s| s.to_string()).collect());
+            (exit_code, String::from_utf8(sp.read_output().to_vec()).unwrap(), String::from_utf8(sp.read_error().to_vec()).unwrap())
+        };
+
+        assert_eq!((0, "offline=false\n".to_string(), String::new()), run(&["app", "fetch"]));
+        assert_eq!((0, "offline=true\n".to_string(), String::new()), run(&["app", "--offline", "local"]));
+        assert_eq!((1, String::new(), "Error: 'fetch' needs network access, which --offline rules out\n".to_string()),
+            run(&["app", "--offline", "fetch"]));
+    }
+
+    #[test]
+    fn application__run_with_env__sandbox__writes_outside_refused() {
+        let params = [Parameter { name: "PATH", required: true, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "save", params: &params, handler: dummy_write_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let mut ep = env::Virtual::new();
+        ep.add_path(Path::new("/work/out"));
+        ep.add_path(Path::new("/etc"));
+        let mut run = |args: &[&str]| {
+            let mut sp = stream::Virtual::new();
+            let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, args.iter().map(|s| s.to_string()).collect());
+            (exit_code, String::from_utf8(sp.read_error().to_vec()).unwrap())
+        };
+
+        assert_eq!((0, String::new()), run(&["app", "-C", "/work", "--sandbox", ".", "save", "out/a.txt"]));
+        assert_eq!((2, "Warning: Refusing to write '/work/../etc/passwd', which is outside the sandbox '/work'\n\
+            Error: '/work/../etc/passwd' couldn't be written\n".to_string()),
+            run(&["app", "-C", "/work", "--sandbox", ".", "save", "../etc/passwd"]));
+        assert_eq!((2, "Error: Unable to use sandbox directory '/nope': No such file or directory\n".to_string()),
+            run(&["app", "--sandbox", "/nope", "save", "a.txt"]));
+        assert_eq!((0, String::new()), run(&["app", "save", "/etc/passwd"]));
+    }
+
+    #[test]
+    fn application__run_with_env__confirm_mutating__asks_or_needs_confirm() {
+        let cmds = [Command { name: "drop", handler: dummy_success_handler, mutates: true, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, confirm_mutating: true, ..Application::DEFAULT };
+        let run = |args: &[&str], input: Option<&[u8]>| {
+            let mut sp = stream::Virtual::new();
+            let mut ep = env::Virtual::new();
+            if let Some(input) = input {
+                sp.write_input(input);
+                ep.set_terminal(env::Stream::Input, true);
+            }
+            let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, args.iter().map(|s| s.to_string()).collect());
+            (exit_code, String::from_utf8(sp.read_error().to_vec()).unwrap())
+        };
+
+        assert_eq!((0, "'drop' modifies state. Continue? [y/N] ".to_string()), run(&["app", "drop"], Some(b"y\n")));
+        assert_eq!((1, "'drop' modifies state. Continue? [y/N] Error: Not confirmed\nUsage: app drop\n".to_string()),
+            run(&["app", "drop"], Some(b"\n")));
+        assert_eq!((1, "Error: 'drop' modifies state, so it needs --confirm=drop when stdin isn't a terminal\nUsage: app drop\n".to_string()),
+            run(&["app", "drop"], None));
+        assert_eq!((0, String::new()), run(&["app", "--confirm=drop", "drop"], None));
+        assert_eq!((1, "Error: --confirm=dorp doesn't match the command 'drop'\nUsage: app drop\n".to_string()),
+            run(&["app", "--confirm=dorp", "drop"], None));
+    }
+
+    #[test]
+    fn application__run__route_builtin__prints_bound_arguments() {
+        let mut sp = stream::Virtual::new();
+        let params = [
+            Parameter { name: "SOURCE", required: true, repeating: true, ..Parameter::DEFAULT },
+            Parameter { name: "DEST", required: true, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "cp", params: &params, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app", "__route", "cp", "a.txt", "b c.txt", "out/"].into_iter().map(String::from).collect();
+
+        let (exit_code, cmd_opt) = app.run(&mut sp, args);
+
+        assert_eq!(0, exit_code);
+        assert!(cmd_opt.is_none());
+        assert_eq!(
+            "command: cp\nSOURCE: a.txt 'b c.txt'\nDEST: out/\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run__route_builtin_renamed_command__reports_new_name() {
+        let mut sp = stream::Virtual::new();
+        let params = [Parameter { name: "FILE", required: true, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "new", params: &params, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, renamed_commands: &[("old", "new")], ..Application::DEFAULT };
+        let args = vec!["app", "__route", "old", "a.txt"].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("command: new\nFILE: a.txt\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run__route_builtin_mismatched_args__prints_usage() {
+        let args = vec!["app", "__route", "cmd1"].into_iter().map(String::from).collect();
+
+        let sp = test_application_run(1, None, args);
+
+        assert_eq!(0, sp.read_output().len());
+        assert_eq!(
+            "Error: Arguments don't match any form of 'cmd1'\nUsage: app cmd1 param1\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__builtin_disabled__unrecognized() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("APP_NO_ROUTE", "1");
+        let cmds = [Command { name: "cmd", ..Command::DEFAULT }];
+        let app = Application {
+            name: "app",
+            commands: &cmds,
+            features: FrameworkFeatures::ALL.without(FrameworkFeatures::COMMANDS_COMMAND),
+            ..Application::DEFAULT
+        };
+
+        let (route_exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "__route".to_string()]);
+        let (commands_exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "commands".to_string()]);
+
+        assert_eq!(ARGUMENT_ERROR_EXIT_CODE, route_exit_code);
+        assert_eq!(ARGUMENT_ERROR_EXIT_CODE, commands_exit_code);
+        assert!(::std::str::from_utf8(sp.read_error()).unwrap().starts_with("Error: Unrecognized command '__route'\n"));
+    }
+
+    #[test]
+    fn application__run__renamed_command__warns_and_runs() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "new", handler: dummy_warning_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, renamed_commands: &[("old", "new")], ..Application::DEFAULT };
+
+        let (exit_code, cmd_opt) = app.run(&mut sp, vec!["app".to_string(), "old".to_string()]);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("new", cmd_opt.unwrap().name);
+        assert_eq!(
+            "Warning: 'old' has been renamed to 'new'\nWarning: careful\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__run__rewrite_args__applied_before_parsing() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [Command { name: "cmd", handler: dummy_print_env_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, rewrite_args: Some(legacy_rewrite), ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "--old-cmd".to_string()]);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("rewritten\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__opts_var__prepended_and_visible() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("APP_OPTS", "--env 'GREETING=hi there'");
+        let cmds = [Command { name: "cmd", handler: dummy_injected_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, opts_var: Some("APP_OPTS"), ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("hi there\n--env|GREETING=hi there\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__run_with_env__opts_var_unbalanced__argument_error() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("APP_OPTS", "--env 'A=b");
+        let cmds = [Command { name: "cmd", handler: dummy_injected_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, opts_var: Some("APP_OPTS"), ..Application::DEFAULT };
+
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut ep, vec!["app".to_string(), "cmd".to_string()]);
+
+        assert_eq!(1, exit_code);
+        assert_eq!("Error: Unable to parse APP_OPTS: unterminated single quote\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    fn check_balanced(pattern: &str) -> Result<(), String> {
+        if pattern.matches('(').count() == pattern.matches(')').count() {
+            Ok(())
+        } else {
+            Err("unclosed group".to_string())
+        }
+    }
+
+    fn legacy_rewrite(args: Vec<String>) -> Vec<String> {
+        args.into_iter()
+            .flat_map(|arg| match &arg[..] {
+                "--old-cmd" => vec!["--env".to_string(), "GREETING=rewritten".to_string(), "cmd".to_string()],
+                _ => vec![arg],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn application__validate__renamed_to_missing_command__error() {
+        let app = Application { name: "app", renamed_commands: &[("old", "new")], ..Application::DEFAULT };
 
-    fn index(&self, index: &S) -> &Vec<String> {
-        &self.param_to_args[index]
+        let result = app.validate();
+
+        assert_eq!(Err("Command 'old' is renamed to 'new', which doesn't exist".to_string()), result);
     }
-}
 
+    #[test]
+    fn application__validate__regex_without_check_pattern__error() {
+        let form: &[Parameter] = &[Parameter { name: "PATTERN", kind: ParamKind::Regex, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "grep", forms: &[form], ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
 
-#[cfg(test)]
-#[allow(non_snake_case)]
-mod tests {
-    use super::*;
-    use std::io;
-    use io_providers::stream;
+        let result = app.validate();
+
+        assert_eq!(Err("Command 'grep' has a Regex parameter, but check_pattern isn't set".to_string()), result);
+    }
 
     #[test]
-    fn application__print_usage__success() {
+    fn application__run__invalid_pattern__argument_error() {
         let mut sp = stream::Virtual::new();
-        let params1: [Parameter; 2] = [
-            Parameter { name: "PARAM1", required: true, repeating: true },
-            Parameter { name: "PARAM2", required: false, repeating: false }];
-        let params2: [Parameter; 0] = [];
-        let cmds: [Command; 2] = [
-            Command { name: "cmd1", short_desc: "desc1", params: &params1, handler: dummy_success_handler },
-            Command { name: "cmd2", short_desc: "desc2", params: &params2, handler: dummy_success_handler }];
-        let app: Application = Application { name: "app", commands: &cmds };
-        let expected = format!("\
-            Usage: app COMMAND [ARGS]\n\n\
-            commands:\n\
-            cmd1                    desc1\n\
-            cmd2                    desc2\n");
+        let params = [Parameter { name: "PATTERN", required: true, kind: ParamKind::Regex, ..Parameter::DEFAULT }];
+        let cmds = [Command { name: "grep", params: &params, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, check_pattern: Some(check_balanced), ..Application::DEFAULT };
+        let args = vec!["app", "grep", "(a|b"].into_iter().map(String::from).collect();
 
-        app.print_usage(&mut sp);
+        let (exit_code, _) = app.run(&mut sp, args);
 
-        assert_eq!(0, sp.read_output().len());
-        assert_eq!(&expected, ::std::str::from_utf8(sp.read_error()).unwrap());
+        assert_eq!(1, exit_code);
+        assert_eq!(
+            "Error: Invalid pattern '(a|b': unclosed group\nUsage: app grep PATTERN\n",
+            ::std::str::from_utf8(sp.read_error()).unwrap());
     }
 
     #[test]
-    fn application__run__empty_args__prints_usage() {
-        let args = vec!["app".to_string()];
+    fn application__validate__bad_stdin_param__error() {
+        let params = [
+            Parameter { name: "FILE", repeating: true, ..Parameter::DEFAULT },
+            Parameter { name: "NAME", required: true, ..Parameter::DEFAULT }];
+        let missing = [Command { name: "cmd", params: &params, stdin_param: Some("PATH"), ..Command::DEFAULT }];
+        let required = [Command { name: "cmd", params: &params, stdin_param: Some("NAME"), ..Command::DEFAULT }];
+        let valid = [Command { name: "cmd", params: &params, stdin_param: Some("FILE"), ..Command::DEFAULT }];
+        let app = |cmds| Application { name: "app", commands: cmds, ..Application::DEFAULT };
 
-        let sp = test_application_run(1, None, args);
+        assert_eq!(
+            Err("Command 'cmd' reads stdin into 'PATH', which isn't one of its parameters".to_string()),
+            app(&missing).validate());
+        assert_eq!(
+            Err("Command 'cmd' reads stdin into 'NAME', which isn't optional and repeating".to_string()),
+            app(&required).validate());
+        assert_eq!(Ok(()), app(&valid).validate());
+    }
 
-        assert_eq!(0, sp.read_output().len());
+    #[test]
+    fn application__validate__verb_noun__consistent() {
+        let validate = |cmds: &[Command]| Application { name: "app", commands: cmds, ..Application::DEFAULT }.validate();
+        let get_pod = || Command { name: "get-pod", verb: Some("get"), noun: Some("pod"), ..Command::DEFAULT };
+
+        assert_eq!(Ok(()), validate(&[get_pod(), Command { name: "ls", ..Command::DEFAULT }]));
+        assert_eq!(
+            Err("Commands 'get-pod' and 'pods' both get pod".to_string()),
+            validate(&[get_pod(), Command { name: "pods", ..get_pod() }]));
+        assert_eq!(
+            Err("Command 'get' has a verb or a noun, but not both".to_string()),
+            validate(&[Command { name: "get", verb: Some("get"), ..Command::DEFAULT }]));
+        assert_eq!(
+            Err("Command 'get-pod' has a verb or noun which isn't a single word".to_string()),
+            validate(&[Command { noun: Some("pod set"), ..get_pod() }]));
+    }
+
+    #[test]
+    fn application__run__help_matrix__verbs_by_nouns() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [
+            Command { name: "get-pod", verb: Some("get"), noun: Some("pod"), ..Command::DEFAULT },
+            Command { name: "rm-pod", verb: Some("delete"), noun: Some("pod"), ..Command::DEFAULT },
+            Command { name: "services", verb: Some("get"), noun: Some("service"), ..Command::DEFAULT },
+            Command { name: "status", ..Command::DEFAULT },
+        ];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let args = vec!["app", "help", "--matrix"].into_iter().map(String::from).collect();
+
+        let (exit_code, _) = app.run(&mut sp, args);
+
+        assert_eq!(0, exit_code);
         assert_eq!("\
-            Usage: app COMMAND [ARGS]\n\n\
-            commands:\n\
-            cmd1                    desc1\n\
-            cmd2                    desc2\n\
-            cmd3                    desc3\n\
-            cmd4                    desc4\n",
-            ::std::str::from_utf8(sp.read_error()).unwrap());
+            \x20        get       delete\n\
+            pod      get-pod   rm-pod\n\
+            service  services  -\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
     }
 
+    const HOST_FIELDS: ParamGroup = ParamGroup {
+        params: &[
+            Parameter { name: "ADDRESS", required: true, ..Parameter::DEFAULT },
+            Parameter { name: "ALIAS", repeating: true, ..Parameter::DEFAULT },
+        ],
+    };
+
     #[test]
-    fn application__run__invalid_command__prints_unrecognized_command() {
-        let args = vec!["app".to_string(), "badcmd".to_string()];
+    fn resource_commands__all_verbs__consistent_commands() {
+        let cmds = resource_commands!("host", HOST_FIELDS, {
+            list: dummy_print_args_handler,
+            get: dummy_print_args_handler,
+            create: dummy_print_args_handler,
+            update: dummy_print_args_handler,
+            delete: dummy_print_args_handler,
+        });
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        let usages: Vec<String> = cmds.iter().map(|cmd| format!("{} ({})", cmd.usage_string("app"), cmd.short_desc)).collect();
+
+        assert_eq!(Ok(()), app.validate());
+        assert_eq!(vec![
+            "Usage: app list-host (lists every host)",
+            "Usage: app get-host ID (shows the host with the given ID)",
+            "Usage: app create-host ADDRESS [ALIAS]... (creates a host)",
+            "Usage: app update-host ID ADDRESS [ALIAS]... (changes the host with the given ID)",
+            "Usage: app delete-host ID (deletes the host with the given ID)",
+        ], usages);
+        assert!(cmds.iter().all(|cmd| cmd.noun == Some("host") && cmd.name.starts_with(cmd.verb.unwrap())));
+        assert_eq!(vec![false, false, true, true, true], cmds.iter().map(|cmd| cmd.mutates).collect::<Vec<_>>());
+    }
 
-        let sp = test_application_run(1, None, args);
+    #[test]
+    fn application__validate__reserved_name__error() {
+        let params: [Parameter; 0] = [];
+        let cmds = [Command { name: "commands", short_desc: "desc", params: &params, handler: dummy_success_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, reserved_names: ReservedNamePolicy::Error, ..Application::DEFAULT };
 
-        assert_eq!(
-            "Error: Unrecognized command 'badcmd'\n",
-            ::std::str::from_utf8(sp.read_error()).unwrap());
+        let result = app.validate();
+
+        assert_eq!(Err("Command 'commands' uses a name reserved for a built-in command".to_string()), result);
     }
 
     #[test]
-    fn application__run__invalid_args__prints_usage() {
-        let args = vec!["app".to_string(), "cmd1".to_string()];
+    fn application__run__reserved_name_override__runs_command() {
+        let mut sp = stream::Virtual::new();
+        let params: [Parameter; 0] = [];
+        let cmds = [Command { name: "commands", short_desc: "desc", params: &params, handler: dummy_print_env_handler, ..Command::DEFAULT }];
+        let app = Application {
+            name: "app",
+            commands: &cmds,
+            reserved_names: ReservedNamePolicy::Override,
+            ..Application::DEFAULT
+        };
 
-        let sp = test_application_run(1, Some("cmd1"), args);
+        let (exit_code, cmd_opt) = app.run(&mut sp, vec!["app".to_string(), "__commands".to_string()]);
 
-        assert_eq!(0, sp.read_output().len());
-        assert_eq!(
-            "Usage: app cmd1 param1\n",
-            ::std::str::from_utf8(sp.read_error()).unwrap());
+        assert_eq!(1, exit_code);
+        assert!(cmd_opt.is_none());
+
+        let (exit_code, cmd_opt) = app.run(&mut sp, vec!["app".to_string(), "commands".to_string()]);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("commands", cmd_opt.unwrap().name);
     }
 
     #[test]
-    fn application__run__handler_success__success() {
-        let args = vec!["app".to_string(), "cmd1".to_string(), "arg1".to_string()];
+    fn application__run__reserved_name_rename__runs_prefixed_builtin() {
+        let mut sp = stream::Virtual::new();
+        let params: [Parameter; 0] = [];
+        let cmds = [Command { name: "commands", short_desc: "desc", params: &params, handler: dummy_success_handler, ..Command::DEFAULT }];
+        let app = Application {
+            name: "app",
+            commands: &cmds,
+            reserved_names: ReservedNamePolicy::Rename,
+            ..Application::DEFAULT
+        };
 
-        let sp = test_application_run(0, Some("cmd1"), args);
+        let (exit_code, cmd_opt) = app.run(&mut sp, vec!["app".to_string(), "__commands".to_string()]);
 
-        assert_eq!(0, sp.read_output().len());
-        assert_eq!(0, sp.read_error().len());
+        assert_eq!(0, exit_code);
+        assert!(cmd_opt.is_none());
+        assert_eq!("commands\n", ::std::str::from_utf8(sp.read_output()).unwrap());
     }
 
     #[test]
-    fn application__run__handler_arg_error__prints_usage() {
-        let args = vec!["app".to_string(), "cmd2".to_string(), "arg1".to_string()];
+    fn application__run__unrecognized_option__prints_error() {
+        let args = vec!["app".to_string(), "--bad".to_string(), "cmd1".to_string()];
 
-        let sp = test_application_run(1, Some("cmd2"), args);
+        let sp = test_application_run(1, None, args);
 
         assert_eq!(0, sp.read_output().len());
-        assert_eq!(
-            "Usage: app cmd2 param1\n",
-            ::std::str::from_utf8(sp.read_error()).unwrap());
+        assert_eq!("Error: Unrecognized option '--bad'\n", ::std::str::from_utf8(sp.read_error()).unwrap());
     }
 
     #[test]
-    fn application__run__handler_exec_error__success() {
-        let args = vec!["app".to_string(), "cmd3".to_string(), "arg1".to_string()];
+    fn application__run__missing_option_value__prints_error() {
+        let args = vec!["app".to_string(), "-C".to_string()];
 
-        let sp = test_application_run(2, Some("cmd3"), args);
+        let sp = test_application_run(1, None, args);
 
-        assert_eq!(0, sp.read_output().len());
-        assert_eq!(0, sp.read_error().len());
+        assert_eq!("Error: Missing value for option '-C'\n", ::std::str::from_utf8(sp.read_error()).unwrap());
     }
 
     #[test]
-    fn application__run__handler_exec_error_with_inner__prints_inner() {
-        let args = vec!["app".to_string(), "cmd4".to_string(), "arg1".to_string()];
+    fn application__run__sanitize_escape__escapes_handler_output() {
+        let mut sp = stream::Virtual::new();
+        let params: [Parameter; 0] = [];
+        let cmds = [Command { name: "cmd", short_desc: "desc", params: &params, handler: dummy_control_chars_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, sanitize: sanitize::Mode::Escape, ..Application::DEFAULT };
 
-        let sp = test_application_run(2, Some("cmd4"), args);
+        let (exit_code, _) = app.run(&mut sp, vec!["app".to_string(), "cmd".to_string()]);
 
-        assert_eq!(0, sp.read_output().len());
-        assert_eq!(
-            "Inner error: :(\n",
-            ::std::str::from_utf8(sp.read_error()).unwrap());
+        assert_eq!(0, exit_code);
+        assert_eq!("\\x1b]0;title\\x07out\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!("\\x1b[2Jerr\n", ::std::str::from_utf8(sp.read_error()).unwrap());
     }
 
     #[test]
     fn command__display__success() {
         let params: [Parameter; 2] = [
-            Parameter { name: "PARAM1", required: true, repeating: true },
-            Parameter { name: "PARAM2", required: false, repeating: false }];
-        let cmd = Command { name: "cmd", short_desc: "desc", params: &params, handler: dummy_success_handler };
+            Parameter { name: "PARAM1", required: true, repeating: true, ..Parameter::DEFAULT },
+            Parameter { name: "PARAM2", required: false, repeating: false, ..Parameter::DEFAULT }];
+        let cmd = Command { name: "cmd", short_desc: "desc", params: &params, handler: dummy_success_handler, ..Command::DEFAULT };
         let expected = format!("cmd {} {}", params[0], params[1]);
 
         let result = format!("{}", cmd);
@@ -462,11 +3343,23 @@ mod tests {
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn command__display_with_params_from__includes_group_params() {
+        let own = [Parameter { name: "NAME", required: true, ..Parameter::DEFAULT }];
+        let shared = [Parameter { name: "FILE", repeating: true, ..Parameter::DEFAULT }];
+        let group = ParamGroup { params: &shared };
+        let cmd = Command { name: "cmd", params: &own, params_from: &[&group], ..Command::DEFAULT };
+
+        let result = format!("{}", cmd);
+
+        assert_eq!("cmd NAME [FILE]...", result);
+    }
+
     #[test]
     fn command__print_usage__success() {
         let mut sp = stream::Virtual::new();
         let params: [Parameter; 0] = [];
-        let cmd = Command { name: "cmd", short_desc: "desc", params: &params, handler: dummy_success_handler };
+        let cmd = Command { name: "cmd", short_desc: "desc", params: &params, handler: dummy_success_handler, ..Command::DEFAULT };
         let expected = format!("Usage: app {}\n", cmd);
 
         cmd.print_usage(&mut sp, "app");
@@ -475,11 +3368,74 @@ mod tests {
         assert_eq!(&expected, ::std::str::from_utf8(sp.read_error()).unwrap());
     }
 
+    #[test]
+    fn command__usage_string__success() {
+        let params: [Parameter; 1] = [Parameter { name: "PARAM", required: false, repeating: true, ..Parameter::DEFAULT }];
+        let cmd = Command { name: "cmd", short_desc: "desc", params: &params, handler: dummy_success_handler, ..Command::DEFAULT };
+
+        let result = cmd.usage_string("app");
+
+        assert_eq!("Usage: app cmd [PARAM]...", result);
+    }
+
+    #[test]
+    fn command__usage_string_with_override__uses_override() {
+        let cmd = Command { name: "cmd", usage_override: Some("app cmd FILE\n       app cmd --all"), ..Command::DEFAULT };
+
+        let result = cmd.usage_string("app");
+
+        assert_eq!("Usage: app cmd FILE\n       app cmd --all", result);
+    }
+
+    #[test]
+    fn command__usage_string_with_forms__lists_forms() {
+        let params = [Parameter { name: "BRANCH", required: true, ..Parameter::DEFAULT }];
+        let form = [
+            Parameter { name: "COMMIT", required: true, ..Parameter::DEFAULT },
+            Parameter { name: "FILE", required: true, repeating: true, ..Parameter::DEFAULT }];
+        let cmd = Command { name: "checkout", params: &params, forms: &[&form], ..Command::DEFAULT };
+
+        let result = cmd.usage_string("app");
+
+        assert_eq!("Usage: app checkout BRANCH\n       app checkout COMMIT FILE...", result);
+    }
+
+    #[test]
+    fn command__parse_arguments__tries_forms_in_order() {
+        let params = [Parameter { name: "BRANCH", required: true, ..Parameter::DEFAULT }];
+        let form = [
+            Parameter { name: "COMMIT", required: true, ..Parameter::DEFAULT },
+            Parameter { name: "FILE", required: true, repeating: true, ..Parameter::DEFAULT }];
+        let cmd = Command { name: "checkout", params: &params, forms: &[&form], ..Command::DEFAULT };
+
+        let (_, first) = cmd.parse_arguments(vec!["app", "checkout", "b"].into_iter().map(String::from).collect())
+            .unwrap();
+        let (second_params, second) = cmd.parse_arguments(
+            vec!["app", "checkout", "c", "f1", "f2"].into_iter().map(String::from).collect())
+            .unwrap();
+
+        assert_eq!(0, first.form());
+        assert_eq!(vec!["b".to_string()], first["BRANCH"]);
+        assert_eq!(1, second.form());
+        assert_eq!(2, second_params.len());
+        assert_eq!(vec!["f1".to_string(), "f2".to_string()], second["FILE"]);
+    }
+
+    #[test]
+    fn command__short_desc_string__success() {
+        let params: [Parameter; 0] = [];
+        let cmd = Command { name: "cmd", short_desc: "the short desc", params: &params, handler: dummy_success_handler, ..Command::DEFAULT };
+
+        let result = cmd.short_desc_string();
+
+        assert_eq!("cmd                     the short desc", result);
+    }
+
     #[test]
     fn command__print_short_desc__success() {
         let mut sp = stream::Virtual::new();
         let params: [Parameter; 0] = [];
-        let cmd = Command { name: "cmd", short_desc: "the short desc", params: &params, handler: dummy_success_handler };
+        let cmd = Command { name: "cmd", short_desc: "the short desc", params: &params, handler: dummy_success_handler, ..Command::DEFAULT };
         let expected = "cmd                     the short desc\n".to_string();
 
         cmd.print_short_desc(&mut sp);
@@ -490,31 +3446,31 @@ mod tests {
 
     #[test]
     fn parameter__display_optional_nonrepeating__success() {
-        let param = Parameter { name: "PARAM", required: false, repeating: false };
+        let param = Parameter { name: "PARAM", required: false, repeating: false, ..Parameter::DEFAULT };
         test_param_display("[PARAM]", &param);
     }
 
     #[test]
     fn parameter__display_optional_repeating__success() {
-        let param = Parameter { name: "PARAM", required: false, repeating: true };
+        let param = Parameter { name: "PARAM", required: false, repeating: true, ..Parameter::DEFAULT };
         test_param_display("[PARAM]...", &param);
     }
 
     #[test]
     fn parameter__display_required_nonrepeating__success() {
-        let param = Parameter { name: "PARAM", required: true, repeating: false };
+        let param = Parameter { name: "PARAM", required: true, repeating: false, ..Parameter::DEFAULT };
         test_param_display("PARAM", &param);
     }
 
     #[test]
     fn parameter__display_required_repeating__success() {
-        let param = Parameter { name: "PARAM", required: true, repeating: true };
+        let param = Parameter { name: "PARAM", required: true, repeating: true, ..Parameter::DEFAULT };
         test_param_display("PARAM...", &param);
     }
 
     #[test]
     fn arguments__new__too_few_args__returns_none() {
-        let param = Parameter { name: "PARAM", required: true, repeating: false };
+        let param = Parameter { name: "PARAM", required: true, repeating: false, ..Parameter::DEFAULT };
         let params = &[param];
         let args = vec!["app".to_string(), "cmd".to_string()];
 
@@ -525,7 +3481,7 @@ mod tests {
 
     #[test]
     fn arguments__new__too_many_args__returns_none() {
-        let param = Parameter { name: "PARAM", required: true, repeating: false };
+        let param = Parameter { name: "PARAM", required: true, repeating: false, ..Parameter::DEFAULT };
         let params = &[param];
         let args = vec!["app".to_string(), "cmd".to_string(), "arg1".to_string(), "arg2".to_string()];
 
@@ -536,7 +3492,7 @@ mod tests {
 
     #[test]
     fn arguments__new__optional_param_and_no_args__returns_empty() {
-        let params = &[Parameter { name: "PARAM", required: false, repeating: false }];
+        let params = &[Parameter { name: "PARAM", required: false, repeating: false, ..Parameter::DEFAULT }];
         let args = vec!["app".to_string(), "cmd".to_string()];
 
         let arguments = Arguments::new(params, args).unwrap();
@@ -547,8 +3503,8 @@ mod tests {
     #[test]
     fn arguments__new__required__success() {
         let params = &[
-            Parameter { name: "PARAM1", required: true, repeating: false },
-            Parameter { name: "PARAM2", required: true, repeating: false }];
+            Parameter { name: "PARAM1", required: true, repeating: false, ..Parameter::DEFAULT },
+            Parameter { name: "PARAM2", required: true, repeating: false, ..Parameter::DEFAULT }];
         let (arg1, arg2) = ("arg1".to_string(), "arg2".to_string());
         let args = vec!["app".to_string(), "cmd".to_string(), arg1.clone(), arg2.clone()];
 
@@ -560,7 +3516,7 @@ mod tests {
 
     #[test]
     fn arguments__new__repeating_param_and_args__success() {
-        let params = &[Parameter { name: "PARAM", required: true, repeating: true }];
+        let params = &[Parameter { name: "PARAM", required: true, repeating: true, ..Parameter::DEFAULT }];
         let (arg1, arg2) = ("arg1".to_string(), "arg2".to_string());
         let args = vec!["app".to_string(), "cmd".to_string(), arg1.clone(), arg2.clone()];
 
@@ -572,8 +3528,8 @@ mod tests {
     #[test]
     fn arguments__new__repeating_then_required__success() {
         let params = &[
-            Parameter { name: "PARAM1", required: true, repeating: true },
-            Parameter { name: "PARAM2", required: true, repeating: false }];
+            Parameter { name: "PARAM1", required: true, repeating: true, ..Parameter::DEFAULT },
+            Parameter { name: "PARAM2", required: true, repeating: false, ..Parameter::DEFAULT }];
         let (arg1, arg2, arg3) = ("arg1".to_string(), "arg2".to_string(), "arg3".to_string());
         let args = vec!["app".to_string(), "cmd".to_string(), arg1.clone(), arg2.clone(), arg3.clone()];
 
@@ -586,8 +3542,8 @@ mod tests {
     #[test]
     fn arguments__new__required_then_repeating__success() {
         let params = &[
-            Parameter { name: "PARAM1", required: true, repeating: false },
-            Parameter { name: "PARAM2", required: true, repeating: true }];
+            Parameter { name: "PARAM1", required: true, repeating: false, ..Parameter::DEFAULT },
+            Parameter { name: "PARAM2", required: true, repeating: true, ..Parameter::DEFAULT }];
         let (arg1, arg2, arg3) = ("arg1".to_string(), "arg2".to_string(), "arg3".to_string());
         let args = vec!["app".to_string(), "cmd".to_string(), arg1.clone(), arg2.clone(), arg3.clone()];
 
@@ -600,8 +3556,8 @@ mod tests {
     #[test]
     fn arguments__new__optional_then_required_with_one_arg__success() {
         let params = &[
-            Parameter { name: "PARAM1", required: false, repeating: false },
-            Parameter {  name: "PARAM2", required: true, repeating: false }];
+            Parameter { name: "PARAM1", required: false, repeating: false, ..Parameter::DEFAULT },
+            Parameter {  name: "PARAM2", required: true, repeating: false, ..Parameter::DEFAULT }];
         let arg1 = "arg1".to_string();
         let args = vec!["app".to_string(), "cmd".to_string(), arg1.clone()];
 
@@ -614,8 +3570,8 @@ mod tests {
     #[test]
     fn arguments__new__optional_then_required_with_two_args__success() {
         let params = &[
-            Parameter { name: "PARAM1", required: false, repeating: false },
-            Parameter { name: "PARAM2", required: true, repeating: false }];
+            Parameter { name: "PARAM1", required: false, repeating: false, ..Parameter::DEFAULT },
+            Parameter { name: "PARAM2", required: true, repeating: false, ..Parameter::DEFAULT }];
         let (arg1, arg2) = ("arg1".to_string(), "arg2".to_string());
         let args = vec!["app".to_string(), "cmd".to_string(), arg1.clone(), arg2.clone()];
 
@@ -628,8 +3584,8 @@ mod tests {
     #[test]
     fn arguments__new__required_then_optional_with_one_arg__success() {
         let params = &[
-            Parameter { name: "PARAM1", required: true, repeating: false },
-            Parameter { name: "PARAM2", required: false, repeating: false }];
+            Parameter { name: "PARAM1", required: true, repeating: false, ..Parameter::DEFAULT },
+            Parameter { name: "PARAM2", required: false, repeating: false, ..Parameter::DEFAULT }];
         let arg1 = "arg1".to_string();
         let args = vec!["app".to_string(), "cmd".to_string(), arg1.clone()];
 
@@ -642,8 +3598,8 @@ mod tests {
     #[test]
     fn arguments__new__required_then_optional_with_two_args__success() {
         let params = &[
-            Parameter { name: "PARAM1", required: true, repeating: false },
-            Parameter { name: "PARAM2", required: false, repeating: false }];
+            Parameter { name: "PARAM1", required: true, repeating: false, ..Parameter::DEFAULT },
+            Parameter { name: "PARAM2", required: false, repeating: false, ..Parameter::DEFAULT }];
         let (arg1, arg2) = ("arg1".to_string(), "arg2".to_string());
         let args = vec!["app".to_string(), "cmd".to_string(), arg1.clone(), arg2.clone()];
 
@@ -653,6 +3609,45 @@ mod tests {
         assert_eq!(vec![arg2], arguments[params[1].name]);
     }
 
+    #[test]
+    fn arguments__new__value_delimiter__splits_repeating_only() {
+        let params = &[
+            Parameter { name: "ONE", required: true, value_delimiter: Some(','), ..Parameter::DEFAULT },
+            Parameter { name: "MANY", repeating: true, value_delimiter: Some(','), ..Parameter::DEFAULT }];
+        let args = vec!["app", "cmd", "x,y", "a,b", "c"].into_iter().map(String::from).collect();
+
+        let result = Arguments::new(params, args).unwrap();
+
+        assert_eq!(vec!["x,y".to_string()], result["ONE"]);
+        assert_eq!(vec!["a".to_string(), "b".to_string(), "c".to_string()], result["MANY"]);
+    }
+
+    #[test]
+    fn arguments__new__sort_and_dedup__raw_keeps_original() {
+        let params = &[
+            Parameter { name: "SORTED", repeating: true, sort: true, dedup: true, value_delimiter: Some(','), ..Parameter::DEFAULT },
+            Parameter { name: "DEDUPED", required: true, repeating: true, dedup: true, value_delimiter: Some(','), ..Parameter::DEFAULT }];
+        let args = vec!["app", "cmd", "c,a,c,b", "z,y,z"].into_iter().map(String::from).collect();
+
+        let result = Arguments::new(params, args).unwrap();
+
+        assert_eq!(vec!["a", "b", "c"], result["SORTED"]);
+        assert_eq!(vec!["c", "a", "c", "b"], result.raw("SORTED"));
+        assert_eq!(vec!["z", "y"], result["DEDUPED"]);
+        assert_eq!(vec!["z", "y", "z"], result.raw("DEDUPED"));
+    }
+
+    #[test]
+    fn arguments__count__success() {
+        let params = &[Parameter { name: "PARAM", repeating: true, ..Parameter::DEFAULT }];
+        let args = vec!["app", "cmd", "a", "b", "c"].into_iter().map(String::from).collect();
+
+        let result = Arguments::new(params, args).unwrap();
+
+        assert_eq!(3, result.count("PARAM"));
+        assert_eq!(0, result.count("OTHER"));
+    }
+
     fn test_application_run(
         expected_exit_code: i32,
         expected_cmd_name: Option<&str>,
@@ -671,9 +3666,11 @@ mod tests {
                             name: "param1",
                             required: true,
                             repeating: false,
+                            ..Parameter::DEFAULT
                         },
                     ],
                     handler: dummy_success_handler,
+                    ..Command::DEFAULT
                 },
                 Command {
                     name: "cmd2",
@@ -683,9 +3680,11 @@ mod tests {
                             name: "param1",
                             required: true,
                             repeating: false,
+                            ..Parameter::DEFAULT
                         },
                     ],
                     handler: dummy_arg_error_handler,
+                    ..Command::DEFAULT
                 },
                 Command {
                     name: "cmd3",
@@ -695,9 +3694,11 @@ mod tests {
                             name: "param1",
                             required: true,
                             repeating: false,
+                            ..Parameter::DEFAULT
                         },
                     ],
                     handler: dummy_exec_error_handler,
+                    ..Command::DEFAULT
                 },
                 Command {
                     name: "cmd4",
@@ -707,11 +3708,14 @@ mod tests {
                             name: "param1",
                             required: true,
                             repeating: false,
+                            ..Parameter::DEFAULT
                         },
                     ],
                     handler: dummy_exec_error_with_inner_handler,
+                    ..Command::DEFAULT
                 },
             ],
+            ..Application::DEFAULT
         };
 
         let (exit_code, cmd_opt) = app.run(&mut sp, args);
@@ -725,28 +3729,224 @@ mod tests {
         sp
     }
 
+    /// Records the working directory after each change.
+    struct DirLog {
+        inner: env::Virtual,
+        log: Vec<PathBuf>,
+    }
+
+    impl env::Provider for DirLog {
+        fn current_dir(&self) -> io::Result<PathBuf> {
+            self.inner.current_dir()
+        }
+
+        fn set_current_dir(&mut self, path: &Path) -> io::Result<()> {
+            try!(self.inner.set_current_dir(path));
+            self.log.push(self.inner.current_dir().unwrap());
+            Ok(())
+        }
+
+        fn var(&self, key: &str) -> Option<String> {
+            self.inner.var(key)
+        }
+
+        fn is_terminal(&self, stream: env::Stream) -> bool {
+            self.inner.is_terminal(stream)
+        }
+
+        fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+            self.inner.canonicalize(path)
+        }
+
+        fn set_echo(&mut self, echo: bool) -> io::Result<()> {
+            self.inner.set_echo(echo)
+        }
+
+        fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.inner.read_file(path)
+        }
+
+        fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            self.inner.write_file(path, contents)
+        }
+
+        fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+            self.inner.create_dir_all(path)
+        }
+
+        fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+            self.inner.rename(from, to)
+        }
+
+        fn now(&self) -> SystemTime {
+            self.inner.now()
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.inner.sleep(duration)
+        }
+
+        fn random(&mut self) -> u64 {
+            self.inner.random()
+        }
+
+        fn create_temp_dir(&mut self) -> io::Result<PathBuf> {
+            self.inner.create_temp_dir()
+        }
+
+        fn remove_dir_all(&mut self, path: &Path) -> io::Result<()> {
+            self.inner.remove_dir_all(path)
+        }
+    }
+
     fn test_param_display(expected: &str, param: &Parameter) {
         let result = format!("{}", param);
         assert_eq!(expected, &result);
     }
 
     #[allow(unused_variables)]
-    fn dummy_success_handler(sp: &mut stream::Provider, args: &Arguments) -> CommandResult {
+    fn dummy_success_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_control_chars_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        writeln!(ctx.output(), "\x1b]0;title\x07out").unwrap();
+        writeln!(ctx.error(), "\x1b[2Jerr").unwrap();
+        CommandResult::Success
+    }
+
+    fn dummy_echo_handler(ctx: &mut Context, _args: &Arguments) -> CommandResult {
+        let mut input = String::new();
+        ctx.input().read_to_string(&mut input).unwrap();
+        write!(ctx.output(), "{} ({} chars)", input, input.chars().count()).unwrap();
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_print_files_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        writeln!(ctx.output(), "{}", args["FILE"].join(",")).unwrap();
+        CommandResult::Success
+    }
+
+    fn dummy_print_args_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        writeln!(ctx.output(), "{}\n{}", args["TEXT"][0], args["PATH"][0]).unwrap();
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_sleep_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        ctx.env().sleep(Duration::from_millis(1500));
+        CommandResult::Success
+    }
+
+    fn dummy_write_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        let path = ctx.env().current_dir().unwrap().join(&args["PATH"][0]);
+        match ctx.write_file(&path, b"data") {
+            Ok(()) => CommandResult::Success,
+            Err(_) => {
+                writeln!(ctx.error(), "Error: '{}' couldn't be written", path.display()).unwrap();
+                CommandResult::ExecutionError(None)
+            },
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_offline_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        let offline = ctx.offline();
+        writeln!(ctx.output(), "offline={}", offline).unwrap();
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_binary_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        writeln!(ctx.output(), "\x1b[1mtext").unwrap();
+        ctx.stdout_raw().write_all(b"\x1b[0m\n\xff\x00").unwrap();
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_injected_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        let greeting = ctx.env().var("GREETING").unwrap_or_default();
+        let injected = ctx.injected_args().join("|");
+        writeln!(ctx.output(), "{}\n{}", greeting, injected).unwrap();
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_print_env_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        let greeting = ctx.env().var("GREETING").unwrap_or_default();
+        writeln!(ctx.output(), "{}", greeting).unwrap();
         CommandResult::Success
     }
 
     #[allow(unused_variables)]
-    fn dummy_arg_error_handler(sp: &mut stream::Provider, args: &Arguments) -> CommandResult {
+    fn dummy_arg_error_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
         CommandResult::ArgumentError
     }
 
     #[allow(unused_variables)]
-    fn dummy_exec_error_handler(sp: &mut stream::Provider, args: &Arguments) -> CommandResult {
+    fn dummy_arg_error_with_message_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        CommandResult::argument_error("'0' is not a valid count")
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_warning_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        ctx.warn("careful");
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_partial_failure_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        CommandResult::PartialFailure { failed: 2, total: 5 }
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_print_raw_args_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        let raw_args = ctx.raw_args().join(" ");
+        writeln!(ctx.output(), "{}", raw_args).unwrap();
+        Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_count_runs_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        let runs = ctx.state().unwrap().get("runs").map_or(0, |runs| runs.parse().unwrap()) + 1;
+        ctx.set_state("runs", &runs.to_string()).unwrap();
+        writeln!(ctx.output(), "run {}", runs).unwrap();
+        Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_reject_token_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        CommandResult::argument_error(format!("token '{}' was rejected", args["TOKEN"][0]))
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_panic_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        panic!("boom")
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_fancy_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        let fancy = ctx.fancy();
+        writeln!(ctx.output(), "\x1b[1mfancy={}\x1b[0m", fancy).unwrap();
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_styled_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        writeln!(ctx.output(), "\x1b[31mred\x1b[0m\ttab").unwrap();
+        writeln!(ctx.error(), "\x1b[31mred\x1b[0m\ttab").unwrap();
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn dummy_exec_error_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
         CommandResult::ExecutionError(None)
     }
 
     #[allow(unused_variables)]
-    fn dummy_exec_error_with_inner_handler(sp: &mut stream::Provider, args: &Arguments) -> CommandResult {
+    fn dummy_exec_error_with_inner_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
         CommandResult::ExecutionError(Some(Box::new(io::Error::new(io::ErrorKind::Other, ":("))))
     }
 }
@@ -0,0 +1,112 @@
+//! Line-ending handling, so that line-oriented commands behave the same way on
+//! Windows as elsewhere.
+
+use std::io::{self, BufRead, BufReader, Read};
+use filter::Filter;
+
+/// Describes the line endings written to stdout and stderr.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Ending {
+    /// Text is written as is, i.e. normally with `\n` line endings.
+    Lf,
+    /// `\n` is written as `\r\n`.
+    CrLf,
+    /// `CrLf` on Windows, and `Lf` elsewhere.
+    Native,
+}
+
+impl Ending {
+    /// Resolves `Native` to the platform's line ending.
+    pub fn resolve(self) -> Ending {
+        match self {
+            Ending::Native if cfg!(windows) => Ending::CrLf,
+            Ending::Native => Ending::Lf,
+            ending => ending,
+        }
+    }
+}
+
+/// An iterator over the lines of a stream, without their `\n` or `\r\n` endings.
+pub struct Lines<'a> {
+    reader: BufReader<&'a mut Read>,
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                Some(Ok(line))
+            },
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Returns an iterator over the lines of `input` (e.g. `ctx.input()`), which
+/// may end in either `\n` or `\r\n`.
+pub fn lines<'a>(input: &'a mut Read) -> Lines<'a> {
+    Lines { reader: BufReader::new(input) }
+}
+
+/// A `Filter` which converts `\n` line endings to `\r\n`, leaving existing
+/// `\r\n` line endings alone.
+#[derive(Default)]
+pub struct CrLf {
+    after_cr: bool,
+}
+
+impl CrLf {
+    /// Constructs a `CrLf` filter.
+    pub fn new() -> CrLf {
+        CrLf { after_cr: false }
+    }
+}
+
+impl Filter for CrLf {
+    fn filter(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        for &b in buf {
+            if b == b'\n' && !self.after_cr {
+                out.push(b'\r');
+            }
+            out.push(b);
+            self.after_cr = b == b'\r';
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use filter::Filter;
+
+    #[test]
+    fn lines__mixed_endings__stripped() {
+        let mut input: &[u8] = b"a\r\nb\n\nc\rd";
+
+        let result: Vec<String> = lines(&mut input).map(|l| l.unwrap()).collect();
+
+        assert_eq!(vec!["a", "b", "", "c\rd"], result);
+    }
+
+    #[test]
+    fn crlf__filter__converts_lf_only() {
+        let mut filter = CrLf::new();
+        let mut out = Vec::new();
+
+        filter.filter(b"a\nb\r", &mut out);
+        filter.filter(b"\nc\n", &mut out);
+
+        assert_eq!(b"a\r\nb\r\nc\r\n", &out[..]);
+    }
+}
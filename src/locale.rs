@@ -0,0 +1,233 @@
+//! Locale-aware formatting of numbers and dates, for output meant for people.
+//! Output meant for scripts should keep to the fixed formats of `output`.
+//!
+//! Handlers get the locale to use from `Context::locale`, which honors
+//! `Application::locale` and otherwise looks at `LC_ALL` and then `LANG`.
+//!
+//! ## Example
+//!
+//! ```
+//! use command_cli::locale::Locale;
+//!
+//! assert_eq!("1,234,567", Locale::from_name("en_US.UTF-8").format_int(1234567));
+//! assert_eq!("1.234,5", Locale::from_name("de_DE").format_float(1234.5, 1));
+//! assert_eq!("1234567", Locale::C.format_int(1234567));
+//! ```
+
+use std::time::SystemTime;
+use datetime;
+use env;
+use output::Value;
+
+/// How a locale writes dates.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DateFormat {
+    /// `2024-02-29`
+    Iso,
+    /// `02/29/2024`
+    MonthDayYear,
+    /// `29.02.2024`, with the given separator.
+    DayMonthYear(char),
+}
+
+/// The conventions a locale uses for numbers and dates.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Locale {
+    /// Separates groups of three digits in the integer part, if at all.
+    pub thousands_separator: Option<char>,
+    pub decimal_separator: char,
+    pub date_format: DateFormat,
+}
+
+impl Locale {
+    /// The `C` (or `POSIX`) locale, which formats numbers and dates like `output`.
+    pub const C: Locale = Locale { thousands_separator: None, decimal_separator: '.', date_format: DateFormat::Iso };
+
+    /// Returns the locale with the given name, e.g. `de_DE.UTF-8`. Locales this
+    /// module doesn't know are treated as `C`.
+    pub fn from_name(name: &str) -> Locale {
+        let name = name.split(['.', '@']).next().unwrap();
+        let mut parts = name.splitn(2, '_');
+        let language = parts.next().unwrap();
+        let territory = parts.next().unwrap_or("");
+
+        match language {
+            "en" if territory.is_empty() || territory == "US" => Locale {
+                thousands_separator: Some(','),
+                decimal_separator: '.',
+                date_format: DateFormat::MonthDayYear,
+            },
+            "en" => Locale {
+                thousands_separator: Some(','),
+                decimal_separator: '.',
+                date_format: DateFormat::DayMonthYear('/'),
+            },
+            "de" => Locale {
+                thousands_separator: Some('.'),
+                decimal_separator: ',',
+                date_format: DateFormat::DayMonthYear('.'),
+            },
+            "es" | "it" | "pt" => Locale {
+                thousands_separator: Some('.'),
+                decimal_separator: ',',
+                date_format: DateFormat::DayMonthYear('/'),
+            },
+            "fr" => Locale {
+                thousands_separator: Some('\u{202f}'),
+                decimal_separator: ',',
+                date_format: DateFormat::DayMonthYear('/'),
+            },
+            "nl" => Locale {
+                thousands_separator: Some('.'),
+                decimal_separator: ',',
+                date_format: DateFormat::DayMonthYear('-'),
+            },
+            _ => Locale::C,
+        }
+    }
+
+    /// Returns the locale named by `LC_ALL`, or else by `LANG`, or else `C`.
+    pub fn from_env(ep: &env::Provider) -> Locale {
+        match ep.var("LC_ALL").into_iter().chain(ep.var("LANG")).find(|name| !name.is_empty()) {
+            Some(name) => Locale::from_name(&name),
+            None => Locale::C,
+        }
+    }
+
+    /// Formats a whole number, e.g. `1,234,567`.
+    pub fn format_int(&self, n: i64) -> String {
+        let digits = n.unsigned_abs().to_string();
+        let mut s = if n < 0 { "-".to_string() } else { String::new() };
+        s.push_str(&self.group(&digits));
+        s
+    }
+
+    /// Formats a number with `decimals` digits after the decimal separator, e.g.
+    /// `1,234.50`.
+    pub fn format_float(&self, x: f64, decimals: usize) -> String {
+        if !x.is_finite() {
+            return x.to_string();
+        }
+
+        let formatted = format!("{:.*}", decimals, x.abs());
+        let (int_part, frac_part) = match formatted.find('.') {
+            Some(i) => (&formatted[..i], &formatted[i + 1..]),
+            None => (&formatted[..], ""),
+        };
+
+        let mut s = if x < 0.0 && formatted.chars().any(|c| c != '0' && c != '.') { "-".to_string() } else { String::new() };
+        s.push_str(&self.group(int_part));
+        if !frac_part.is_empty() {
+            s.push(self.decimal_separator);
+            s.push_str(frac_part);
+        }
+        s
+    }
+
+    /// Formats the date of `time` in UTC, e.g. `02/29/2024`.
+    pub fn format_date(&self, time: SystemTime) -> String {
+        let (year, month, day) = datetime::date_of(time);
+        match self.date_format {
+            DateFormat::Iso => format!("{:04}-{:02}-{:02}", year, month, day),
+            DateFormat::MonthDayYear => format!("{:02}/{:02}/{:04}", month, day, year),
+            DateFormat::DayMonthYear(sep) => format!("{:02}{}{:02}{}{:04}", day, sep, month, sep, year),
+        }
+    }
+
+    /// Formats a record value for display, formatting numbers as described above
+    /// (floats with as many decimals as they need) and anything else as usual.
+    pub fn format_value(&self, value: &Value) -> String {
+        match *value {
+            Value::Int(n) => self.format_int(n),
+            Value::Float(x) if x.is_finite() => {
+                let plain = x.abs().to_string();
+                let decimals = plain.find('.').map_or(0, |i| plain.len() - i - 1);
+                self.format_float(x, decimals)
+            },
+            ref value => value.to_string(),
+        }
+    }
+
+    /// Inserts the thousands separator into a string of digits.
+    fn group(&self, digits: &str) -> String {
+        let sep = match self.thousands_separator {
+            Some(sep) => sep,
+            None => return digits.to_string(),
+        };
+
+        let mut s = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                s.push(sep);
+            }
+            s.push(c);
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+    use env;
+
+    #[test]
+    fn locale__format_int__grouped() {
+        let en = Locale::from_name("en_US.UTF-8");
+
+        assert_eq!("0", en.format_int(0));
+        assert_eq!("999", en.format_int(999));
+        assert_eq!("1,000", en.format_int(1000));
+        assert_eq!("-12,345,678", en.format_int(-12345678));
+        assert_eq!("-9,223,372,036,854,775,808", en.format_int(i64::MIN));
+        assert_eq!("1\u{202f}000", Locale::from_name("fr_FR").format_int(1000));
+        assert_eq!("1000", Locale::C.format_int(1000));
+    }
+
+    #[test]
+    fn locale__format_float__separators() {
+        let de = Locale::from_name("de_DE@euro");
+
+        assert_eq!("1.234,50", de.format_float(1234.5, 2));
+        assert_eq!("-0,5", de.format_float(-0.5, 1));
+        assert_eq!("0", de.format_float(-0.4, 0));
+        assert_eq!("1,234.5", Locale::from_name("en").format_float(1234.5, 1));
+        assert_eq!("inf", de.format_float(f64::INFINITY, 2));
+    }
+
+    #[test]
+    fn locale__format_date__per_locale() {
+        let time = UNIX_EPOCH + Duration::from_secs(1709164800);
+
+        assert_eq!("02/29/2024", Locale::from_name("en_US").format_date(time));
+        assert_eq!("29/02/2024", Locale::from_name("en_GB").format_date(time));
+        assert_eq!("29.02.2024", Locale::from_name("de_AT").format_date(time));
+        assert_eq!("2024-02-29", Locale::from_name("POSIX").format_date(time));
+    }
+
+    #[test]
+    fn locale__format_value__numbers_localized() {
+        let de = Locale::from_name("de_DE");
+
+        assert_eq!("12.345", de.format_value(&Value::Int(12345)));
+        assert_eq!("1.234,25", de.format_value(&Value::Float(1234.25)));
+        assert_eq!("a.b", de.format_value(&Value::from("a.b")));
+    }
+
+    #[test]
+    fn locale__from_env__lc_all_then_lang() {
+        let mut ep = env::Virtual::new();
+        assert_eq!(Locale::C, Locale::from_env(&ep));
+
+        ep.set_var("LANG", "de_DE.UTF-8");
+        assert_eq!(Locale::from_name("de_DE"), Locale::from_env(&ep));
+
+        ep.set_var("LC_ALL", "en_US.UTF-8");
+        assert_eq!(Locale::from_name("en_US"), Locale::from_env(&ep));
+
+        ep.set_var("LC_ALL", "");
+        assert_eq!(Locale::from_name("de_DE"), Locale::from_env(&ep));
+    }
+}
@@ -0,0 +1,123 @@
+//! Rendering of the lightweight markup used in long descriptions: paragraphs
+//! separated by blank lines, bullet lists (lines starting with `- ` or `* `),
+//! and `inline code` in backticks.
+
+/// ANSI sequences used to style inline code on terminals.
+const CODE_START: &str = "\x1b[1m";
+const CODE_END: &str = "\x1b[0m";
+
+/// Renders `text`, reflowing paragraphs and list items to fit within `width`
+/// columns. If `styled`, inline code is shown in bold rather than in backticks.
+pub fn render(text: &str, width: usize, styled: bool) -> String {
+    let mut blocks = Vec::new();
+    for block in text.split("\n\n").map(str::trim).filter(|b| !b.is_empty()) {
+        let mut lines = Vec::new();
+        for item in split_items(block) {
+            match item {
+                Item::Paragraph(words) => lines.extend(wrap(&words, width, "", "", styled)),
+                Item::Bullet(words) => lines.extend(wrap(&words, width, "  - ", "    ", styled)),
+            }
+        }
+        blocks.push(lines.join("\n"));
+    }
+    blocks.join("\n\n")
+}
+
+enum Item<'a> {
+    Paragraph(Vec<&'a str>),
+    Bullet(Vec<&'a str>),
+}
+
+/// Splits a block into a leading paragraph (if any) followed by bullet items.
+fn split_items<'a>(block: &'a str) -> Vec<Item<'a>> {
+    let mut items: Vec<Item> = Vec::new();
+    for line in block.lines().map(str::trim) {
+        if line.starts_with("- ") || line.starts_with("* ") {
+            items.push(Item::Bullet(line[2..].split_whitespace().collect()));
+            continue;
+        }
+
+        if items.is_empty() {
+            items.push(Item::Paragraph(Vec::new()));
+        }
+        match items.last_mut() {
+            Some(&mut Item::Paragraph(ref mut words)) | Some(&mut Item::Bullet(ref mut words)) => {
+                words.extend(line.split_whitespace())
+            },
+            None => unreachable!(),
+        }
+    }
+    items
+}
+
+/// Wraps `words` into lines of at most `width` columns (unless a word is longer),
+/// prefixing the first line with `first_indent` and the rest with `indent`.
+fn wrap(words: &[&str], width: usize, first_indent: &str, indent: &str, styled: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = first_indent.to_string();
+    let mut line_width = first_indent.len();
+    let mut line_empty = true;
+    let mut in_code = false;
+
+    for word in words {
+        let word_width = word.chars().filter(|&c| !styled || c != '`').count();
+        if !line_empty && line_width + 1 + word_width > width {
+            lines.push(line);
+            line = indent.to_string();
+            line_width = indent.len();
+            line_empty = true;
+        }
+
+        if !line_empty {
+            line.push(' ');
+            line_width += 1;
+        }
+        for c in word.chars() {
+            match c {
+                '`' if styled => {
+                    line.push_str(if in_code { CODE_END } else { CODE_START });
+                    in_code = !in_code;
+                },
+                c => line.push(c),
+            }
+        }
+        line_width += word_width;
+        line_empty = false;
+    }
+
+    if !line_empty {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render__paragraphs__reflowed() {
+        let text = "The quick brown\nfox jumps over the lazy dog.\n\nSecond paragraph.";
+
+        let result = render(text, 20, false);
+
+        assert_eq!("The quick brown fox\njumps over the lazy\ndog.\n\nSecond paragraph.", result);
+    }
+
+    #[test]
+    fn render__bullets__hanging_indent() {
+        let text = "Options are:\n- first item which is long\n* second";
+
+        let result = render(text, 20, false);
+
+        assert_eq!("Options are:\n  - first item which\n    is long\n  - second", result);
+    }
+
+    #[test]
+    fn render__styled_code__bold_without_backticks() {
+        let result = render("run `app foo` now", 80, true);
+
+        assert_eq!("run \x1b[1mapp foo\x1b[0m now", result);
+    }
+}
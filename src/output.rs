@@ -0,0 +1,1050 @@
+//! Machine-readable output of records, for commands whose output is meant to be
+//! consumed by scripts as well as people.
+//!
+//! Handlers build a list of `Record`s and print them in whichever `Format` the
+//! user asked for, typically via an `--output`-style parameter, or through a
+//! user-supplied `Template` for `--format`-style parameters. A `Query` applies
+//! user-requested projection, filtering, sorting and limiting to the records
+//! beforehand. Commands with too many records to hold at once can write them
+//! one at a time with a `RecordWriter` instead.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # extern crate command_cli;
+//! use command_cli::{Arguments, CommandResult, Context};
+//! use command_cli::output::{self, Format, Record};
+//!
+//! // Handler for `app list [FORMAT]`
+//! fn list_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+//!     let format = match args["FORMAT"].iter().next() {
+//!         Some(name) => match Format::from_name(name) {
+//!             Some(format) => format,
+//!             None => return CommandResult::ArgumentError,
+//!         },
+//!         None => Format::Text,
+//!     };
+//!     let records = vec![
+//!         Record::new().field("name", "a.txt").field("size", 120),
+//!         Record::new().field("name", "b.txt").field("size", 4096),
+//!     ];
+//!
+//!     match output::print_records(ctx, format, &records) {
+//!         Ok(()) => CommandResult::Success,
+//!         Err(_) => CommandResult::ExecutionError(None),
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+
+use std::cmp::Ordering;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use io_providers::stream;
+#[cfg(feature = "csv")]
+use quote;
+
+/// A single value within a `Record`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Str(ref s) => f.write_str(s),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value { Value::Bool(b) }
+}
+
+impl From<i32> for Value {
+    fn from(i: i32) -> Value { Value::Int(i as i64) }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value { Value::Int(i) }
+}
+
+impl From<u32> for Value {
+    fn from(i: u32) -> Value { Value::Int(i as i64) }
+}
+
+impl From<u64> for Value {
+    fn from(i: u64) -> Value { Value::Int(i as i64) }
+}
+
+impl From<usize> for Value {
+    fn from(i: usize) -> Value { Value::Int(i as i64) }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Value { Value::Float(x) }
+}
+
+impl<'a> From<&'a str> for Value {
+    fn from(s: &'a str) -> Value { Value::Str(s.to_string()) }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value { Value::Str(s) }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(o: Option<T>) -> Value { o.map_or(Value::Null, Into::into) }
+}
+
+/// An ordered collection of named values, e.g. one row of a listing.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Record {
+    fields: Vec<(String, Value)>,
+}
+
+impl Record {
+    /// Constructs an empty `Record`.
+    pub fn new() -> Record {
+        Record { fields: Vec::new() }
+    }
+
+    /// Appends a field, replacing any existing field with the same key.
+    pub fn field<K: Into<String>, V: Into<Value>>(mut self, key: K, value: V) -> Record {
+        self.set(key, value);
+        self
+    }
+
+    /// Sets a field, replacing any existing field with the same key.
+    pub fn set<K: Into<String>, V: Into<Value>>(&mut self, key: K, value: V) {
+        let key = key.into();
+        let value = value.into();
+
+        match self.fields.iter().position(|(k, _)| *k == key) {
+            Some(i) => self.fields[i].1 = value,
+            None => self.fields.push((key, value)),
+        }
+    }
+
+    /// Gets the value of a field, if present.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// The record's fields, in insertion order.
+    pub fn fields(&self) -> &[(String, Value)] {
+        &self.fields
+    }
+}
+
+/// A serialization format for records.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// One line per record with tab-separated values and no header; friendly to `cut` and `awk`.
+    Text,
+    /// A JSON array of objects.
+    Json,
+    /// RFC 4180 CSV with a header row. Requires the `csv` Cargo feature.
+    #[cfg(feature = "csv")]
+    Csv,
+    /// A YAML sequence of mappings. Requires the `yaml` Cargo feature.
+    #[cfg(feature = "yaml")]
+    Yaml,
+}
+
+impl Format {
+    /// Looks up a format by the name a user would pass to an `--output` option.
+    /// The names of formats whose Cargo feature is disabled aren't recognized.
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            #[cfg(feature = "csv")]
+            "csv" => Some(Format::Csv),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Writes `records` in this format.
+    pub fn write(&self, w: &mut Write, records: &[Record]) -> io::Result<()> {
+        #[cfg(feature = "csv")]
+        if *self == Format::Csv {
+            return write_csv(w, records);
+        }
+
+        let mut writer = self.writer();
+        for record in records {
+            try!(writer.write(w, record));
+        }
+        writer.finish(w)
+    }
+
+    /// Returns a `RecordWriter` for writing records in this format one at a time.
+    pub fn writer(&self) -> RecordWriter {
+        RecordWriter {
+            format: *self,
+            count: 0,
+            #[cfg(feature = "csv")]
+            header: None,
+        }
+    }
+
+    /// Writes the records produced for each of several items, e.g. the files
+    /// given to a repeating parameter.
+    ///
+    /// As `Text`, each item's records follow a `==> item <==` header like those
+    /// of `head` and `tail`, unless there's only one item. Other formats write a
+    /// single list of records, each with the item as a leading `item` field; it's
+    /// an `InvalidInput` error for a record to have its own `item` field.
+    pub fn write_grouped(&self, w: &mut Write, groups: &[(&str, &[Record])]) -> io::Result<()> {
+        if *self == Format::Text {
+            for (i, &(item, records)) in groups.iter().enumerate() {
+                if groups.len() > 1 {
+                    try!(writeln!(w, "{}==> {} <==", if i > 0 { "\n" } else { "" }, item));
+                }
+                try!(Format::Text.write(w, records));
+            }
+            return Ok(());
+        }
+
+        let mut flattened = Vec::new();
+        for &(item, records) in groups {
+            for record in records {
+                if record.get("item").is_some() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "Record already has an 'item' field"));
+                }
+                let mut tagged = Record::new().field("item", item);
+                for (key, value) in record.fields() {
+                    tagged.set(key.clone(), value.clone());
+                }
+                flattened.push(tagged);
+            }
+        }
+        self.write(w, &flattened)
+    }
+}
+
+/// Writes records one at a time, so that they needn't all be held in memory.
+///
+/// The output is the same as `Format::write`'s, except that as `Csv`, the header
+/// is taken from the first record: fields which later records lack are left
+/// blank, and fields which only later records have are left out.
+///
+/// Each call is given the stream to write to, so that e.g. progress can be
+/// reported on stderr in between:
+///
+/// ```
+/// # use command_cli::output::{Format, Record};
+/// # use std::io::{self, Write};
+/// let mut writer = Format::Json.writer();
+/// for i in 0..3 {
+///     writer.write(&mut io::stdout(), &Record::new().field("i", i)).unwrap();
+///     writeln!(io::stderr(), "{} of 3 done", i + 1).unwrap();
+/// }
+/// writer.finish(&mut io::stdout()).unwrap();
+/// ```
+pub struct RecordWriter {
+    format: Format,
+    /// The number of records written so far.
+    count: usize,
+    /// The keys written as the CSV header.
+    #[cfg(feature = "csv")]
+    header: Option<Vec<String>>,
+}
+
+impl RecordWriter {
+    /// Writes one record.
+    pub fn write(&mut self, w: &mut Write, record: &Record) -> io::Result<()> {
+        self.count += 1;
+        match self.format {
+            Format::Text => {
+                let values: Vec<String> = record.fields().iter().map(|(_, v)| v.to_string()).collect();
+                writeln!(w, "{}", values.join("\t"))
+            },
+            Format::Json => write!(w, "{}  {}", if self.count == 1 { "[\n" } else { ",\n" }, json_object(record)),
+            #[cfg(feature = "csv")]
+            Format::Csv => {
+                if self.header.is_none() {
+                    let header: Vec<String> = record.fields().iter().map(|(k, _)| k.clone()).collect();
+                    let header_fields: Vec<String> = header.iter().map(|k| quote::csv(k)).collect();
+                    try!(write!(w, "{}\r\n", header_fields.join(",")));
+                    self.header = Some(header);
+                }
+                let fields: Vec<String> = self.header.as_ref().unwrap().iter()
+                    .map(|k| record.get(k).map_or(String::new(), |v| quote::csv(&v.to_string())))
+                    .collect();
+                write!(w, "{}\r\n", fields.join(","))
+            },
+            #[cfg(feature = "yaml")]
+            Format::Yaml => {
+                if record.fields().is_empty() {
+                    return writeln!(w, "- {{}}");
+                }
+                for (i, (key, value)) in record.fields().iter().enumerate() {
+                    let prefix = if i == 0 { "- " } else { "  " };
+                    try!(writeln!(w, "{}{}: {}", prefix, yaml_string(key), yaml_value(value)));
+                }
+                Ok(())
+            },
+        }
+    }
+
+    /// Writes whatever has to follow the last record, e.g. the end of a JSON array.
+    pub fn finish(self, w: &mut Write) -> io::Result<()> {
+        match self.format {
+            Format::Json if self.count == 0 => writeln!(w, "[]"),
+            Format::Json => writeln!(w, "\n]"),
+            #[cfg(feature = "yaml")]
+            Format::Yaml if self.count == 0 => writeln!(w, "[]"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Prints `records` to stdout in the given format.
+///
+/// Write errors are returned rather than panicking on, since e.g. piping the
+/// output into `head` closes stdout early.
+pub fn print_records(sp: &mut stream::Provider, format: Format, records: &[Record]) -> io::Result<()> {
+    format.write(sp.output(), records)
+}
+
+/// Prints the records produced for each of several items to stdout in the
+/// given format; see `Format::write_grouped`.
+pub fn print_grouped(sp: &mut stream::Provider, format: Format, groups: &[(&str, &[Record])]) -> io::Result<()> {
+    format.write_grouped(sp.output(), groups)
+}
+
+/// A user-supplied format string for records, like `docker ps --format`.
+///
+/// `{{.field}}` is replaced by the value of `field` (or nothing, if the record
+/// doesn't have it), and the escapes `\t`, `\n` and `\\` are recognized in the
+/// surrounding text so that templates can be written without shell-specific quoting.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Field(String),
+}
+
+impl Template {
+    /// Parses a template such as `{{.name}}\t{{.size}}`.
+    pub fn parse(template: &str) -> Result<Template, TemplateError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+
+        while !rest.is_empty() {
+            if rest.starts_with("{{") {
+                let offset = template.len() - rest.len();
+                let end = match rest.find("}}") {
+                    Some(end) => end,
+                    None => return Err(TemplateError::new(offset, "unterminated '{{'")),
+                };
+                let name = rest[2..end].trim();
+
+                if !name.starts_with('.') || name.len() == 1 {
+                    return Err(TemplateError::new(offset, "expected a field reference like '{{.name}}'"));
+                }
+
+                if !literal.is_empty() {
+                    parts.push(TemplatePart::Literal(literal));
+                    literal = String::new();
+                }
+                parts.push(TemplatePart::Field(name[1..].to_string()));
+                rest = &rest[end + 2..];
+            } else if rest.starts_with('\\') {
+                // Unrecognized escapes are kept as-is
+                let (c, len) = match rest[1..].chars().next() {
+                    Some('t') => ('\t', 2),
+                    Some('n') => ('\n', 2),
+                    Some('\\') => ('\\', 2),
+                    _ => ('\\', 1),
+                };
+                literal.push(c);
+                rest = &rest[len..];
+            } else {
+                let c = rest.chars().next().unwrap();
+                literal.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// Renders a record through the template.
+    pub fn render(&self, record: &Record) -> String {
+        let mut rendered = String::new();
+
+        for part in &self.parts {
+            match *part {
+                TemplatePart::Literal(ref s) => rendered.push_str(s),
+                TemplatePart::Field(ref name) => {
+                    if let Some(value) = record.get(name) {
+                        rendered.push_str(&value.to_string());
+                    }
+                },
+            }
+        }
+
+        rendered
+    }
+
+    /// Writes each record through the template, one per line.
+    pub fn write(&self, w: &mut Write, records: &[Record]) -> io::Result<()> {
+        for record in records {
+            try!(writeln!(w, "{}", self.render(record)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Describes why a template failed to parse.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemplateError {
+    /// The byte offset into the template at which the error was found.
+    pub position: usize,
+    message: &'static str,
+}
+
+impl TemplateError {
+    fn new(position: usize, message: &'static str) -> TemplateError {
+        TemplateError { position, message }
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid template at position {}: {}", self.position, self.message)
+    }
+}
+
+impl error::Error for TemplateError {
+    fn description(&self) -> &str {
+        self.message
+    }
+}
+
+/// User-requested post-processing of records, as given by `--fields`, `--filter`,
+/// `--sort-by` and `--limit`-style parameters.
+///
+/// Records are filtered, then sorted, then limited, and finally projected, so
+/// sorting and filtering can use fields which aren't selected for output.
+///
+/// ```
+/// # use command_cli::output::{Query, Record};
+/// let query = Query::new().fields("name").filter("kind=file").unwrap();
+/// let records = vec![
+///     Record::new().field("name", "a.txt").field("kind", "file"),
+///     Record::new().field("name", "src").field("kind", "dir"),
+/// ];
+///
+/// assert_eq!(vec![Record::new().field("name", "a.txt")], query.apply(records));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    fields: Option<Vec<String>>,
+    filters: Vec<(String, String)>,
+    sort_by: Option<(String, bool)>,
+    limit: Option<usize>,
+}
+
+impl Query {
+    /// Constructs a `Query` which leaves records unchanged.
+    pub fn new() -> Query {
+        Query { fields: None, filters: Vec::new(), sort_by: None, limit: None }
+    }
+
+    /// Restricts records to the given comma-separated fields (e.g. `name,size`), in that order.
+    pub fn fields(mut self, spec: &str) -> Query {
+        self.fields = Some(spec.split(',')
+            .map(|f| f.trim())
+            .filter(|f| !f.is_empty())
+            .map(String::from)
+            .collect());
+        self
+    }
+
+    /// Keeps only records whose field matches a `key=value` filter. Filters are cumulative.
+    pub fn filter(mut self, spec: &str) -> Result<Query, QueryError> {
+        match spec.find('=') {
+            Some(i) if i > 0 => {
+                self.filters.push((spec[..i].to_string(), spec[i + 1..].to_string()));
+                Ok(self)
+            },
+            _ => Err(QueryError(format!("invalid filter '{}': expected KEY=VALUE", spec))),
+        }
+    }
+
+    /// Sorts records by a field, given as `FIELD` or `FIELD:desc` (`FIELD:asc` is
+    /// also accepted). The sort is stable, and records lacking the field sort last.
+    pub fn sort_by(mut self, spec: &str) -> Result<Query, QueryError> {
+        let (field, descending) = match spec.rfind(':') {
+            Some(i) => match &spec[i + 1..] {
+                "asc" => (&spec[..i], false),
+                "desc" => (&spec[..i], true),
+                _ => return Err(QueryError(
+                    format!("invalid sort order in '{}': expected 'asc' or 'desc'", spec))),
+            },
+            None => (spec, false),
+        };
+
+        if field.is_empty() {
+            return Err(QueryError(format!("invalid sort '{}': expected FIELD[:desc]", spec)));
+        }
+
+        self.sort_by = Some((field.to_string(), descending));
+        Ok(self)
+    }
+
+    /// Keeps at most the first `n` records (after filtering and sorting).
+    pub fn limit(mut self, n: usize) -> Query {
+        self.limit = Some(n);
+        self
+    }
+
+    /// Applies the query to `records`.
+    pub fn apply(&self, records: Vec<Record>) -> Vec<Record> {
+        let mut records: Vec<Record> = records.into_iter().filter(|r| self.matches(r)).collect();
+
+        if let Some((ref field, descending)) = self.sort_by {
+            records.sort_by(|a, b| match (a.get(field), b.get(field)) {
+                (Some(x), Some(y)) if descending => compare_values(y, x),
+                (Some(x), Some(y)) => compare_values(x, y),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            });
+        }
+
+        if let Some(n) = self.limit {
+            records.truncate(n);
+        }
+
+        records.into_iter()
+            .map(|r| match self.fields {
+                Some(ref fields) => project(&r, fields),
+                None => r,
+            })
+            .collect()
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        self.filters.iter().all(|(key, value)| record.get(key).is_some_and(|v| v.to_string() == *value))
+    }
+}
+
+/// Describes an invalid `Query` specification given by the user.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryError(String);
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for QueryError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Orders numbers numerically and everything else by its text, with numbers first.
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (as_number(a), as_number(b)) {
+        (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match *value {
+        Value::Int(i) => Some(i as f64),
+        Value::Float(x) => Some(x),
+        _ => None,
+    }
+}
+
+fn project(record: &Record, fields: &[String]) -> Record {
+    let mut projected = Record::new();
+
+    for field in fields {
+        if let Some(value) = record.get(field) {
+            projected.set(field.clone(), value.clone());
+        }
+    }
+
+    projected
+}
+
+#[cfg(feature = "csv")]
+fn write_csv(w: &mut Write, records: &[Record]) -> io::Result<()> {
+    // Records needn't all have the same fields, so the header is every key in order of first use
+    let mut header: Vec<&str> = Vec::new();
+    for record in records {
+        for (key, _) in record.fields() {
+            if !header.contains(&&key[..]) {
+                header.push(key);
+            }
+        }
+    }
+
+    if header.is_empty() {
+        return Ok(());
+    }
+
+    let header_fields: Vec<String> = header.iter().map(|k| quote::csv(k)).collect();
+    try!(write!(w, "{}\r\n", header_fields.join(",")));
+
+    for record in records {
+        let fields: Vec<String> = header.iter()
+            .map(|k| record.get(k).map_or(String::new(), |v| quote::csv(&v.to_string())))
+            .collect();
+        try!(write!(w, "{}\r\n", fields.join(",")));
+    }
+
+    Ok(())
+}
+
+/// Serializes a record as a single-line JSON object.
+pub(crate) fn json_object(record: &Record) -> String {
+    let members: Vec<String> = record.fields().iter()
+        .map(|(k, v)| format!("{}: {}", json_string(k), json_value(v)))
+        .collect();
+    format!("{{{}}}", members.join(", "))
+}
+
+pub(crate) fn json_value(value: &Value) -> String {
+    match *value {
+        Value::Null => "null".to_string(),
+        Value::Float(x) if !x.is_finite() => "null".to_string(),
+        Value::Str(ref s) => json_string(s),
+        ref v => v.to_string(),
+    }
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(feature = "yaml")]
+fn yaml_value(value: &Value) -> String {
+    match *value {
+        Value::Null => "null".to_string(),
+        Value::Float(x) if x.is_nan() => ".nan".to_string(),
+        Value::Float(x) if x.is_infinite() => (if x > 0.0 { ".inf" } else { "-.inf" }).to_string(),
+        Value::Str(ref s) => yaml_string(s),
+        ref v => v.to_string(),
+    }
+}
+
+/// Quotes `s` unless it's certain to be read back as the same string: it has to
+/// start with an ASCII letter, so that e.g. `.inf`, `0x1F` and `2001-12-14` (which
+/// YAML reads as numbers and timestamps) are quoted.
+#[cfg(feature = "yaml")]
+fn yaml_string(s: &str) -> String {
+    let is_plain = s.starts_with(|c: char| c.is_ascii_alphabetic())
+        && !s.ends_with(' ')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || " _-./".contains(c))
+        && s.parse::<f64>().is_err()
+        && !is_yaml_keyword(s);
+
+    if is_plain {
+        s.to_string()
+    } else {
+        // Double-quoted YAML scalars accept JSON string escapes
+        json_string(s)
+    }
+}
+
+#[cfg(feature = "yaml")]
+fn is_yaml_keyword(s: &str) -> bool {
+    matches!(&s.to_lowercase()[..], "null" | "true" | "false" | "yes" | "no" | "on" | "off" | "y" | "n")
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use io_providers::stream::{self, Provider};
+
+    #[test]
+    fn record__set_existing_key__replaces_in_place() {
+        let mut record = Record::new().field("a", 1).field("b", 2);
+
+        record.set("a", "x");
+
+        assert_eq!(
+            &[("a".to_string(), Value::from("x")), ("b".to_string(), Value::from(2))],
+            record.fields());
+    }
+
+    #[test]
+    fn format__from_name__success() {
+        assert_eq!(Some(Format::Json), Format::from_name("json"));
+        assert_eq!(None, Format::from_name("xml"));
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn format__from_name_yml__yaml() {
+        assert_eq!(Some(Format::Yaml), Format::from_name("yml"));
+    }
+
+    #[test]
+    fn format__write_text__success() {
+        test_write(Format::Text, &sample_records(), "a.txt\t120\tnull-ish\nb,c\t4096\t\n");
+    }
+
+    #[test]
+    fn format__write_json__success() {
+        test_write(Format::Json, &sample_records(), "\
+            [\n  \
+              {\"name\": \"a.txt\", \"size\": 120, \"note\": \"null-ish\"},\n  \
+              {\"name\": \"b,c\", \"size\": 4096, \"note\": null}\n\
+            ]\n");
+    }
+
+    #[test]
+    fn format__write_json_empty__success() {
+        test_write(Format::Json, &[], "[]\n");
+    }
+
+    #[test]
+    fn format__write_json__escapes_strings() {
+        let records = [Record::new().field("k", "say \"hi\"\n\u{1}")];
+        test_write(Format::Json, &records, "[\n  {\"k\": \"say \\\"hi\\\"\\n\\u0001\"}\n]\n");
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn format__write_csv__success() {
+        test_write(Format::Csv, &sample_records(), "\
+            name,size,note\r\n\
+            a.txt,120,null-ish\r\n\
+            \"b,c\",4096,\r\n");
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn format__write_csv_mismatched_fields__fills_blanks() {
+        let records = [Record::new().field("a", 1), Record::new().field("b", 2)];
+        test_write(Format::Csv, &records, "a,b\r\n1,\r\n,2\r\n");
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn format__write_yaml__success() {
+        test_write(Format::Yaml, &sample_records(), "\
+            - name: a.txt\n  \
+              size: 120\n  \
+              note: null-ish\n\
+            - name: \"b,c\"\n  \
+              size: 4096\n  \
+              note: null\n");
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn format__write_yaml__quotes_ambiguous_strings() {
+        let records = [Record::new().field("a", "yes").field("b", "12").field("c", "-x").field("d", "")];
+        test_write(Format::Yaml, &records, "- a: \"yes\"\n  b: \"12\"\n  c: \"-x\"\n  d: \"\"\n");
+    }
+
+    #[test]
+    #[cfg(feature = "yaml")]
+    fn format__write_yaml__quotes_special_numbers_and_dates() {
+        let values = [".inf", ".nan", "0x1F", "0o17", "1_000", "2001-12-14", "inf", "~", "_x"];
+        let records: Vec<Record> = values.iter().map(|&v| Record::new().field("v", v)).collect();
+        let expected: String = values.iter().map(|v| format!("- v: \"{}\"\n", v)).collect();
+
+        test_write(Format::Yaml, &records, &expected);
+    }
+
+    #[test]
+    fn print_records__success() {
+        let mut sp = stream::Virtual::new();
+
+        print_records(&mut sp, Format::Text, &[Record::new().field("a", true)]).unwrap();
+
+        assert_eq!("true\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!(0, sp.read_error().len());
+    }
+
+    #[test]
+    fn record_writer__each_format__same_as_write() {
+        let formats = [
+            Format::Text,
+            Format::Json,
+            #[cfg(feature = "csv")]
+            Format::Csv,
+            #[cfg(feature = "yaml")]
+            Format::Yaml,
+        ];
+        for &format in &formats {
+            for records in &[sample_records(), Vec::new()] {
+                let mut expected: Vec<u8> = Vec::new();
+                format.write(&mut expected, records).unwrap();
+
+                let mut buf: Vec<u8> = Vec::new();
+                let mut writer = format.writer();
+                for record in records {
+                    writer.write(&mut buf, record).unwrap();
+                }
+                writer.finish(&mut buf).unwrap();
+
+                assert_eq!(::std::str::from_utf8(&expected).unwrap(), ::std::str::from_utf8(&buf).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn record_writer__progress_on_stderr__streams_stay_separate() {
+        let mut sp = stream::Virtual::new();
+
+        let mut writer = Format::Json.writer();
+        for i in 0..3 {
+            writer.write(sp.output(), &Record::new().field("i", i)).unwrap();
+            writeln!(sp.error(), "{} of 3", i + 1).unwrap();
+        }
+        writer.finish(sp.output()).unwrap();
+
+        assert_eq!("[\n  {\"i\": 0},\n  {\"i\": 1},\n  {\"i\": 2}\n]\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+        assert_eq!("1 of 3\n2 of 3\n3 of 3\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "csv")]
+    fn record_writer__csv_later_fields__header_from_first() {
+        let mut buf: Vec<u8> = Vec::new();
+
+        let mut writer = Format::Csv.writer();
+        writer.write(&mut buf, &Record::new().field("a", 1).field("b", 2)).unwrap();
+        writer.write(&mut buf, &Record::new().field("b", 3).field("c", 4)).unwrap();
+        writer.finish(&mut buf).unwrap();
+
+        assert_eq!("a,b\r\n1,2\r\n,3\r\n", ::std::str::from_utf8(&buf).unwrap());
+    }
+
+    #[test]
+    fn format__write_grouped_text__headers_between_items() {
+        let records = sample_records();
+        let mut buf: Vec<u8> = Vec::new();
+
+        Format::Text.write_grouped(&mut buf, &[("one", &records[..1]), ("two", &records[1..])]).unwrap();
+
+        assert_eq!(
+            "==> one <==\na.txt\t120\tnull-ish\n\n==> two <==\nb,c\t4096\t\n",
+            ::std::str::from_utf8(&buf).unwrap());
+    }
+
+    #[test]
+    fn format__write_grouped_json__item_fields() {
+        let records = sample_records();
+        let mut buf: Vec<u8> = Vec::new();
+
+        Format::Json.write_grouped(&mut buf, &[("one", &records[..1])]).unwrap();
+
+        assert_eq!(
+            "[\n  {\"item\": \"one\", \"name\": \"a.txt\", \"size\": 120, \"note\": \"null-ish\"}\n]\n",
+            ::std::str::from_utf8(&buf).unwrap());
+    }
+
+    #[test]
+    fn format__write_grouped_json__item_field_collision_rejected() {
+        let records = [Record::new().field("item", "mine")];
+        let mut buf: Vec<u8> = Vec::new();
+
+        let result = Format::Json.write_grouped(&mut buf, &[("one", &records[..])]);
+
+        assert_eq!(io::ErrorKind::InvalidInput, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn template__render__success() {
+        let template = Template::parse("{{.name}}\\t{{ .size }} bytes{{.missing}}\\n").unwrap();
+
+        let result = template.render(&sample_records()[0]);
+
+        assert_eq!("a.txt\t120 bytes\n", result);
+    }
+
+    #[test]
+    fn template__parse_escaped_backslash__literal_backslash() {
+        let template = Template::parse("a\\\\tb\\x").unwrap();
+
+        assert_eq!("a\\tb\\x", template.render(&Record::new()));
+    }
+
+    #[test]
+    fn template__parse_unterminated__returns_err() {
+        let result = Template::parse("ok {{.name");
+
+        assert_eq!(3, result.unwrap_err().position);
+    }
+
+    #[test]
+    fn template__parse_missing_dot__returns_err() {
+        let result = Template::parse("{{name}}");
+
+        assert_eq!(
+            "invalid template at position 0: expected a field reference like '{{.name}}'",
+            result.unwrap_err().to_string());
+    }
+
+    #[test]
+    fn template__write__one_line_per_record() {
+        let template = Template::parse("{{.name}}={{.size}}").unwrap();
+        let mut buf: Vec<u8> = Vec::new();
+
+        template.write(&mut buf, &sample_records()).unwrap();
+
+        assert_eq!("a.txt=120\nb,c=4096\n", ::std::str::from_utf8(&buf).unwrap());
+    }
+
+    #[test]
+    fn query__apply_empty__unchanged() {
+        let result = Query::new().apply(sample_records());
+
+        assert_eq!(sample_records(), result);
+    }
+
+    #[test]
+    fn query__apply_fields__projects_in_given_order() {
+        let query = Query::new().fields("size, name,,missing");
+
+        let result = query.apply(sample_records());
+
+        assert_eq!(vec![
+            Record::new().field("size", 120).field("name", "a.txt"),
+            Record::new().field("size", 4096).field("name", "b,c")],
+            result);
+    }
+
+    #[test]
+    fn query__apply_filters__keeps_records_matching_all() {
+        let query = Query::new().filter("size=4096").unwrap().filter("name=b,c").unwrap();
+
+        let result = query.apply(sample_records());
+
+        assert_eq!(vec![sample_records()[1].clone()], result);
+    }
+
+    #[test]
+    fn query__apply_filter_on_missing_field__excludes() {
+        let query = Query::new().filter("owner=root").unwrap();
+
+        let result = query.apply(sample_records());
+
+        assert_eq!(0, result.len());
+    }
+
+    #[test]
+    fn query__filter_without_equals__returns_err() {
+        let result = Query::new().filter("size");
+
+        assert_eq!("invalid filter 'size': expected KEY=VALUE", result.unwrap_err().to_string());
+    }
+
+    #[test]
+    fn query__apply_sort_by__numeric_ascending() {
+        let records = vec![
+            Record::new().field("n", 10), Record::new().field("n", 9), Record::new().field("n", 2.5)];
+
+        let result = Query::new().sort_by("n").unwrap().apply(records);
+
+        assert_eq!(vec![
+            Record::new().field("n", 2.5), Record::new().field("n", 9), Record::new().field("n", 10)],
+            result);
+    }
+
+    #[test]
+    fn query__apply_sort_by_desc__missing_fields_last() {
+        let records = vec![
+            Record::new().field("name", "b"),
+            Record::new(),
+            Record::new().field("name", "c"),
+            Record::new().field("name", "a")];
+
+        let result = Query::new().sort_by("name:desc").unwrap().apply(records);
+
+        assert_eq!(vec![
+            Record::new().field("name", "c"),
+            Record::new().field("name", "b"),
+            Record::new().field("name", "a"),
+            Record::new()],
+            result);
+    }
+
+    #[test]
+    fn query__apply_sort_by_unselected_field_and_limit__success() {
+        let query = Query::new().fields("name").sort_by("size:desc").unwrap().limit(1);
+
+        let result = query.apply(sample_records());
+
+        assert_eq!(vec![Record::new().field("name", "b,c")], result);
+    }
+
+    #[test]
+    fn query__sort_by_bad_order__returns_err() {
+        let result = Query::new().sort_by("size:down");
+
+        assert_eq!(
+            "invalid sort order in 'size:down': expected 'asc' or 'desc'",
+            result.unwrap_err().to_string());
+    }
+
+    fn sample_records() -> Vec<Record> {
+        vec![
+            Record::new().field("name", "a.txt").field("size", 120).field("note", "null-ish"),
+            Record::new().field("name", "b,c").field("size", 4096).field("note", None::<String>),
+        ]
+    }
+
+    fn test_write(format: Format, records: &[Record], expected: &str) {
+        let mut buf: Vec<u8> = Vec::new();
+
+        format.write(&mut buf, records).unwrap();
+
+        assert_eq!(expected, ::std::str::from_utf8(&buf).unwrap());
+    }
+}
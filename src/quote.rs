@@ -0,0 +1,207 @@
+//! Quoting of values printed by handlers for re-consumption by shells or
+//! spreadsheets, and splitting of shell-quoted text back into words.
+//!
+//! Where possible, values which are already safe to use verbatim are returned
+//! unchanged.
+
+/// Quotes `value` as a single word for POSIX-compatible shells.
+pub fn posix(value: &str) -> String {
+    if is_plain_word(value) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+/// Quotes `value` as a single word for the fish shell.
+pub fn fish(value: &str) -> String {
+    if is_plain_word(value) {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+    }
+}
+
+/// Quotes `value` as a PowerShell string literal.
+///
+/// Unlike the other functions this always quotes, since the result may be used
+/// in expression context (e.g. `$Env:KEY = ...`) where barewords aren't allowed.
+pub fn powershell(value: &str) -> String {
+    // PowerShell also treats typographic quotes as quote characters
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('\'');
+    for c in value.chars() {
+        if c == '\'' || c == '\u{2018}' || c == '\u{2019}' {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Quotes `value` as a single CSV field, as described by RFC 4180.
+///
+/// Values which a spreadsheet would take for a formula (starting with `=`, `+`,
+/// `-`, `@`, a tab or a carriage return) are prefixed with `'`, so that opening
+/// the file can't run them. Numbers such as `-1.5` are left alone.
+pub fn csv(value: &str) -> String {
+    let is_formula = value.starts_with(['=', '+', '-', '@', '\t', '\r']);
+    let value = if is_formula && value.parse::<f64>().is_err() {
+        format!("'{}", value)
+    } else {
+        value.to_string()
+    };
+
+    let needs_quotes = value.contains([',', '"', '\n', '\r'])
+        || value.starts_with(' ')
+        || value.ends_with(' ');
+
+    if needs_quotes {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Splits `s` into words as a POSIX shell would, undoing `posix`: words are
+/// separated by whitespace, and single quotes, double quotes and backslashes
+/// quote as usual. Nothing is expanded, so e.g. `$HOME` is left as it is.
+pub fn split_posix(s: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            },
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' || c == '$' || c == '`' => word.push(c),
+                            Some('\n') => (),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            },
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            },
+            '\\' => match chars.next() {
+                Some('\n') => (),
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("trailing backslash".to_string()),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    words.extend(word);
+    Ok(words)
+}
+
+/// Whether `value` is non-empty and made only of characters no shell treats specially.
+fn is_plain_word(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| matches!(c,
+        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '.' | '/' | ':' | ',' | '+' | '@' | '%'))
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posix__plain__unchanged() {
+        assert_eq!("/usr/local/bin:/usr/bin", posix("/usr/local/bin:/usr/bin"));
+    }
+
+    #[test]
+    fn posix__empty__quoted() {
+        assert_eq!("''", posix(""));
+    }
+
+    #[test]
+    fn posix__special_chars__quoted() {
+        assert_eq!("'a b $HOME'", posix("a b $HOME"));
+        assert_eq!("'it'\\''s'", posix("it's"));
+    }
+
+    #[test]
+    fn fish__special_chars__escaped() {
+        assert_eq!("'a\\\\b\\'c'", fish("a\\b'c"));
+    }
+
+    #[test]
+    fn powershell__special_chars__quoted() {
+        assert_eq!("'$x y'", powershell("$x y"));
+        assert_eq!("'it''s'", powershell("it's"));
+        assert_eq!("'it\u{2019}\u{2019}s'", powershell("it\u{2019}s"));
+    }
+
+    #[test]
+    fn powershell__plain__quoted() {
+        assert_eq!("'value'", powershell("value"));
+    }
+
+    #[test]
+    fn split_posix__quoting__words() {
+        let words = split_posix(r#"  -C 'a dir' --env "K=\"v\" \$x" plain\ word '' x"y"z "#).unwrap();
+
+        assert_eq!(vec!["-C", "a dir", "--env", "K=\"v\" $x", "plain word", "", "xyz"], words);
+    }
+
+    #[test]
+    fn split_posix__round_trip__unchanged() {
+        let values = ["it's", "a b", "", "\\"];
+        let quoted: Vec<String> = values.iter().map(|v| posix(v)).collect();
+
+        assert_eq!(values.to_vec(), split_posix(&quoted.join(" ")).unwrap());
+    }
+
+    #[test]
+    fn split_posix__unterminated__error() {
+        assert_eq!(Err("unterminated single quote".to_string()), split_posix("'a"));
+        assert_eq!(Err("unterminated double quote".to_string()), split_posix("\"a"));
+        assert_eq!(Err("trailing backslash".to_string()), split_posix("a\\"));
+    }
+
+    #[test]
+    fn csv__plain__unchanged() {
+        assert_eq!("hello world", csv("hello world"));
+    }
+
+    #[test]
+    fn csv__special_chars__quoted() {
+        assert_eq!("\"a,b\"", csv("a,b"));
+        assert_eq!("\"say \"\"hi\"\"\"", csv("say \"hi\""));
+        assert_eq!("\"two\nlines\"", csv("two\nlines"));
+        assert_eq!("\" padded \"", csv(" padded "));
+    }
+
+    #[test]
+    fn csv__formula__prefixed() {
+        assert_eq!("'=1+2", csv("=1+2"));
+        assert_eq!("'@SUM(A1:A2)", csv("@SUM(A1:A2)"));
+        assert_eq!("\"'-2+3,4\"", csv("-2+3,4"));
+        assert_eq!("-1.5", csv("-1.5"));
+        assert_eq!("+7", csv("+7"));
+    }
+}
@@ -0,0 +1,278 @@
+//! Neutralizing of terminal control sequences in untrusted text (file names,
+//! remote strings, etc.), so that printing it can't rewrite the user's screen,
+//! set the window title, or worse.
+//!
+//! Individual values can be cleaned up with `escape` or `strip`; alternatively,
+//! setting `Application::sanitize` applies a `Sanitizer` to everything handlers
+//! write to stdout and stderr.
+
+use filter::Filter;
+
+/// Describes how control characters are neutralized.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Text is left untouched.
+    Off,
+    /// Control characters (other than tab, newline and the carriage return of a
+    /// CRLF line ending) are replaced with visible `\xNN` escapes, so that the
+    /// text's full content is still shown.
+    Escape,
+    /// Escape sequences and other control characters (other than tab, newline and
+    /// the carriage return of a CRLF line ending) are removed.
+    Strip,
+    /// Escape sequences are removed, but other control characters are kept; this
+    /// removes styling (e.g. colors) from otherwise trusted text.
+    StripEscapes,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Ground,
+    /// After a carriage return, which is kept only if a newline follows.
+    CarriageReturn,
+    /// After a 0xC2 byte, which starts the UTF-8 encoding of C1 control characters.
+    C1Lead,
+    /// After ESC.
+    Escape,
+    /// Within a control sequence (`ESC [ ...`).
+    Csi,
+    /// Within an operating system command (`ESC ] ...`), terminated by BEL or `ESC \`.
+    Osc,
+    OscEscape,
+}
+
+/// A `Filter` which neutralizes control characters in UTF-8 text according to a `Mode`.
+pub struct Sanitizer {
+    mode: Mode,
+    state: State,
+}
+
+impl Sanitizer {
+    /// Constructs a `Sanitizer` operating in the given mode.
+    pub fn new(mode: Mode) -> Sanitizer {
+        Sanitizer { mode, state: State::Ground }
+    }
+
+    fn escape_byte(&mut self, b: u8, out: &mut Vec<u8>) {
+        if self.state == State::CarriageReturn {
+            self.state = State::Ground;
+            out.extend(if b == b'\n' { &b"\r"[..] } else { &b"\\x0d"[..] });
+        }
+
+        if self.state == State::C1Lead {
+            self.state = State::Ground;
+            if (0x80..=0x9f).contains(&b) {
+                out.extend(format!("\\x{:02x}", b).as_bytes());
+                return;
+            }
+            out.push(0xc2);
+        }
+
+        if b == 0xc2 {
+            self.state = State::C1Lead;
+        } else if b == b'\r' {
+            self.state = State::CarriageReturn;
+        } else if is_control(b) {
+            out.extend(format!("\\x{:02x}", b).as_bytes());
+        } else {
+            out.push(b);
+        }
+    }
+
+    fn strip_byte(&mut self, b: u8, out: &mut Vec<u8>) {
+        self.state = match self.state {
+            State::Ground => {
+                match b {
+                    0x1b => State::Escape,
+                    0xc2 => State::C1Lead,
+                    b'\r' if self.mode == Mode::Strip => State::CarriageReturn,
+                    b if is_control(b) && self.mode == Mode::Strip => State::Ground,
+                    b => {
+                        out.push(b);
+                        State::Ground
+                    },
+                }
+            },
+            State::CarriageReturn => {
+                if b == b'\n' {
+                    out.push(b'\r');
+                }
+                self.state = State::Ground;
+                return self.strip_byte(b, out);
+            },
+            State::C1Lead => {
+                match b {
+                    0x9b => State::Csi,
+                    0x9d => State::Osc,
+                    0x80..=0x9f => State::Ground,
+                    b => {
+                        out.push(0xc2);
+                        self.state = State::Ground;
+                        return self.strip_byte(b, out);
+                    },
+                }
+            },
+            State::Escape => {
+                match b {
+                    b'[' => State::Csi,
+                    b']' => State::Osc,
+                    _ => State::Ground,
+                }
+            },
+            State::Csi => {
+                match b {
+                    0x20..=0x3f => State::Csi,
+                    _ => State::Ground,
+                }
+            },
+            State::Osc => {
+                match b {
+                    0x07 => State::Ground,
+                    0x1b => State::OscEscape,
+                    _ => State::Osc,
+                }
+            },
+            State::OscEscape => State::Ground,
+        };
+    }
+}
+
+impl Filter for Sanitizer {
+    fn filter(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        for &b in buf {
+            match self.mode {
+                Mode::Off => out.push(b),
+                Mode::Escape => self.escape_byte(b, out),
+                Mode::Strip | Mode::StripEscapes => self.strip_byte(b, out),
+            }
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        // A lone 0xC2 is passed through; incomplete escape sequences are dropped
+        match self.state {
+            State::C1Lead => out.push(0xc2),
+            State::CarriageReturn if self.mode == Mode::Escape => out.extend(b"\\x0d"),
+            _ => (),
+        }
+        self.state = State::Ground;
+    }
+}
+
+/// Replaces control characters (other than tab, newline and CRLF line endings) in
+/// `s` with visible `\xNN` escapes.
+pub fn escape(s: &str) -> String {
+    apply(Mode::Escape, s)
+}
+
+/// Removes escape sequences and control characters (other than tab, newline and
+/// CRLF line endings) from `s`.
+pub fn strip(s: &str) -> String {
+    apply(Mode::Strip, s)
+}
+
+fn apply(mode: Mode, s: &str) -> String {
+    let mut sanitizer = Sanitizer::new(mode);
+    let mut out = Vec::with_capacity(s.len());
+    sanitizer.filter(s.as_bytes(), &mut out);
+    sanitizer.finish(&mut out);
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Whether `b` is an ASCII control character which could affect the terminal.
+fn is_control(b: u8) -> bool {
+    (b < 0x20 && b != b'\t' && b != b'\n') || b == 0x7f
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use filter::Filter;
+
+    #[test]
+    fn escape__plain_text__unchanged() {
+        assert_eq!("héllo\tworld\n", escape("héllo\tworld\n"));
+    }
+
+    #[test]
+    fn escape__control_chars__escaped() {
+        assert_eq!("\\x1b[31mred\\x1b[0m\\x07\\x0d", escape("\x1b[31mred\x1b[0m\x07\r"));
+    }
+
+    #[test]
+    fn escape__crlf__kept() {
+        assert_eq!("a\r\nb\\x0dc\\x0d", escape("a\r\nb\rc\r"));
+    }
+
+    #[test]
+    fn strip__crlf__kept() {
+        assert_eq!("a\r\nbc", strip("a\r\nb\rc\r"));
+    }
+
+    #[test]
+    fn sanitizer__crlf_split_across_writes__kept() {
+        let mut sanitizer = Sanitizer::new(Mode::Escape);
+        let mut out = Vec::new();
+
+        sanitizer.filter(b"a\r", &mut out);
+        sanitizer.filter(b"\nb", &mut out);
+        sanitizer.finish(&mut out);
+
+        assert_eq!(b"a\r\nb", &out[..]);
+    }
+
+    #[test]
+    fn escape__c1_control_chars__escaped() {
+        assert_eq!("\\x9b2J", escape("\u{9b}2J"));
+    }
+
+    #[test]
+    fn strip__sequences__removed() {
+        assert_eq!("red plain", strip("\x1b[1;31mred\x1b[0m plain"));
+    }
+
+    #[test]
+    fn strip__osc__removed() {
+        assert_eq!("ab", strip("a\x1b]0;pwned\x07b"));
+        assert_eq!("ab", strip("a\x1b]0;pwned\x1b\\b"));
+    }
+
+    #[test]
+    fn strip__c1_csi__removed() {
+        assert_eq!("éx", strip("é\u{9b}2Jx"));
+    }
+
+    #[test]
+    fn sanitizer__sequence_split_across_writes__handled() {
+        let mut sanitizer = Sanitizer::new(Mode::Strip);
+        let mut out = Vec::new();
+
+        sanitizer.filter(b"a\x1b[3", &mut out);
+        sanitizer.filter(b"1mb\xc2", &mut out);
+        sanitizer.filter(b"\x9b1mc", &mut out);
+        sanitizer.finish(&mut out);
+
+        assert_eq!(b"abc", &out[..]);
+    }
+
+    #[test]
+    fn sanitizer__strip_escapes__keeps_other_controls() {
+        let mut sanitizer = Sanitizer::new(Mode::StripEscapes);
+        let mut out = Vec::new();
+
+        sanitizer.filter(b"\x1b[1mbold\x1b[0m\r\x07", &mut out);
+
+        assert_eq!(b"bold\r\x07", &out[..]);
+    }
+
+    #[test]
+    fn sanitizer__off__unchanged() {
+        let mut sanitizer = Sanitizer::new(Mode::Off);
+        let mut out = Vec::new();
+
+        sanitizer.filter(b"\x1b[0m", &mut out);
+
+        assert_eq!(b"\x1b[0m", &out[..]);
+    }
+}
@@ -0,0 +1,156 @@
+//! A small key-value store which persists between runs of an application, so
+//! that commands can remember things like the last profile used or a pagination
+//! cursor. Handlers get at it with `Context::state`, and change it with
+//! `Context::set_state` and `Context::remove_state`.
+//!
+//! The store is a flat file in the application's state directory, with one
+//! `key<TAB>value` line per entry. It's read and written through the context's
+//! `env::Provider`, so a `--sandbox` applies to it like any other file.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use env;
+
+/// The persisted state of an application.
+pub struct State {
+    values: BTreeMap<String, String>,
+}
+
+impl State {
+    /// Constructs an empty `State`.
+    pub(crate) fn new() -> State {
+        State { values: BTreeMap::new() }
+    }
+
+    /// Loads the state saved at `path`, which is empty if the file doesn't exist yet.
+    pub(crate) fn load(ep: &env::Provider, path: &Path) -> io::Result<State> {
+        let mut state = State::new();
+        match ep.read_file(path) {
+            Ok(contents) => {
+                for line in String::from_utf8_lossy(&contents).lines() {
+                    let mut fields = line.splitn(2, '\t');
+                    if let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+                        state.values.insert(unescape(key), unescape(value));
+                    }
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        Ok(state)
+    }
+
+    /// Returns the value stored under `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|value| &value[..])
+    }
+
+    /// Stores `value` under `key`, without saving the state.
+    pub(crate) fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    /// Removes the value stored under `key`, without saving the state.
+    pub(crate) fn remove(&mut self, key: &str) {
+        self.values.remove(key);
+    }
+
+    /// Writes the state to a temporary file and moves it into place, so that an
+    /// interrupted save doesn't lose what was there before. The temporary file is
+    /// named after the process, so that concurrent runs don't write to the same one.
+    pub(crate) fn save(&self, ep: &mut env::Provider, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            try!(ep.create_dir_all(dir));
+        }
+
+        let mut contents = String::new();
+        for (key, value) in &self.values {
+            contents.push_str(&format!("{}\t{}\n", escape(key), escape(value)));
+        }
+        let temp_path: PathBuf = path.with_extension(format!("{}.tmp", process::id()));
+        try!(ep.write_file(&temp_path, contents.as_bytes()));
+        ep.rename(&temp_path, path)
+    }
+}
+
+/// Returns where the state of the application named `app_name` is saved:
+/// `$XDG_STATE_HOME/APP/state`, falling back to `~/.local/state/APP/state`
+/// (or `%LOCALAPPDATA%\APP\state` on Windows). Returns `None` if none of these
+/// variables is set.
+pub fn default_path(app_name: &str, ep: &env::Provider) -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        ep.var("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        ep.var("XDG_STATE_HOME").map(PathBuf::from)
+            .or_else(|| ep.var("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))
+    };
+    base.map(|base| base.join(app_name).join("state"))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('t') => result.push('\t'),
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some(c) => result.push(c),
+                None => result.push('\\'),
+            },
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use env::{self, Provider};
+
+    #[test]
+    fn escape__special_characters__round_trip() {
+        let value = "a\tb\nc\\d\r";
+
+        assert_eq!("a\\tb\\nc\\\\d\\r", escape(value));
+        assert_eq!(value, unescape(&escape(value)));
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn default_path__xdg_state_home__preferred() {
+        let mut ep = env::Virtual::new();
+        ep.set_var("HOME", "/home/user");
+        assert_eq!(Some(PathBuf::from("/home/user/.local/state/app/state")), default_path("app", &ep));
+
+        ep.set_var("XDG_STATE_HOME", "/state");
+        assert_eq!(Some(PathBuf::from("/state/app/state")), default_path("app", &ep));
+    }
+
+    #[test]
+    fn state__save_then_load__persisted() {
+        let mut ep = env::Virtual::new();
+        let path = Path::new("/state/app/state");
+        let mut state = State::load(&ep, path).unwrap();
+        assert_eq!(None, state.get("profile"));
+
+        state.set("profile", "work\tlaptop\r");
+        state.set("cursor", "42");
+        state.remove("cursor");
+        state.save(&mut ep, path).unwrap();
+
+        let state = State::load(&ep, path).unwrap();
+        assert_eq!(Some("work\tlaptop\r"), state.get("profile"));
+        assert_eq!(None, state.get("cursor"));
+        assert_eq!(b"profile\twork\\tlaptop\\r\n".to_vec(), ep.read_file(path).unwrap());
+    }
+}
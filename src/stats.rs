@@ -0,0 +1,108 @@
+//! Measurement of the resources an invocation uses, for `--stats`.
+
+use std::io::{self, Read, Write};
+use io_providers::stream;
+
+/// A stream provider which counts the bytes written to stdout and stderr
+/// through it.
+pub struct Counted<'a> {
+    inner: &'a mut stream::Provider,
+    to_error: bool,
+    /// The number of bytes written to stdout.
+    pub output_bytes: u64,
+    /// The number of bytes written to stderr.
+    pub error_bytes: u64,
+}
+
+impl<'a> Counted<'a> {
+    /// Constructs a `Counted` provider wrapping `inner`, with both counts at zero.
+    pub fn new(inner: &'a mut stream::Provider) -> Counted<'a> {
+        Counted { inner, to_error: false, output_bytes: 0, error_bytes: 0 }
+    }
+}
+
+impl<'a> stream::Provider for Counted<'a> {
+    fn input(&mut self) -> &mut Read {
+        self.inner.input()
+    }
+
+    fn output(&mut self) -> &mut Write {
+        self.to_error = false;
+        self
+    }
+
+    fn error(&mut self) -> &mut Write {
+        self.to_error = true;
+        self
+    }
+}
+
+impl<'a> Write for Counted<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.to_error {
+            let len = try!(self.inner.error().write(buf));
+            self.error_bytes += len as u64;
+            Ok(len)
+        } else {
+            let len = try!(self.inner.output().write(buf));
+            self.output_bytes += len as u64;
+            Ok(len)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.to_error {
+            self.inner.error().flush()
+        } else {
+            self.inner.output().flush()
+        }
+    }
+}
+
+/// Returns the most memory the process has had resident at once, in KiB.
+#[cfg(target_os = "linux")]
+pub fn peak_rss_kib() -> Option<u64> {
+    let status = match ::std::fs::read_to_string("/proc/self/status") {
+        Ok(status) => status,
+        Err(_) => return None,
+    };
+    status.lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+/// Elsewhere the peak isn't available yet.
+#[cfg(not(target_os = "linux"))]
+pub fn peak_rss_kib() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use io_providers::stream::{self, Provider};
+
+    #[test]
+    fn counted__writes__counted_per_stream() {
+        let mut sp = stream::Virtual::new();
+        {
+            let mut counted = Counted::new(&mut sp);
+            write!(counted.output(), "hello").unwrap();
+            writeln!(counted.error(), "oops").unwrap();
+            write!(counted.output(), "!").unwrap();
+
+            assert_eq!(6, counted.output_bytes);
+            assert_eq!(5, counted.error_bytes);
+        }
+
+        assert_eq!(b"hello!", sp.read_output());
+        assert_eq!(b"oops\n", sp.read_error());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn peak_rss_kib__linux__some() {
+        assert!(peak_rss_kib().is_some_and(|kib| kib > 0));
+    }
+}
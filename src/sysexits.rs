@@ -0,0 +1,50 @@
+//! The exit codes of BSD's `sysexits.h`, which some packaging policies ask
+//! applications to use, e.g. as `Application::panic_exit_code`:
+//!
+//! ```
+//! # extern crate command_cli;
+//! use command_cli::{Application, StaticApplication};
+//! use command_cli::sysexits;
+//!
+//! const APP: StaticApplication = Application {
+//!     name: "app",
+//!     panic_exit_code: sysexits::SOFTWARE,
+//!     partial_failure_exit_code: sysexits::TEMPFAIL,
+//!     ..Application::DEFAULT
+//! };
+//! # fn main() {}
+//! ```
+//!
+//! The framework's own exit codes (0 for success, 1 for argument errors and 2
+//! for execution errors) are fixed, since callers already depend on them.
+
+/// The command was used incorrectly.
+pub const USAGE: i32 = 64;
+/// The input data was incorrect.
+pub const DATAERR: i32 = 65;
+/// An input file didn't exist or wasn't readable.
+pub const NOINPUT: i32 = 66;
+/// The user specified didn't exist.
+pub const NOUSER: i32 = 67;
+/// The host specified didn't exist.
+pub const NOHOST: i32 = 68;
+/// A service is unavailable.
+pub const UNAVAILABLE: i32 = 69;
+/// An internal software error was detected.
+pub const SOFTWARE: i32 = 70;
+/// An operating system error was detected, e.g. a failed `fork`.
+pub const OSERR: i32 = 71;
+/// A system file didn't exist, couldn't be opened, or had the wrong format.
+pub const OSFILE: i32 = 72;
+/// An output file couldn't be created.
+pub const CANTCREAT: i32 = 73;
+/// An error occurred while doing I/O on a file.
+pub const IOERR: i32 = 74;
+/// A temporary failure; the user is invited to retry.
+pub const TEMPFAIL: i32 = 75;
+/// The remote system returned something impossible during a protocol exchange.
+pub const PROTOCOL: i32 = 76;
+/// The user didn't have sufficient permission.
+pub const NOPERM: i32 = 77;
+/// Something was found in an unconfigured or misconfigured state.
+pub const CONFIG: i32 = 78;
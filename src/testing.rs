@@ -0,0 +1,329 @@
+//! Helpers for testing applications built with this crate.
+//!
+//! `check_contract` runs invocations which are known to be wrong, derived from
+//! the application's definition, and checks that each fails with the documented
+//! exit code and an error of the expected shape. Calling it from a unit test
+//! guards against accidental changes to how the application reports misuse:
+//!
+//! ```
+//! # use command_cli::{Application, StaticApplication};
+//! # use command_cli::testing;
+//! const APP: StaticApplication = Application { name: "app", commands: &[], ..Application::DEFAULT };
+//!
+//! assert_eq!(Ok(()), testing::check_contract(&APP));
+//! ```
+//!
+//! `Terminal` runs an application as if in a terminal of a given size, so that
+//! how output is wrapped or styled there can be asserted, and `Session` plays
+//! a scripted sequence of answers to prompts while recording a transcript.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use io_providers::stream;
+use env;
+use {Application, ARGUMENT_ERROR_EXIT_CODE};
+
+/// A simulated terminal of a fixed size. Which of stdin, stdout and stderr are
+/// attached to it is set per stream; `new` attaches all three.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Terminal {
+    pub width: usize,
+    pub height: usize,
+    pub input: bool,
+    pub output: bool,
+    pub error: bool,
+}
+
+/// What an application wrote while running in a `Terminal`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Screen {
+    pub exit_code: i32,
+    pub output: String,
+    pub error: String,
+}
+
+impl Terminal {
+    /// Constructs a terminal `width` columns wide and `height` lines high, to
+    /// which all the streams are attached.
+    pub fn new(width: usize, height: usize) -> Terminal {
+        Terminal { width, height, input: true, output: true, error: true }
+    }
+
+    /// Returns a simulated environment in which the attached streams are
+    /// terminals, and `COLUMNS` and `LINES` give the terminal's size.
+    pub fn env(&self) -> env::Virtual {
+        let mut ep = env::Virtual::new();
+        ep.set_terminal(env::Stream::Input, self.input);
+        ep.set_terminal(env::Stream::Output, self.output);
+        ep.set_terminal(env::Stream::Error, self.error);
+        ep.set_var("COLUMNS", &self.width.to_string());
+        ep.set_var("LINES", &self.height.to_string());
+        ep
+    }
+
+    /// Runs `app` with `args` (including the application name), in the
+    /// environment returned by `env`.
+    pub fn run(&self, app: &Application, args: &[&str]) -> Screen {
+        let mut sp = stream::Virtual::new();
+        let (exit_code, _) = app.run_with_env(&mut sp, &mut self.env(), args.iter().map(|s| s.to_string()).collect());
+        Screen {
+            exit_code,
+            output: String::from_utf8_lossy(sp.read_output()).into_owned(),
+            error: String::from_utf8_lossy(sp.read_error()).into_owned(),
+        }
+    }
+
+    /// Checks that no line of `text` is wider than the terminal, returning a
+    /// description of each line which is.
+    pub fn check_fits(&self, text: &str) -> Result<(), String> {
+        let overflows: Vec<String> = text.lines().enumerate()
+            .filter(|&(_, line)| line.chars().count() > self.width)
+            .map(|(i, line)| format!("line {} is {} columns wide: {:?}", i + 1, line.chars().count(), line))
+            .collect();
+
+        if overflows.is_empty() {
+            Ok(())
+        } else {
+            Err(overflows.join("\n"))
+        }
+    }
+}
+
+/// A stream provider for testing interactive flows: stdin gives a scripted
+/// sequence of lines, and a transcript is kept of the session as seen at a
+/// terminal, with what's written to stdout and stderr interleaved with each line
+/// typed in as it's read. Lines are shown as typed even while echo is off.
+#[derive(Debug, Default)]
+pub struct Session {
+    lines: VecDeque<String>,
+    /// The rest of the line being read.
+    pending: VecDeque<u8>,
+    transcript: Vec<u8>,
+}
+
+impl Session {
+    /// Constructs a session in which `lines` are typed in, in order. Once
+    /// they're all read, stdin is at its end.
+    pub fn new(lines: &[&str]) -> Session {
+        Session { lines: lines.iter().map(|line| line.to_string()).collect(), ..Session::default() }
+    }
+
+    /// Runs `app` with `args` (including the application name) in this session,
+    /// returning the exit code. `ep` should report stdin as a terminal, as
+    /// `Terminal::env` does, for the application to prompt.
+    pub fn run(&mut self, app: &Application, ep: &mut env::Provider, args: &[&str]) -> i32 {
+        app.run_with_env(self, ep, args.iter().map(|s| s.to_string()).collect()).0
+    }
+
+    /// Returns the transcript so far.
+    pub fn transcript(&self) -> String {
+        String::from_utf8_lossy(&self.transcript).into_owned()
+    }
+
+    /// Returns the number of scripted lines which haven't been read.
+    pub fn unread(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+impl stream::Provider for Session {
+    fn input(&mut self) -> &mut Read {
+        self
+    }
+
+    fn output(&mut self) -> &mut Write {
+        self
+    }
+
+    fn error(&mut self) -> &mut Write {
+        self
+    }
+}
+
+impl Read for Session {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            if let Some(line) = self.lines.pop_front() {
+                self.transcript.extend(line.bytes().chain(Some(b'\n')));
+                self.pending.extend(line.bytes().chain(Some(b'\n')));
+            }
+        }
+
+        let len = buf.len().min(self.pending.len());
+        for (b, pending) in buf.iter_mut().zip(self.pending.drain(..len)) {
+            *b = pending;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for Session {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transcript.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An invocation which is expected to fail, and how.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContractCase {
+    /// The command-line arguments, including the application name.
+    pub args: Vec<String>,
+    pub exit_code: i32,
+    /// What stderr is expected to start with.
+    pub error_prefix: String,
+}
+
+/// Returns invocations of `app` which are wrong: no command, an unknown option,
+/// an unknown command, and for each command, too few or too many arguments
+/// where the command's parameters make those possible.
+pub fn contract_cases(app: &Application) -> Vec<ContractCase> {
+    let case = |args: &[&str], error_prefix: String| ContractCase {
+        args: Some(app.name).into_iter().chain(args.iter().cloned()).map(String::from).collect(),
+        exit_code: ARGUMENT_ERROR_EXIT_CODE,
+        error_prefix,
+    };
+
+    let mut unknown = "no-such-command".to_string();
+    while app.commands.iter().any(|cmd| cmd.name == unknown) {
+        unknown.push('-');
+    }
+
+    let mut cases = vec![
+        case(&[], format!("Usage: {} [OPTIONS] COMMAND", app.name)),
+        case(&["--no-such-option"], "Error: Unrecognized option '--no-such-option'".to_string()),
+        case(&["-C"], "Error: Missing value for option '-C'".to_string()),
+        case(&[&unknown], format!("Error: Unrecognized command '{}'", unknown)),
+    ];
+
+    for cmd in app.commands {
+        let usage = cmd.usage_string(app.name);
+        let invocation = |count: usize| -> Vec<String> {
+            vec![app.name, cmd.name].into_iter().map(String::from)
+                .chain((0..count).map(|_| "x".to_string()))
+                .collect()
+        };
+
+        if cmd.parse_arguments(invocation(0)).is_none() {
+            cases.push(case(&[cmd.name], usage.clone()));
+        }
+
+        let most = cmd.forms.iter().map(|form| form.len()).fold(cmd.all_params().len(), usize::max);
+        let args = invocation(most + 1);
+        if cmd.parse_arguments(args.clone()).is_none() {
+            cases.push(ContractCase { args, exit_code: ARGUMENT_ERROR_EXIT_CODE, error_prefix: usage });
+        }
+    }
+
+    cases
+}
+
+/// Runs each of `contract_cases` against `app` in a simulated environment,
+/// returning a description of every case which didn't fail as expected.
+pub fn check_contract(app: &Application) -> Result<(), String> {
+    let failures: Vec<String> = contract_cases(app).into_iter()
+        .filter_map(|case| {
+            let mut sp = stream::Virtual::new();
+            let (exit_code, _) = app.run_with_env(&mut sp, &mut env::Virtual::new(), case.args.clone());
+            let error = String::from_utf8_lossy(sp.read_error()).into_owned();
+            if exit_code == case.exit_code && error.starts_with(&case.error_prefix) {
+                None
+            } else {
+                Some(format!("'{}' exited with {} and printed {:?}, but should exit with {} and print {:?}",
+                    case.args.join(" "), exit_code, error, case.exit_code, case.error_prefix))
+            }
+        })
+        .collect();
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures.join("\n"))
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use {Application, Arguments, Command, CommandResult, Context, Parameter};
+
+    const PARAMS: &[Parameter] = &[Parameter { name: "NAME", required: true, ..Parameter::DEFAULT }];
+
+    #[test]
+    fn contract_cases__required_param__too_few_and_too_many() {
+        let cmds = [Command { name: "greet", params: PARAMS, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+
+        let cases: Vec<String> = contract_cases(&app).iter().map(|case| case.args.join(" ")).collect();
+
+        assert_eq!(vec!["app", "app --no-such-option", "app -C", "app no-such-command", "app greet", "app greet x x"],
+            cases);
+        assert_eq!(Ok(()), check_contract(&app));
+    }
+
+    #[test]
+    fn check_contract__misreported__describes_failures() {
+        let cmds = [Command { name: "greet", params: PARAMS, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, rewrite_args: Some(drop_extra_args), ..Application::DEFAULT };
+
+        let result = check_contract(&app);
+
+        assert_eq!(Err("'app greet x x' exited with 2 and printed \"\", but should exit with 1 and print \"Usage: app greet NAME\"".to_string()),
+            result);
+    }
+
+    #[test]
+    fn terminal__run__help_fits() {
+        let cmds = [Command {
+            name: "greet",
+            short_desc: "greets",
+            long_desc: "Greets someone by name, politely and at some length.",
+            params: PARAMS,
+            ..Command::DEFAULT
+        }];
+        let app = Application { name: "app", commands: &cmds, ..Application::DEFAULT };
+        let terminal = Terminal { output: false, ..Terminal::new(24, 10) };
+
+        let screen = terminal.run(&app, &["app", "help", "greet"]);
+
+        assert_eq!(0, screen.exit_code);
+        assert_eq!(Ok(()), terminal.check_fits(&screen.output));
+        assert_eq!(Err("line 1 is 21 columns wide: \"Usage: app greet NAME\"".to_string()),
+            Terminal::new(20, 10).check_fits("Usage: app greet NAME\nok"));
+    }
+
+    #[test]
+    fn session__run__records_transcript() {
+        let cmds = [Command { name: "drop", handler: say_done_handler, mutates: true, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, confirm_mutating: true, ..Application::DEFAULT };
+        let mut ep = Terminal::new(80, 24).env();
+        let mut session = Session::new(&["n", "yes", "extra"]);
+
+        assert_eq!(1, session.run(&app, &mut ep, &["app", "drop"]));
+        assert_eq!(0, session.run(&app, &mut ep, &["app", "drop"]));
+
+        assert_eq!("\
+            'drop' modifies state. Continue? [y/N] n\n\
+            Error: Not confirmed\n\
+            Usage: app drop\n\
+            'drop' modifies state. Continue? [y/N] yes\n\
+            done\n",
+            session.transcript());
+        assert_eq!(1, session.unread());
+    }
+
+    fn say_done_handler(ctx: &mut Context, _args: &Arguments) -> CommandResult {
+        writeln!(ctx.output(), "done").unwrap();
+        CommandResult::Success
+    }
+
+    fn drop_extra_args(mut args: Vec<String>) -> Vec<String> {
+        args.truncate(3);
+        args
+    }
+}
@@ -0,0 +1,467 @@
+//! Periodic re-running of a command, like `watch(1)`.
+//!
+//! Applications typically expose this as a `watch` command taking a single
+//! repeating parameter, whose handler calls `parse_args` and then
+//! `Application::watch`, passing on its streams and environment. The streams
+//! should be unfiltered, since each run's output is filtered already:
+//!
+//! ```no_run
+//! # extern crate command_cli;
+//! use command_cli::{Application, Arguments, Command, CommandResult, Context, Parameter, StaticApplication};
+//! use command_cli::watch;
+//!
+//! const APP: StaticApplication = Application {
+//!     name: "app",
+//!     commands: &[
+//!         Command {
+//!             name: "watch",
+//!             short_desc: "re-runs a command periodically",
+//!             params: &[Parameter { name: "ARGS", required: true, repeating: true, ..Parameter::DEFAULT }],
+//!             handler: watch_handler,
+//!             ..Command::DEFAULT
+//!         },
+//!     ],
+//!     ..Application::DEFAULT
+//! };
+//!
+//! // Handles e.g. `app watch --interval 2s -- status --all`
+//! fn watch_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+//!     match watch::parse_args(&args["ARGS"], ctx.env()) {
+//!         Ok((options, cmd_args)) => {
+//!             let (sp, ep) = ctx.unfiltered_parts();
+//!             match APP.watch(sp, ep, cmd_args, &options) {
+//!                 0 => CommandResult::Success,
+//!                 _ => CommandResult::ExecutionError(None),
+//!             }
+//!         },
+//!         Err(_) => CommandResult::ArgumentError,
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+
+use std::io;
+use std::time::Duration;
+use io_providers::stream;
+use env;
+use features::FrameworkFeatures;
+use super::{Application, ARGUMENT_ERROR_EXIT_CODE};
+
+/// ANSI sequence which clears the screen and moves the cursor to the top-left corner.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// ANSI sequences which start and end reverse-video text, used to highlight changes.
+const HIGHLIGHT_START: &str = "\x1b[7m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+
+/// Describes how a command is re-run.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Options {
+    /// How long to wait between runs.
+    pub interval: Duration,
+
+    /// Whether to clear the screen before each run; this should only be set when
+    /// stdout is a terminal.
+    pub clear_screen: bool,
+
+    /// The number of runs after which to stop, or `None` to run until interrupted.
+    pub max_runs: Option<usize>,
+
+    /// Whether to highlight lines of stdout which changed since the previous run,
+    /// like `watch -d`. Output is buffered per run when this is set.
+    pub highlight_changes: bool,
+}
+
+impl Options {
+    /// Constructs `Options` which re-run a command every `interval` until
+    /// interrupted, clearing the screen in between if stdout is a terminal.
+    pub fn new(interval: Duration, ep: &env::Provider) -> Options {
+        Options {
+            interval,
+            clear_screen: ep.is_terminal(env::Stream::Output),
+            max_runs: None,
+            highlight_changes: false,
+        }
+    }
+}
+
+impl<'c, 'p> Application<'c, 'p> {
+    /// Runs the command given by `cmd_args` (which excludes the application name)
+    /// repeatedly, as described by `options`, in the environment `ep`.
+    ///
+    /// Runs stop early if the command was invoked incorrectly, since re-running it
+    /// can't help. Returns the exit code of the last run.
+    ///
+    /// If the user has turned off `FrameworkFeatures::FANCY_OUTPUT`, or output is
+    /// `deterministic`, the screen isn't cleared and changes aren't highlighted.
+    pub fn watch(&self, sp: &mut stream::Provider, ep: &mut env::Provider, cmd_args: Vec<String>, options: &Options)
+        -> i32
+    {
+        let mut args = Vec::with_capacity(cmd_args.len() + 1);
+        args.push(self.name.to_string());
+        args.extend(cmd_args);
+
+        let fancy = self.features.with_env_overrides(self.name, ep).contains(FrameworkFeatures::FANCY_OUTPUT)
+            && !self.is_deterministic(ep);
+        let mut runs = 0;
+        let mut previous_frame: Option<String> = None;
+        loop {
+            if options.clear_screen && fancy {
+                write!(sp.output(), "{}", CLEAR_SCREEN).unwrap();
+            }
+
+            let exit_code = if options.highlight_changes && fancy {
+                let (exit_code, frame) = {
+                    let mut capture = Capture { inner: sp, output: Vec::new() };
+                    let (exit_code, _) = self.run_with_env(&mut capture, ep, args.clone());
+                    (exit_code, String::from_utf8_lossy(&capture.output).into_owned())
+                };
+
+                let highlighted = match previous_frame {
+                    Some(ref previous) => highlight_changes(previous, &frame),
+                    None => frame.clone(),
+                };
+                write!(sp.output(), "{}", highlighted).unwrap();
+                previous_frame = Some(frame);
+                exit_code
+            } else {
+                self.run_with_env(sp, ep, args.clone()).0
+            };
+            runs += 1;
+
+            if exit_code == ARGUMENT_ERROR_EXIT_CODE || options.max_runs.is_some_and(|max| runs >= max) {
+                return exit_code;
+            }
+
+            ep.sleep(options.interval);
+        }
+    }
+}
+
+/// Stream provider which buffers stdout, passing stdin and stderr through.
+struct Capture<'a> {
+    inner: &'a mut stream::Provider,
+    output: Vec<u8>,
+}
+
+impl<'a> stream::Provider for Capture<'a> {
+    fn input(&mut self) -> &mut io::Read {
+        self.inner.input()
+    }
+
+    fn output(&mut self) -> &mut io::Write {
+        &mut self.output
+    }
+
+    fn error(&mut self) -> &mut io::Write {
+        self.inner.error()
+    }
+}
+
+/// Returns `current` with each line which differs from the same line of `previous` highlighted.
+fn highlight_changes(previous: &str, current: &str) -> String {
+    let previous_lines: Vec<&str> = previous.split('\n').collect();
+    let mut highlighted = String::with_capacity(current.len());
+
+    for (i, line) in current.split('\n').enumerate() {
+        if i > 0 {
+            highlighted.push('\n');
+        }
+
+        if line.is_empty() || previous_lines.get(i) == Some(&line) {
+            highlighted.push_str(line);
+        } else {
+            highlighted.push_str(HIGHLIGHT_START);
+            highlighted.push_str(line);
+            highlighted.push_str(HIGHLIGHT_END);
+        }
+    }
+
+    highlighted
+}
+
+/// Parses watch arguments of the form
+/// `[--interval DURATION] [--differences] [--no-clear] [--] COMMAND [ARGS]...`,
+/// returning the options and the command's arguments. The other options default
+/// to those of `Options::new`, with an interval of 2 seconds.
+pub fn parse_args(tokens: &[String], ep: &env::Provider) -> Result<(Options, Vec<String>), String> {
+    let mut options = Options::new(Duration::from_secs(2), ep);
+    let mut i = 0;
+
+    while i < tokens.len() {
+        match &tokens[i][..] {
+            "--interval" | "-n" => {
+                let value = match tokens.get(i + 1) {
+                    Some(v) => v,
+                    None => return Err(format!("missing value for '{}'", tokens[i])),
+                };
+                options.interval = match parse_interval(value) {
+                    Some(d) => d,
+                    None => return Err(format!("invalid interval '{}'", value)),
+                };
+                i += 2;
+            },
+            "--differences" | "-d" => {
+                options.highlight_changes = true;
+                i += 1;
+            },
+            "--no-clear" => {
+                options.clear_screen = false;
+                i += 1;
+            },
+            "--" => {
+                i += 1;
+                break;
+            },
+            _ => break,
+        }
+    }
+
+    if i >= tokens.len() {
+        return Err("missing command to watch".to_string());
+    }
+
+    Ok((options, tokens[i..].to_vec()))
+}
+
+/// Parses an interval such as `500ms`, `2s`, `1.5s`, `1m` or `2` (seconds).
+pub fn parse_interval(s: &str) -> Option<Duration> {
+    let (number, millis_per_unit) =
+        if let Some(number) = s.strip_suffix("ms") {
+            (number, 1.0)
+        } else if let Some(number) = s.strip_suffix('s') {
+            (number, 1000.0)
+        } else if let Some(number) = s.strip_suffix('m') {
+            (number, 60000.0)
+        } else {
+            (s, 1000.0)
+        };
+
+    match number.parse::<f64>() {
+        Ok(n) if n >= 0.0 && n.is_finite() => Some(Duration::from_millis((n * millis_per_unit) as u64)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+#[allow(non_snake_case)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::time::{Duration, UNIX_EPOCH};
+    use io_providers::stream;
+    use env;
+    use sanitize;
+    use {Application, Arguments, Command, CommandResult, Context};
+
+    #[test]
+    fn application__watch__runs_max_runs_times() {
+        let mut sp = stream::Virtual::new();
+        let options = Options { interval: Duration::from_millis(0), clear_screen: false, max_runs: Some(3), highlight_changes: false };
+
+        let exit_code = test_app().watch(&mut sp, &mut env::Virtual::new(), vec!["hello".to_string()], &options);
+
+        assert_eq!(0, exit_code);
+        assert_eq!("hello\nhello\nhello\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__watch__sleeps_between_runs() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_now(UNIX_EPOCH);
+        let options = Options { interval: Duration::from_secs(2), clear_screen: false, max_runs: Some(3), highlight_changes: false };
+
+        test_app().watch(&mut sp, &mut ep, vec!["hello".to_string()], &options);
+
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(4), env::Provider::now(&ep));
+    }
+
+    #[test]
+    fn application__watch_clear_screen__clears_before_each_run() {
+        let mut sp = stream::Virtual::new();
+        let options = Options { interval: Duration::from_millis(0), clear_screen: true, max_runs: Some(2), highlight_changes: false };
+
+        test_app().watch(&mut sp, &mut env::Virtual::new(), vec!["hello".to_string()], &options);
+
+        assert_eq!(
+            "\x1b[2J\x1b[Hhello\n\x1b[2J\x1b[Hhello\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__watch_no_fancy__doesnt_clear() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("APP_NO_FANCY", "1");
+        let options = Options { interval: Duration::from_millis(0), clear_screen: true, max_runs: Some(2), highlight_changes: true };
+
+        test_app().watch(&mut sp, &mut ep, vec!["hello".to_string()], &options);
+
+        assert_eq!("hello\nhello\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__watch_argument_error__stops() {
+        let mut sp = stream::Virtual::new();
+        let options = Options { interval: Duration::from_millis(0), clear_screen: false, max_runs: None, highlight_changes: false };
+
+        let exit_code = test_app().watch(&mut sp, &mut env::Virtual::new(), vec!["nope".to_string()], &options);
+
+        assert_eq!(1, exit_code);
+        assert_eq!("Error: Unrecognized command 'nope'\n", ::std::str::from_utf8(sp.read_error()).unwrap());
+    }
+
+    #[test]
+    fn application__watch_highlight_changes__highlights_changed_lines() {
+        let mut sp = stream::Virtual::new();
+        let options = Options { interval: Duration::from_millis(0), clear_screen: false, max_runs: Some(3), highlight_changes: true };
+
+        test_app().watch(&mut sp, &mut env::Virtual::new(), vec!["count".to_string()], &options);
+
+        assert_eq!("\
+            static\ncount: 1\n\
+            static\n\x1b[7mcount: 2\x1b[0m\n\
+            static\n\x1b[7mcount: 3\x1b[0m\n",
+            ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn application__watch_from_sanitized_handler__framework_sequences_kept() {
+        let mut sp = stream::Virtual::new();
+        let cmds = [
+            Command { name: "styled", handler: styled_handler, ..Command::DEFAULT },
+            Command { name: "watch", handler: watch_handler, ..Command::DEFAULT },
+        ];
+        let app = Application { name: "app", commands: &cmds, sanitize: sanitize::Mode::Escape, ..Application::DEFAULT };
+
+        app.run(&mut sp, strings(&["app", "watch"]));
+
+        assert_eq!("\x1b[2J\x1b[H\\x1b[1mbold\r\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn highlight_changes__added_lines__highlighted() {
+        let result = highlight_changes("a\n", "a\nb\n");
+
+        assert_eq!("a\n\x1b[7mb\x1b[0m\n", result);
+    }
+
+    #[test]
+    fn parse_args__options_and_command__success() {
+        let tokens = strings(&["--interval", "500ms", "-d", "--no-clear", "--", "cmd", "--arg"]);
+
+        let (options, cmd_args) = parse_args(&tokens, &env::Virtual::new()).unwrap();
+
+        assert_eq!(
+            Options { interval: Duration::from_millis(500), clear_screen: false, max_runs: None, highlight_changes: true },
+            options);
+        assert_eq!(strings(&["cmd", "--arg"]), cmd_args);
+    }
+
+    #[test]
+    fn parse_args__command_only__defaults() {
+        let ep = env::Virtual::new();
+
+        let (options, cmd_args) = parse_args(&strings(&["cmd"]), &ep).unwrap();
+
+        assert_eq!(Options::new(Duration::from_secs(2), &ep), options);
+        assert!(!options.clear_screen);
+        assert_eq!(strings(&["cmd"]), cmd_args);
+    }
+
+    #[test]
+    fn options__new_stdout_terminal__clears_screen() {
+        let mut ep = env::Virtual::new();
+        ep.set_terminal(env::Stream::Output, true);
+
+        assert!(Options::new(Duration::from_secs(1), &ep).clear_screen);
+    }
+
+    #[test]
+    fn application__watch__passes_environment() {
+        let mut sp = stream::Virtual::new();
+        let mut ep = env::Virtual::new();
+        ep.set_var("GREETING", "hi");
+        let options = Options { interval: Duration::from_millis(0), clear_screen: false, max_runs: Some(1), highlight_changes: false };
+
+        test_app().watch(&mut sp, &mut ep, vec!["greet".to_string()], &options);
+
+        assert_eq!("hi\n", ::std::str::from_utf8(sp.read_output()).unwrap());
+    }
+
+    #[test]
+    fn parse_args__no_command__returns_err() {
+        assert!(parse_args(&strings(&["-n", "1", "--"]), &env::Virtual::new()).is_err());
+    }
+
+    #[test]
+    fn parse_args__bad_interval__returns_err() {
+        assert_eq!(Err("invalid interval 'soon'".to_string()), parse_args(&strings(&["-n", "soon", "cmd"]), &env::Virtual::new()));
+    }
+
+    #[test]
+    fn parse_interval__units__success() {
+        assert_eq!(Some(Duration::from_millis(250)), parse_interval("250ms"));
+        assert_eq!(Some(Duration::from_millis(1500)), parse_interval("1.5s"));
+        assert_eq!(Some(Duration::from_secs(120)), parse_interval("2m"));
+        assert_eq!(Some(Duration::from_secs(3)), parse_interval("3"));
+        assert_eq!(None, parse_interval("-1s"));
+        assert_eq!(None, parse_interval("s"));
+    }
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn test_app() -> Application<'static, 'static> {
+        Application {
+            name: "app",
+            commands: &[
+                Command { name: "hello", short_desc: "says hello", params: &[], handler: hello_handler, ..Command::DEFAULT },
+                Command { name: "count", short_desc: "counts its runs", params: &[], handler: count_handler, ..Command::DEFAULT },
+                Command { name: "greet", short_desc: "greets", params: &[], handler: greet_handler, ..Command::DEFAULT },
+            ],
+            ..Application::DEFAULT
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn count_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        thread_local!(static RUNS: Cell<usize> = const { Cell::new(0) });
+
+        let runs = RUNS.with(|r| { r.set(r.get() + 1); r.get() });
+        writeln!(ctx.output(), "static\ncount: {}", runs).unwrap();
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn styled_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        write!(ctx.output(), "\x1b[1mbold\r\n").unwrap();
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn watch_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        let cmds = [Command { name: "styled", handler: styled_handler, ..Command::DEFAULT }];
+        let app = Application { name: "app", commands: &cmds, sanitize: sanitize::Mode::Escape, ..Application::DEFAULT };
+        let options = Options { interval: Duration::from_millis(0), clear_screen: true, max_runs: Some(1), highlight_changes: false };
+        let (sp, ep) = ctx.unfiltered_parts();
+
+        app.watch(sp, ep, strings(&["styled"]), &options);
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn greet_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        let greeting = ctx.env().var("GREETING").unwrap_or_default();
+        writeln!(ctx.output(), "{}", greeting).unwrap();
+        CommandResult::Success
+    }
+
+    #[allow(unused_variables)]
+    fn hello_handler(ctx: &mut Context, args: &Arguments) -> CommandResult {
+        writeln!(ctx.output(), "hello").unwrap();
+        CommandResult::Success
+    }
+}